@@ -0,0 +1,343 @@
+#ifndef REMOTE_UNLOCK_H
+#define REMOTE_UNLOCK_H
+
+/* Generated by cbindgen from ffi/src, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Protocol version spoken by this library
+ */
+#define RU_PROTOCOL_VERSION 1
+
+/**
+ * Upper bound of a serialized request
+ */
+#define RU_MAX_REQUEST_LEN 4096
+
+/**
+ * Length of a server fingerprint in bytes
+ */
+#define RU_FINGERPRINT_LEN 32
+
+/**
+ * Length of an id in its simple form, including the NUL terminator
+ */
+#define RU_ID_STRING_LEN 33
+
+/**
+ * Length of a server issued challenge, including the NUL terminator
+ */
+#define RU_CHALLENGE_STRING_LEN 45
+
+typedef enum {
+  RU_ACTION_UNLOCK,
+  RU_ACTION_LOCK,
+  RU_ACTION_STATUS,
+  RU_ACTION_WAKE,
+} RuAction;
+
+typedef enum {
+  RU_STATUS_OK = 0,
+  RU_STATUS_NULL_POINTER,
+  RU_STATUS_INVALID_ARGUMENT,
+  RU_STATUS_BUFFER_TOO_SMALL,
+  RU_STATUS_INVALID_KEY,
+  RU_STATUS_INVALID_PAIRING_URI,
+  RU_STATUS_INVALID_RESPONSE,
+  RU_STATUS_UNEXPECTED_STATUS,
+  RU_STATUS_INTERNAL,
+  RU_STATUS_STALE_REQUEST,
+  RU_STATUS_ACTION_NOT_ALLOWED,
+  RU_STATUS_OUTSIDE_HOURS,
+  RU_STATUS_SUBNET_NOT_ALLOWED,
+  RU_STATUS_TOO_SOON_AFTER_LOCK,
+  RU_STATUS_RATE_LIMITED,
+} RuStatus;
+
+/**
+ * Parsed `remote-unlock://` pairing URI
+ */
+typedef struct RuPairingUri RuPairingUri;
+
+/**
+ * P-256 key used to enroll and sign requests
+ */
+typedef struct RuSigningKey RuSigningKey;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Generates a new signing key.
+ *
+ * # Safety
+ * `out` must be valid for writes.
+ */
+RuStatus ru_signing_key_generate(RuSigningKey **out);
+
+/**
+ * Loads a signing key from PKCS#8 DER.
+ *
+ * # Safety
+ * `der` must point to `der_len` readable bytes and `out` must be valid for writes.
+ */
+RuStatus ru_signing_key_from_pkcs8_der(const uint8_t *der, size_t der_len, RuSigningKey **out);
+
+/**
+ * Exports the signing key as PKCS#8 DER for storage in the platform keystore.
+ *
+ * # Safety
+ * `key` must be a live handle, `out` must point to `out_cap` writable bytes and
+ * `out_len` must be valid for writes.
+ */
+RuStatus ru_signing_key_to_pkcs8_der(const RuSigningKey *key,
+                                     uint8_t *out,
+                                     size_t out_cap,
+                                     size_t *out_len);
+
+/**
+ * Writes the PEM encoded public key as a NUL terminated string.
+ *
+ * # Safety
+ * `key` must be a live handle, `out` must point to `out_cap` writable bytes and
+ * `out_len` must be valid for writes.
+ */
+RuStatus ru_signing_key_public_key_pem(const RuSigningKey *key,
+                                       char *out,
+                                       size_t out_cap,
+                                       size_t *out_len);
+
+/**
+ * Frees a signing key, passing NULL is a no-op.
+ *
+ * # Safety
+ * `key` must be NULL or a handle not freed before.
+ */
+void ru_signing_key_free(RuSigningKey *key);
+
+/**
+ * Parses a pairing URI, as scanned from the QR code.
+ *
+ * # Safety
+ * `uri` must be a NUL terminated string and `out` must be valid for writes.
+ */
+RuStatus ru_pairing_uri_parse(const char *uri, RuPairingUri **out);
+
+/**
+ * Writes the host as a NUL terminated string.
+ *
+ * # Safety
+ * `uri` must be a live handle, `out` must point to `out_cap` writable bytes and
+ * `out_len` must be valid for writes.
+ */
+RuStatus ru_pairing_uri_host(const RuPairingUri *uri, char *out, size_t out_cap, size_t *out_len);
+
+/**
+ * Replaces the host, e.g. with an address resolved over mDNS.
+ *
+ * # Safety
+ * `uri` must be a live handle and `host` a NUL terminated string.
+ */
+RuStatus ru_pairing_uri_set_host(RuPairingUri *uri, const char *host);
+
+/**
+ * Returns the port, 0 for a NULL handle.
+ *
+ * # Safety
+ * `uri` must be NULL or a live handle.
+ */
+uint16_t ru_pairing_uri_port(const RuPairingUri *uri);
+
+/**
+ * Returns the enrollment code, 0 for a NULL handle.
+ *
+ * # Safety
+ * `uri` must be NULL or a live handle.
+ */
+uint32_t ru_pairing_uri_code(const RuPairingUri *uri);
+
+/**
+ * Returns the code expiry as a unix timestamp, 0 for a NULL handle.
+ *
+ * # Safety
+ * `uri` must be NULL or a live handle.
+ */
+int64_t ru_pairing_uri_expires(const RuPairingUri *uri);
+
+/**
+ * Copies the SHA-256 fingerprint of the server identity key.
+ *
+ * # Safety
+ * `uri` must be a live handle and `out` must point to `RU_FINGERPRINT_LEN` writable bytes.
+ */
+RuStatus ru_pairing_uri_fingerprint(const RuPairingUri *uri, uint8_t *out);
+
+/**
+ * Formats the URI as a NUL terminated string.
+ *
+ * # Safety
+ * `uri` must be a live handle, `out` must point to `out_cap` writable bytes and
+ * `out_len` must be valid for writes.
+ */
+RuStatus ru_pairing_uri_to_string(const RuPairingUri *uri,
+                                  char *out,
+                                  size_t out_cap,
+                                  size_t *out_len);
+
+/**
+ * Frees a pairing URI, passing NULL is a no-op.
+ *
+ * # Safety
+ * `uri` must be NULL or a handle not freed before.
+ */
+void ru_pairing_uri_free(RuPairingUri *uri);
+
+/**
+ * Serializes the HTTP request enrolling `key` with an enrollment code.
+ *
+ * # Safety
+ * `key` must be a live handle, `out` must point to `out_cap` writable bytes and
+ * `out_len` must be valid for writes.
+ */
+RuStatus ru_enroll_request_build(const RuSigningKey *key,
+                                 uint32_t code,
+                                 uint8_t *out,
+                                 size_t out_cap,
+                                 size_t *out_len);
+
+/**
+ * Serializes a signed request for `action`. The caller must persist `nonce + 1`
+ * before sending so a lost response never leads to nonce reuse.
+ *
+ * # Safety
+ * `key` must be a live handle, `id` a NUL terminated string, `out` must point to
+ * `out_cap` writable bytes and `out_len` must be valid for writes.
+ */
+RuStatus ru_signed_request_build(const RuSigningKey *key,
+                                 const char *id,
+                                 uint64_t nonce,
+                                 RuAction action,
+                                 uint8_t *out,
+                                 size_t out_cap,
+                                 size_t *out_len);
+
+/**
+ * Like `ru_signed_request_build`, also signing `ts`, the current Unix time in
+ * seconds, for servers that refuse stale requests.
+ *
+ * # Safety
+ * Same as `ru_signed_request_build`.
+ */
+RuStatus ru_signed_request_build_at(const RuSigningKey *key,
+                                    const char *id,
+                                    uint64_t nonce,
+                                    int64_t ts,
+                                    RuAction action,
+                                    uint8_t *out,
+                                    size_t out_cap,
+                                    size_t *out_len);
+
+/**
+ * Serializes the request fetching a challenge for a device the server has set
+ * to challenge mode.
+ *
+ * # Safety
+ * `id` must be a NUL terminated string, `out` must point to `out_cap` writable
+ * bytes and `out_len` must be valid for writes.
+ */
+RuStatus ru_challenge_request_build(const char *id, uint8_t *out, size_t out_cap, size_t *out_len);
+
+/**
+ * Serializes a signed request for `action` answering `challenge`, which the
+ * server accepts once and only for a short time.
+ *
+ * # Safety
+ * `key` must be a live handle, `id` and `challenge` NUL terminated strings, `out`
+ * must point to `out_cap` writable bytes and `out_len` must be valid for writes.
+ */
+RuStatus ru_challenged_request_build(const RuSigningKey *key,
+                                     const char *id,
+                                     const char *challenge,
+                                     RuAction action,
+                                     uint8_t *out,
+                                     size_t out_cap,
+                                     size_t *out_len);
+
+/**
+ * Like `ru_challenged_request_build`, also signing `ts`, the current Unix time
+ * in seconds.
+ *
+ * # Safety
+ * Same as `ru_challenged_request_build`.
+ */
+RuStatus ru_challenged_request_build_at(const RuSigningKey *key,
+                                        const char *id,
+                                        const char *challenge,
+                                        int64_t ts,
+                                        RuAction action,
+                                        uint8_t *out,
+                                        size_t out_cap,
+                                        size_t *out_len);
+
+/**
+ * Reads the HTTP status code of a raw response.
+ *
+ * # Safety
+ * `resp` must point to `resp_len` readable bytes and `status` must be valid for writes.
+ */
+RuStatus ru_response_status(const uint8_t *resp, size_t resp_len, uint16_t *status);
+
+/**
+ * Checks a raw response to an unlock, lock or wake request. Refusals by the
+ * device's policy come back as their own status, e.g. `RU_STATUS_OUTSIDE_HOURS`.
+ *
+ * # Safety
+ * `resp` must point to `resp_len` readable bytes.
+ */
+RuStatus ru_response_check(const uint8_t *resp, size_t resp_len);
+
+/**
+ * Extracts the assigned id from a raw enrollment response as a NUL terminated
+ * string of `RU_ID_STRING_LEN` bytes.
+ *
+ * # Safety
+ * `resp` must point to `resp_len` readable bytes, `id_out` to `id_cap` writable
+ * bytes.
+ */
+RuStatus ru_enroll_response_parse(const uint8_t *resp,
+                                  size_t resp_len,
+                                  char *id_out,
+                                  size_t id_cap);
+
+/**
+ * Extracts the challenge from a raw challenge response as a NUL terminated
+ * string of `RU_CHALLENGE_STRING_LEN` bytes.
+ *
+ * # Safety
+ * `resp` must point to `resp_len` readable bytes, `challenge_out` to
+ * `challenge_cap` writable bytes.
+ */
+RuStatus ru_challenge_response_parse(const uint8_t *resp,
+                                     size_t resp_len,
+                                     char *challenge_out,
+                                     size_t challenge_cap);
+
+/**
+ * Reads the lock state from a raw status response.
+ *
+ * # Safety
+ * `resp` must point to `resp_len` readable bytes and `locked` must be valid for writes.
+ */
+RuStatus ru_status_response_parse(const uint8_t *resp, size_t resp_len, bool *locked);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* REMOTE_UNLOCK_H */
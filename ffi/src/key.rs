@@ -0,0 +1,98 @@
+use p256::ecdsa::SigningKey;
+use p256::pkcs8::{DecodePrivateKey, EncodePrivateKey};
+use rand::rngs::OsRng;
+use remote_unlock_lib::client::Signer;
+
+use crate::{guard, handle, input, write_bytes, write_str, RuStatus};
+use std::ffi::c_char;
+
+/// P-256 key used to enroll and sign requests
+pub struct RuSigningKey {
+    pub(crate) inner: SigningKey,
+}
+
+fn into_handle(inner: SigningKey, out: *mut *mut RuSigningKey) -> Result<(), RuStatus> {
+    if out.is_null() {
+        return Err(RuStatus::NullPointer);
+    }
+
+    unsafe { *out = Box::into_raw(Box::new(RuSigningKey { inner })) };
+    Ok(())
+}
+
+/// Generates a new signing key.
+///
+/// # Safety
+/// `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ru_signing_key_generate(out: *mut *mut RuSigningKey) -> RuStatus {
+    guard(|| into_handle(SigningKey::random(&mut OsRng), out))
+}
+
+/// Loads a signing key from PKCS#8 DER.
+///
+/// # Safety
+/// `der` must point to `der_len` readable bytes and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ru_signing_key_from_pkcs8_der(
+    der: *const u8,
+    der_len: usize,
+    out: *mut *mut RuSigningKey,
+) -> RuStatus {
+    guard(|| {
+        let der = input(der, der_len)?;
+        let inner = SigningKey::from_pkcs8_der(der).map_err(|_| RuStatus::InvalidKey)?;
+        into_handle(inner, out)
+    })
+}
+
+/// Exports the signing key as PKCS#8 DER for storage in the platform keystore.
+///
+/// # Safety
+/// `key` must be a live handle, `out` must point to `out_cap` writable bytes and
+/// `out_len` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ru_signing_key_to_pkcs8_der(
+    key: *const RuSigningKey,
+    out: *mut u8,
+    out_cap: usize,
+    out_len: *mut usize,
+) -> RuStatus {
+    guard(|| {
+        let key = handle(key)?;
+        let der = key.inner.to_pkcs8_der().map_err(|_| RuStatus::InvalidKey)?;
+        write_bytes(der.as_bytes(), out, out_cap, out_len)
+    })
+}
+
+/// Writes the PEM encoded public key as a NUL terminated string.
+///
+/// # Safety
+/// `key` must be a live handle, `out` must point to `out_cap` writable bytes and
+/// `out_len` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ru_signing_key_public_key_pem(
+    key: *const RuSigningKey,
+    out: *mut c_char,
+    out_cap: usize,
+    out_len: *mut usize,
+) -> RuStatus {
+    guard(|| {
+        let key = handle(key)?;
+        let pubkey = key.inner.public_key().map_err(|_| RuStatus::InvalidKey)?;
+        let pem = pubkey.pem().map_err(|_| RuStatus::InvalidKey)?;
+        let pem = pem.as_str().map_err(|_| RuStatus::Internal)?;
+        write_str(pem, out, out_cap, out_len)
+    })
+}
+
+/// Frees a signing key, passing NULL is a no-op.
+///
+/// # Safety
+/// `key` must be NULL or a handle not freed before.
+#[no_mangle]
+pub unsafe extern "C" fn ru_signing_key_free(key: *mut RuSigningKey) {
+    if !key.is_null() {
+        drop(Box::from_raw(key));
+    }
+}
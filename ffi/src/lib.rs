@@ -0,0 +1,136 @@
+//! C ABI over the remote_unlock client SDK for the mobile apps.
+//!
+//! Handles are opaque and owned by the caller until passed to their `_free`
+//! function. Output goes to caller provided buffers: on `RU_STATUS_BUFFER_TOO_SMALL`
+//! `out_len` holds the size required, including the NUL terminator for strings.
+
+use std::ffi::{c_char, CStr};
+use std::panic::{self, AssertUnwindSafe};
+
+pub mod key;
+pub mod pairing_uri;
+pub mod request;
+
+/// Protocol version spoken by this library
+pub const RU_PROTOCOL_VERSION: u16 = 1;
+
+/// Upper bound of a serialized request
+pub const RU_MAX_REQUEST_LEN: usize = 4096;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuStatus {
+    Ok = 0,
+    NullPointer,
+    InvalidArgument,
+    BufferTooSmall,
+    InvalidKey,
+    InvalidPairingUri,
+    InvalidResponse,
+    UnexpectedStatus,
+    Internal,
+    StaleRequest,
+    ActionNotAllowed,
+    OutsideHours,
+    SubnetNotAllowed,
+    TooSoonAfterLock,
+    RateLimited,
+}
+
+// Runs the body of an exported function, never letting a panic cross the ABI
+fn guard(f: impl FnOnce() -> Result<(), RuStatus>) -> RuStatus {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => RuStatus::Ok,
+        Ok(Err(status)) => status,
+        Err(_) => RuStatus::Internal,
+    }
+}
+
+unsafe fn input<'a>(ptr: *const u8, len: usize) -> Result<&'a [u8], RuStatus> {
+    if len == 0 {
+        return Ok(&[]);
+    }
+    if ptr.is_null() {
+        return Err(RuStatus::NullPointer);
+    }
+
+    Ok(std::slice::from_raw_parts(ptr, len))
+}
+
+unsafe fn input_str<'a>(ptr: *const c_char) -> Result<&'a str, RuStatus> {
+    if ptr.is_null() {
+        return Err(RuStatus::NullPointer);
+    }
+
+    CStr::from_ptr(ptr)
+        .to_str()
+        .map_err(|_| RuStatus::InvalidArgument)
+}
+
+unsafe fn handle<'a, T>(ptr: *const T) -> Result<&'a T, RuStatus> {
+    ptr.as_ref().ok_or(RuStatus::NullPointer)
+}
+
+unsafe fn write_bytes(
+    bytes: &[u8],
+    out: *mut u8,
+    out_cap: usize,
+    out_len: *mut usize,
+) -> Result<(), RuStatus> {
+    if out_len.is_null() {
+        return Err(RuStatus::NullPointer);
+    }
+
+    *out_len = bytes.len();
+    if out.is_null() || bytes.len() > out_cap {
+        return Err(RuStatus::BufferTooSmall);
+    }
+
+    std::ptr::copy_nonoverlapping(bytes.as_ptr(), out, bytes.len());
+    Ok(())
+}
+
+// Writes a NUL terminated string, out_len excludes the terminator on success
+unsafe fn write_str(
+    s: &str,
+    out: *mut c_char,
+    out_cap: usize,
+    out_len: *mut usize,
+) -> Result<(), RuStatus> {
+    if out_len.is_null() {
+        return Err(RuStatus::NullPointer);
+    }
+
+    *out_len = s.len() + 1;
+    if out.is_null() || s.len() >= out_cap {
+        return Err(RuStatus::BufferTooSmall);
+    }
+
+    std::ptr::copy_nonoverlapping(s.as_ptr(), out as *mut u8, s.len());
+    *out.add(s.len()) = 0;
+    *out_len = s.len();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use remote_unlock_lib::prelude::*;
+
+    #[test]
+    fn test_constants_match_lib() {
+        assert_eq!(RU_PROTOCOL_VERSION, Config::PROTOCOL_VERSION);
+        assert_eq!(RU_MAX_REQUEST_LEN, Config::MAX_PACKET_SIZE);
+    }
+
+    #[test]
+    fn test_write_str_too_small() {
+        let mut out = [0 as c_char; 4];
+        let mut out_len = 0;
+
+        let status = guard(|| unsafe { write_str("test", out.as_mut_ptr(), 4, &mut out_len) });
+        assert_eq!(status, RuStatus::BufferTooSmall);
+        assert_eq!(out_len, 5);
+    }
+}
@@ -0,0 +1,185 @@
+use std::ffi::c_char;
+
+use remote_unlock_lib::crypto::fingerprint::Fingerprint;
+use remote_unlock_lib::pairing_uri::PairingUri;
+
+use crate::{guard, handle, input_str, write_str, RuStatus};
+
+/// Length of a server fingerprint in bytes
+pub const RU_FINGERPRINT_LEN: usize = 32;
+
+/// Parsed `remote-unlock://` pairing URI
+pub struct RuPairingUri {
+    inner: PairingUri,
+}
+
+unsafe fn handle_mut<'a>(ptr: *mut RuPairingUri) -> Result<&'a mut RuPairingUri, RuStatus> {
+    ptr.as_mut().ok_or(RuStatus::NullPointer)
+}
+
+/// Parses a pairing URI, as scanned from the QR code.
+///
+/// # Safety
+/// `uri` must be a NUL terminated string and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ru_pairing_uri_parse(
+    uri: *const c_char,
+    out: *mut *mut RuPairingUri,
+) -> RuStatus {
+    guard(|| {
+        if out.is_null() {
+            return Err(RuStatus::NullPointer);
+        }
+
+        let inner = PairingUri::parse(input_str(uri)?).map_err(|_| RuStatus::InvalidPairingUri)?;
+        *out = Box::into_raw(Box::new(RuPairingUri { inner }));
+
+        Ok(())
+    })
+}
+
+/// Writes the host as a NUL terminated string.
+///
+/// # Safety
+/// `uri` must be a live handle, `out` must point to `out_cap` writable bytes and
+/// `out_len` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ru_pairing_uri_host(
+    uri: *const RuPairingUri,
+    out: *mut c_char,
+    out_cap: usize,
+    out_len: *mut usize,
+) -> RuStatus {
+    guard(|| write_str(handle(uri)?.inner.host(), out, out_cap, out_len))
+}
+
+/// Replaces the host, e.g. with an address resolved over mDNS.
+///
+/// # Safety
+/// `uri` must be a live handle and `host` a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn ru_pairing_uri_set_host(
+    uri: *mut RuPairingUri,
+    host: *const c_char,
+) -> RuStatus {
+    guard(|| {
+        let uri = handle_mut(uri)?;
+        uri.inner
+            .set_host(input_str(host)?)
+            .map_err(|_| RuStatus::InvalidArgument)
+    })
+}
+
+/// Returns the port, 0 for a NULL handle.
+///
+/// # Safety
+/// `uri` must be NULL or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn ru_pairing_uri_port(uri: *const RuPairingUri) -> u16 {
+    uri.as_ref().map(|uri| uri.inner.port()).unwrap_or(0)
+}
+
+/// Returns the enrollment code, 0 for a NULL handle.
+///
+/// # Safety
+/// `uri` must be NULL or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn ru_pairing_uri_code(uri: *const RuPairingUri) -> u32 {
+    uri.as_ref().map(|uri| uri.inner.code()).unwrap_or(0)
+}
+
+/// Returns the code expiry as a unix timestamp, 0 for a NULL handle.
+///
+/// # Safety
+/// `uri` must be NULL or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn ru_pairing_uri_expires(uri: *const RuPairingUri) -> i64 {
+    uri.as_ref().map(|uri| uri.inner.expires()).unwrap_or(0)
+}
+
+/// Copies the SHA-256 fingerprint of the server identity key.
+///
+/// # Safety
+/// `uri` must be a live handle and `out` must point to `RU_FINGERPRINT_LEN` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn ru_pairing_uri_fingerprint(
+    uri: *const RuPairingUri,
+    out: *mut u8,
+) -> RuStatus {
+    guard(|| {
+        let uri = handle(uri)?;
+        if out.is_null() {
+            return Err(RuStatus::NullPointer);
+        }
+
+        let fingerprint = uri.inner.fingerprint().as_bytes();
+        std::ptr::copy_nonoverlapping(fingerprint.as_ptr(), out, Fingerprint::LEN);
+
+        Ok(())
+    })
+}
+
+/// Formats the URI as a NUL terminated string.
+///
+/// # Safety
+/// `uri` must be a live handle, `out` must point to `out_cap` writable bytes and
+/// `out_len` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ru_pairing_uri_to_string(
+    uri: *const RuPairingUri,
+    out: *mut c_char,
+    out_cap: usize,
+    out_len: *mut usize,
+) -> RuStatus {
+    guard(|| write_str(&handle(uri)?.inner.to_string(), out, out_cap, out_len))
+}
+
+/// Frees a pairing URI, passing NULL is a no-op.
+///
+/// # Safety
+/// `uri` must be NULL or a handle not freed before.
+#[no_mangle]
+pub unsafe extern "C" fn ru_pairing_uri_free(uri: *mut RuPairingUri) {
+    if !uri.is_null() {
+        drop(Box::from_raw(uri));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::{CStr, CString};
+
+    #[test]
+    fn test_parse_and_format() {
+        let raw = "remote-unlock://desktop.local:8142/enroll?code=123456&expires=1700000000\
+                   &fp=3b2c1e9a6f0d4e8b7a5c2d1f0e9b8a7c6d5e4f3a2b1c0d9e8f7a6b5c4d3e2f1a";
+        let c_raw = CString::new(raw).unwrap();
+
+        unsafe {
+            let mut uri = std::ptr::null_mut();
+            assert_eq!(ru_pairing_uri_parse(c_raw.as_ptr(), &mut uri), RuStatus::Ok);
+            assert_eq!(ru_pairing_uri_port(uri), 8142);
+            assert_eq!(ru_pairing_uri_code(uri), 123456);
+
+            let mut out = [0 as c_char; 256];
+            let mut out_len = 0;
+            let status = ru_pairing_uri_to_string(uri, out.as_mut_ptr(), out.len(), &mut out_len);
+            assert_eq!(status, RuStatus::Ok);
+            assert_eq!(CStr::from_ptr(out.as_ptr()).to_str().unwrap(), raw);
+            assert_eq!(out_len, raw.len());
+
+            ru_pairing_uri_free(uri);
+        }
+    }
+
+    #[test]
+    fn test_rejects_invalid() {
+        let c_raw = CString::new("https://desktop.local").unwrap();
+        let mut uri = std::ptr::null_mut();
+
+        let status = unsafe { ru_pairing_uri_parse(c_raw.as_ptr(), &mut uri) };
+        assert_eq!(status, RuStatus::InvalidPairingUri);
+        assert!(uri.is_null());
+    }
+}
@@ -0,0 +1,499 @@
+use std::ffi::c_char;
+
+use remote_unlock_lib::client::{self, Action, ClientRequest};
+use remote_unlock_lib::denial::Denial;
+use remote_unlock_lib::net::response::Response;
+use remote_unlock_lib::prelude::*;
+use remote_unlock_lib::status_response::StatusResponse;
+
+use crate::key::RuSigningKey;
+use crate::{guard, handle, input, input_str, write_bytes, write_str, RuStatus};
+
+/// Length of an id in its simple form, including the NUL terminator
+pub const RU_ID_STRING_LEN: usize = 33;
+
+/// Length of a server issued challenge, including the NUL terminator
+pub const RU_CHALLENGE_STRING_LEN: usize = 45;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuAction {
+    Unlock,
+    Lock,
+    Status,
+    Wake,
+}
+
+impl From<RuAction> for Action {
+    fn from(action: RuAction) -> Action {
+        match action {
+            RuAction::Unlock => Action::Unlock,
+            RuAction::Lock => Action::Lock,
+            RuAction::Status => Action::Status,
+            RuAction::Wake => Action::Wake,
+        }
+    }
+}
+
+unsafe fn write_request(
+    req: &ClientRequest,
+    out: *mut u8,
+    out_cap: usize,
+    out_len: *mut usize,
+) -> Result<(), RuStatus> {
+    let mut serial = ByteArray::<{ Config::MAX_PACKET_SIZE }>::new();
+    req.to_writer(&mut serial).map_err(|_| RuStatus::Internal)?;
+    write_bytes(serial.as_bytes(), out, out_cap, out_len)
+}
+
+impl From<Denial> for RuStatus {
+    fn from(denial: Denial) -> RuStatus {
+        match denial {
+            Denial::ActionNotAllowed => RuStatus::ActionNotAllowed,
+            Denial::OutsideHours => RuStatus::OutsideHours,
+            Denial::SubnetNotAllowed => RuStatus::SubnetNotAllowed,
+            Denial::TooSoonAfterLock => RuStatus::TooSoonAfterLock,
+            Denial::RateLimited => RuStatus::RateLimited,
+        }
+    }
+}
+
+fn parse_response(resp: &[u8]) -> Result<Response, RuStatus> {
+    let resp = Response::from_stream(&mut &resp[..]).map_err(|_| RuStatus::InvalidResponse)?;
+    client::check_status(resp).map_err(|e| match e.kind() {
+        Some(ErrorKind::StaleRequest) => RuStatus::StaleRequest,
+        Some(ErrorKind::PolicyDenied(denial)) => (*denial).into(),
+        _ => RuStatus::UnexpectedStatus,
+    })
+}
+
+/// Serializes the HTTP request enrolling `key` with an enrollment code.
+///
+/// # Safety
+/// `key` must be a live handle, `out` must point to `out_cap` writable bytes and
+/// `out_len` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ru_enroll_request_build(
+    key: *const RuSigningKey,
+    code: u32,
+    out: *mut u8,
+    out_cap: usize,
+    out_len: *mut usize,
+) -> RuStatus {
+    guard(|| {
+        let key = handle(key)?;
+        let req = client::enroll_request(&key.inner, code).map_err(|_| RuStatus::InvalidKey)?;
+        write_request(&req, out, out_cap, out_len)
+    })
+}
+
+unsafe fn build_signed(
+    key: *const RuSigningKey,
+    id: *const c_char,
+    nonce: u64,
+    ts: Option<i64>,
+    action: RuAction,
+) -> Result<ClientRequest, RuStatus> {
+    let key = handle(key)?;
+    let id = uuid::Uuid::parse_str(input_str(id)?).map_err(|_| RuStatus::InvalidArgument)?;
+
+    client::signed_request(&key.inner, &id, nonce as u128, ts, action.into())
+        .map_err(|_| RuStatus::Internal)
+}
+
+unsafe fn build_challenged(
+    key: *const RuSigningKey,
+    id: *const c_char,
+    challenge: *const c_char,
+    ts: Option<i64>,
+    action: RuAction,
+) -> Result<ClientRequest, RuStatus> {
+    let key = handle(key)?;
+    let id = uuid::Uuid::parse_str(input_str(id)?).map_err(|_| RuStatus::InvalidArgument)?;
+
+    client::signed_challenge_request(&key.inner, &id, input_str(challenge)?, ts, action.into())
+        .map_err(|_| RuStatus::InvalidArgument)
+}
+
+/// Serializes a signed request for `action`. The caller must persist `nonce + 1`
+/// before sending so a lost response never leads to nonce reuse.
+///
+/// # Safety
+/// `key` must be a live handle, `id` a NUL terminated string, `out` must point to
+/// `out_cap` writable bytes and `out_len` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ru_signed_request_build(
+    key: *const RuSigningKey,
+    id: *const c_char,
+    nonce: u64,
+    action: RuAction,
+    out: *mut u8,
+    out_cap: usize,
+    out_len: *mut usize,
+) -> RuStatus {
+    guard(|| {
+        let req = build_signed(key, id, nonce, None, action)?;
+        write_request(&req, out, out_cap, out_len)
+    })
+}
+
+/// Like `ru_signed_request_build`, also signing `ts`, the current Unix time in
+/// seconds, for servers that refuse stale requests.
+///
+/// # Safety
+/// Same as `ru_signed_request_build`.
+#[no_mangle]
+pub unsafe extern "C" fn ru_signed_request_build_at(
+    key: *const RuSigningKey,
+    id: *const c_char,
+    nonce: u64,
+    ts: i64,
+    action: RuAction,
+    out: *mut u8,
+    out_cap: usize,
+    out_len: *mut usize,
+) -> RuStatus {
+    guard(|| {
+        let req = build_signed(key, id, nonce, Some(ts), action)?;
+        write_request(&req, out, out_cap, out_len)
+    })
+}
+
+/// Serializes the request fetching a challenge for a device the server has set
+/// to challenge mode.
+///
+/// # Safety
+/// `id` must be a NUL terminated string, `out` must point to `out_cap` writable
+/// bytes and `out_len` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ru_challenge_request_build(
+    id: *const c_char,
+    out: *mut u8,
+    out_cap: usize,
+    out_len: *mut usize,
+) -> RuStatus {
+    guard(|| {
+        let id = uuid::Uuid::parse_str(input_str(id)?).map_err(|_| RuStatus::InvalidArgument)?;
+        let req = client::challenge_request(&id).map_err(|_| RuStatus::Internal)?;
+        write_request(&req, out, out_cap, out_len)
+    })
+}
+
+/// Serializes a signed request for `action` answering `challenge`, which the
+/// server accepts once and only for a short time.
+///
+/// # Safety
+/// `key` must be a live handle, `id` and `challenge` NUL terminated strings, `out`
+/// must point to `out_cap` writable bytes and `out_len` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ru_challenged_request_build(
+    key: *const RuSigningKey,
+    id: *const c_char,
+    challenge: *const c_char,
+    action: RuAction,
+    out: *mut u8,
+    out_cap: usize,
+    out_len: *mut usize,
+) -> RuStatus {
+    guard(|| {
+        let req = build_challenged(key, id, challenge, None, action)?;
+        write_request(&req, out, out_cap, out_len)
+    })
+}
+
+/// Like `ru_challenged_request_build`, also signing `ts`, the current Unix time
+/// in seconds.
+///
+/// # Safety
+/// Same as `ru_challenged_request_build`.
+#[no_mangle]
+pub unsafe extern "C" fn ru_challenged_request_build_at(
+    key: *const RuSigningKey,
+    id: *const c_char,
+    challenge: *const c_char,
+    ts: i64,
+    action: RuAction,
+    out: *mut u8,
+    out_cap: usize,
+    out_len: *mut usize,
+) -> RuStatus {
+    guard(|| {
+        let req = build_challenged(key, id, challenge, Some(ts), action)?;
+        write_request(&req, out, out_cap, out_len)
+    })
+}
+
+/// Reads the HTTP status code of a raw response.
+///
+/// # Safety
+/// `resp` must point to `resp_len` readable bytes and `status` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ru_response_status(
+    resp: *const u8,
+    resp_len: usize,
+    status: *mut u16,
+) -> RuStatus {
+    guard(|| {
+        if status.is_null() {
+            return Err(RuStatus::NullPointer);
+        }
+
+        let resp: Response = Response::from_stream(&mut input(resp, resp_len)?)
+            .map_err(|_| RuStatus::InvalidResponse)?;
+        *status = resp.status().to_u16();
+
+        Ok(())
+    })
+}
+
+/// Checks a raw response to an unlock, lock or wake request. Refusals by the
+/// device's policy come back as their own status, e.g. `RU_STATUS_OUTSIDE_HOURS`.
+///
+/// # Safety
+/// `resp` must point to `resp_len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn ru_response_check(resp: *const u8, resp_len: usize) -> RuStatus {
+    guard(|| {
+        parse_response(input(resp, resp_len)?)?;
+        Ok(())
+    })
+}
+
+/// Extracts the assigned id from a raw enrollment response as a NUL terminated
+/// string of `RU_ID_STRING_LEN` bytes.
+///
+/// # Safety
+/// `resp` must point to `resp_len` readable bytes, `id_out` to `id_cap` writable
+/// bytes.
+#[no_mangle]
+pub unsafe extern "C" fn ru_enroll_response_parse(
+    resp: *const u8,
+    resp_len: usize,
+    id_out: *mut c_char,
+    id_cap: usize,
+) -> RuStatus {
+    guard(|| {
+        let resp = parse_response(input(resp, resp_len)?)?;
+        let id = client::parse_enrollment_response(&resp).map_err(|_| RuStatus::InvalidResponse)?;
+
+        let mut id_buf: [u8; 32] = [0; 32];
+        let id = id.as_simple().encode_lower(&mut id_buf);
+
+        let mut id_len = 0;
+        write_str(id, id_out, id_cap, &mut id_len)
+    })
+}
+
+/// Extracts the challenge from a raw challenge response as a NUL terminated
+/// string of `RU_CHALLENGE_STRING_LEN` bytes.
+///
+/// # Safety
+/// `resp` must point to `resp_len` readable bytes, `challenge_out` to
+/// `challenge_cap` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn ru_challenge_response_parse(
+    resp: *const u8,
+    resp_len: usize,
+    challenge_out: *mut c_char,
+    challenge_cap: usize,
+) -> RuStatus {
+    guard(|| {
+        let resp = parse_response(input(resp, resp_len)?)?;
+        let challenge =
+            client::parse_challenge_response(&resp).map_err(|_| RuStatus::InvalidResponse)?;
+        let challenge = challenge.as_str().map_err(|_| RuStatus::InvalidResponse)?;
+
+        let mut challenge_len = 0;
+        write_str(challenge, challenge_out, challenge_cap, &mut challenge_len)
+    })
+}
+
+/// Reads the lock state from a raw status response.
+///
+/// # Safety
+/// `resp` must point to `resp_len` readable bytes and `locked` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ru_status_response_parse(
+    resp: *const u8,
+    resp_len: usize,
+    locked: *mut bool,
+) -> RuStatus {
+    guard(|| {
+        if locked.is_null() {
+            return Err(RuStatus::NullPointer);
+        }
+
+        let resp = parse_response(input(resp, resp_len)?)?;
+        let status = serde_json::from_slice::<StatusResponse>(&resp.body[..resp.body_len])
+            .map_err(|_| RuStatus::InvalidResponse)?;
+        *locked = status.locked();
+
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::{ru_signing_key_free, ru_signing_key_generate};
+    use base64::prelude::*;
+    use remote_unlock_lib::challenge_response::{ChallengeResponse, CHALLENGE_LEN};
+    use remote_unlock_lib::client::{Signer, SIGNATURE_HEADER};
+    use remote_unlock_lib::enroll_response::EnrollmentResponse;
+    use remote_unlock_lib::net::request::Request;
+    use remote_unlock_lib::net::status::Status;
+    use remote_unlock_lib::unlock_request::UnlockRequestBody;
+    use std::ffi::{CStr, CString};
+
+    #[test]
+    fn test_signed_request_verifies() {
+        unsafe {
+            let mut key = std::ptr::null_mut();
+            assert_eq!(ru_signing_key_generate(&mut key), RuStatus::Ok);
+
+            let id = CString::new(uuid::Uuid::new_v4().to_string()).unwrap();
+            let mut out = [0u8; crate::RU_MAX_REQUEST_LEN];
+            let mut out_len = 0;
+            let status = ru_signed_request_build(
+                key,
+                id.as_ptr(),
+                3,
+                RuAction::Unlock,
+                out.as_mut_ptr(),
+                out.len(),
+                &mut out_len,
+            );
+            assert_eq!(status, RuStatus::Ok);
+
+            let req = Request::<{ 64 * 2 }>::from_stream(&mut &out[..out_len]).unwrap();
+            assert_eq!(req.path(), Some("/unlock"));
+
+            let body = std::str::from_utf8(&req.body[..req.body_len]).unwrap();
+            let signed = serde_json::from_str::<UnlockRequestBody>(body).unwrap();
+            let signature = BASE64_STANDARD
+                .decode(req.get_header(SIGNATURE_HEADER).unwrap().value.as_bytes())
+                .unwrap();
+            let pubkey = (*key).inner.public_key().unwrap();
+            assert_eq!(signed.nonce(), Some(3));
+            assert!(signed.verify(&signature, &pubkey).unwrap());
+
+            ru_signing_key_free(key);
+        }
+    }
+
+    #[test]
+    fn test_signed_request_at() {
+        unsafe {
+            let mut key = std::ptr::null_mut();
+            assert_eq!(ru_signing_key_generate(&mut key), RuStatus::Ok);
+
+            let id = CString::new(uuid::Uuid::new_v4().to_string()).unwrap();
+            let mut out = [0u8; crate::RU_MAX_REQUEST_LEN];
+            let mut out_len = 0;
+            let status = ru_signed_request_build_at(
+                key,
+                id.as_ptr(),
+                3,
+                1_700_000_000,
+                RuAction::Lock,
+                out.as_mut_ptr(),
+                out.len(),
+                &mut out_len,
+            );
+            assert_eq!(status, RuStatus::Ok);
+
+            let req = Request::<{ 64 * 2 }>::from_stream(&mut &out[..out_len]).unwrap();
+            let body = std::str::from_utf8(&req.body[..req.body_len]).unwrap();
+            let signed = serde_json::from_str::<UnlockRequestBody>(body).unwrap();
+            assert_eq!(signed.timestamp(), Some(1_700_000_000));
+
+            ru_signing_key_free(key);
+        }
+    }
+
+    #[test]
+    fn test_stale_response() {
+        let mut raw = ByteArray::<{ Config::MAX_PACKET_SIZE }>::new();
+        Response::<{ 64 * 2 }>::new(Status::RequestTimeout)
+            .to_writer(&mut raw)
+            .unwrap();
+
+        let mut locked = false;
+        let status = unsafe {
+            ru_status_response_parse(raw.as_bytes().as_ptr(), raw.as_bytes().len(), &mut locked)
+        };
+        assert_eq!(status, RuStatus::StaleRequest);
+    }
+
+    #[test]
+    fn test_policy_denial_response() {
+        let mut raw = ByteArray::<{ Config::MAX_PACKET_SIZE }>::new();
+        Response::<{ 64 * 2 }>::builder()
+            .status(Status::Forbidden)
+            .add_header(client::DENIAL_HEADER, Denial::RateLimited.code())
+            .unwrap()
+            .build()
+            .to_writer(&mut raw)
+            .unwrap();
+
+        let status = unsafe { ru_response_check(raw.as_bytes().as_ptr(), raw.as_bytes().len()) };
+        assert_eq!(status, RuStatus::RateLimited);
+
+        let mut raw = ByteArray::<{ Config::MAX_PACKET_SIZE }>::new();
+        Response::<{ 64 * 2 }>::new(Status::Ok)
+            .to_writer(&mut raw)
+            .unwrap();
+        let status = unsafe { ru_response_check(raw.as_bytes().as_ptr(), raw.as_bytes().len()) };
+        assert_eq!(status, RuStatus::Ok);
+    }
+
+    #[test]
+    fn test_enroll_response_parse() {
+        let enroll_resp = EnrollmentResponse::new();
+        let mut resp = Response::<{ 64 * 2 }>::new(Status::Ok);
+        serde_json::to_writer(&mut resp, &enroll_resp).unwrap();
+        let mut raw = ByteArray::<{ Config::MAX_PACKET_SIZE }>::new();
+        resp.to_writer(&mut raw).unwrap();
+
+        let mut id = [0 as c_char; RU_ID_STRING_LEN];
+        let status = unsafe {
+            ru_enroll_response_parse(
+                raw.as_bytes().as_ptr(),
+                raw.as_bytes().len(),
+                id.as_mut_ptr(),
+                id.len(),
+            )
+        };
+        assert_eq!(status, RuStatus::Ok);
+
+        let id = unsafe { CStr::from_ptr(id.as_ptr()) }.to_str().unwrap();
+        assert_eq!(id, enroll_resp.id().as_simple().to_string());
+    }
+
+    #[test]
+    fn test_challenge_response_parse() {
+        assert_eq!(RU_CHALLENGE_STRING_LEN, CHALLENGE_LEN + 1);
+
+        let issued = BASE64_STANDARD.encode([7u8; 32]);
+        let mut resp = Response::<{ 64 * 2 }>::new(Status::Ok);
+        serde_json::to_writer(&mut resp, &ChallengeResponse::new(&issued, 0)).unwrap();
+        let mut raw = ByteArray::<{ Config::MAX_PACKET_SIZE }>::new();
+        resp.to_writer(&mut raw).unwrap();
+
+        let mut challenge = [0 as c_char; RU_CHALLENGE_STRING_LEN];
+        let status = unsafe {
+            ru_challenge_response_parse(
+                raw.as_bytes().as_ptr(),
+                raw.as_bytes().len(),
+                challenge.as_mut_ptr(),
+                challenge.len(),
+            )
+        };
+        assert_eq!(status, RuStatus::Ok);
+
+        let challenge = unsafe { CStr::from_ptr(challenge.as_ptr()) }
+            .to_str()
+            .unwrap();
+        assert_eq!(challenge, issued);
+    }
+}
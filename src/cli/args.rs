@@ -3,6 +3,22 @@ use clap::{Args, Parser, Subcommand, ValueEnum};
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Cli {
+    #[arg(
+        short,
+        long,
+        global = true,
+        help = "Config file applied over the system and user config files"
+    )]
+    pub config: Option<String>,
+
+    #[arg(
+        long = "set",
+        global = true,
+        value_name = "KEY=VALUE",
+        help = "Override a config setting, takes precedence over everything else"
+    )]
+    pub set: Vec<String>,
+
     #[command(subcommand)]
     pub command: Command,
 }
@@ -12,6 +28,34 @@ pub struct Cli {
 pub enum Command {
     BeginEnroll(BeginEnrollCommand),
 
+    Discover(DiscoverCommand),
+
+    Enroll(EnrollCommand),
+
+    Unlock(UnlockCommand),
+
+    Lock(LockCommand),
+
+    Wake(WakeCommand),
+
+    Status(StatusCommand),
+
+    #[command(subcommand)]
+    Config(ConfigCommand),
+
+    #[command(subcommand)]
+    Codes(CodesCommand),
+
+    #[command(subcommand)]
+    Devices(DevicesCommand),
+
+    #[command(subcommand)]
+    Storage(StorageCommand),
+
+    ServerStatus(ServerStatusCommand),
+
+    Reload(ReloadCommand),
+
     Terminate(TerminateCommand),
 
     #[cfg(debug_assertions)]
@@ -19,7 +63,288 @@ pub enum Command {
 }
 
 #[derive(Args, Debug)]
-pub struct BeginEnrollCommand {}
+pub struct BeginEnrollCommand {
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Do not print the pairing QR code to the terminal"
+    )]
+    pub no_qr: bool,
+
+    #[arg(short, long, help = "Write the pairing QR code to a file")]
+    pub output: Option<String>,
+
+    #[arg(long, default_value = "png", help = "Format of the QR code file")]
+    pub format: QrFormat,
+
+    #[arg(long, help = "Override the host advertised in the pairing URI")]
+    pub host: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct DiscoverCommand {
+    #[arg(
+        short,
+        long,
+        default_value_t = 3,
+        help = "Seconds to browse the network for"
+    )]
+    pub timeout: u64,
+
+    #[arg(long, default_value_t = false, help = "Print results as JSON")]
+    pub json: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct EnrollCommand {
+    #[arg(
+        long,
+        required_unless_present = "uri",
+        help = "Host running the daemon"
+    )]
+    pub host: Option<String>,
+
+    #[arg(long, help = "Port the daemon listens on")]
+    pub port: Option<u16>,
+
+    #[arg(
+        long,
+        required_unless_present = "uri",
+        help = "Enrollment code from begin-enroll"
+    )]
+    pub code: Option<u32>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["host", "port", "code"],
+        help = "Pairing URI from begin-enroll"
+    )]
+    pub uri: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        help = "PEM private key to enroll instead of generating one"
+    )]
+    pub key: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        default_value = "default",
+        help = "Name of the client profile"
+    )]
+    pub profile: String,
+
+    #[arg(
+        short,
+        long,
+        default_value_t = false,
+        help = "Overwrite the profile if it exists"
+    )]
+    pub force: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct UnlockCommand {
+    #[arg(
+        short,
+        long,
+        default_value = "default",
+        help = "Name of the client profile"
+    )]
+    pub profile: String,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Sign a challenge from the server instead of the next nonce"
+    )]
+    pub challenge: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Sign the current time so the server refuses stale copies"
+    )]
+    pub timestamp: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct LockCommand {
+    #[arg(
+        short,
+        long,
+        default_value = "default",
+        help = "Name of the client profile"
+    )]
+    pub profile: String,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Sign a challenge from the server instead of the next nonce"
+    )]
+    pub challenge: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Sign the current time so the server refuses stale copies"
+    )]
+    pub timestamp: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct WakeCommand {
+    #[arg(
+        short,
+        long,
+        default_value = "default",
+        help = "Name of the client profile"
+    )]
+    pub profile: String,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Sign a challenge from the server instead of the next nonce"
+    )]
+    pub challenge: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Sign the current time so the server refuses stale copies"
+    )]
+    pub timestamp: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct StatusCommand {
+    #[arg(
+        short,
+        long,
+        default_value = "default",
+        help = "Name of the client profile"
+    )]
+    pub profile: String,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Sign a challenge from the server instead of the next nonce"
+    )]
+    pub challenge: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Sign the current time so the server refuses stale copies"
+    )]
+    pub timestamp: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    Show(ConfigShowCommand),
+}
+
+#[derive(Args, Debug)]
+pub struct ConfigShowCommand {
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Show the daemon's settings instead of the client's"
+    )]
+    pub server: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CodesCommand {
+    List(CodesListCommand),
+    Cancel(CodesCancelCommand),
+}
+
+#[derive(Args, Debug)]
+pub struct CodesListCommand {}
+
+#[derive(Args, Debug)]
+pub struct CodesCancelCommand {
+    #[arg(help = "Pending enrollment code to withdraw")]
+    pub code: u32,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DevicesCommand {
+    List(DevicesListCommand),
+    Export(DevicesExportCommand),
+    Import(DevicesImportCommand),
+}
+
+#[derive(Args, Debug)]
+pub struct DevicesListCommand {}
+
+#[derive(Args, Debug)]
+pub struct DevicesExportCommand {
+    #[arg(help = "File to write the bundle to")]
+    pub output: String,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Encrypt the bundle with a passphrase"
+    )]
+    pub encrypt: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct DevicesImportCommand {
+    #[arg(help = "Bundle written by devices export")]
+    pub input: String,
+
+    #[arg(
+        long,
+        default_value = "fail",
+        help = "What to do with devices already enrolled with another key"
+    )]
+    pub on_conflict: OnConflict,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum StorageCommand {
+    Migrate(StorageMigrateCommand),
+    Verify(StorageVerifyCommand),
+    Repair(StorageRepairCommand),
+}
+
+#[derive(Args, Debug)]
+pub struct StorageMigrateCommand {
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Show the pending migrations without changing anything"
+    )]
+    pub dry_run: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct StorageVerifyCommand {}
+
+#[derive(Args, Debug)]
+pub struct StorageRepairCommand {
+    #[arg(
+        long,
+        help = "Reseal this rejected device instead of removing it, may be repeated"
+    )]
+    pub trust: Vec<uuid::Uuid>,
+}
+
+#[derive(Args, Debug)]
+pub struct ServerStatusCommand {}
+
+#[derive(Args, Debug)]
+pub struct ReloadCommand {}
 
 #[derive(Args, Debug)]
 pub struct TerminateCommand {}
@@ -41,6 +366,19 @@ pub struct GenerateKeysCommand {
     pub format: KeyFormat,
 }
 
+#[derive(ValueEnum, Clone, Debug)]
+pub enum QrFormat {
+    Png,
+    Svg,
+}
+
+#[derive(ValueEnum, Clone, Debug)]
+pub enum OnConflict {
+    Fail,
+    Skip,
+    Replace,
+}
+
 #[derive(ValueEnum, Clone, Debug)]
 pub enum KeyFormat {
     Pem,
@@ -1,40 +1,41 @@
-use remote_unlock_lib::enrollment_code::EnrollmentCode;
-use remote_unlock_lib::net::method::Method;
-use remote_unlock_lib::net::request::Request;
-use remote_unlock_lib::net::response::Response;
-use remote_unlock_lib::net::status::Status;
+use crate::args::{BeginEnrollCommand, QrFormat};
+use crate::control::ControlClient;
+use crate::qr;
+use chrono::{TimeZone, Utc};
+use remote_unlock_lib::messages::{MessageData, ReplyData};
 use remote_unlock_lib::prelude::*;
-use std::net::Shutdown;
-use std::os::unix::net::UnixStream;
+use std::path::Path;
 
-pub fn begin_enroll(config: &Config) -> Result<(), Error> {
-    let mut stream = UnixStream::connect(config.socket_path())?;
-    let req = Request::<{ 64 * 2 }>::builder()
-        .method(Method::POST)
-        .path("/begin_enroll")
-        .build();
+pub fn begin_enroll(config: &ClientConfig, args: BeginEnrollCommand) -> Result<(), Error> {
+    let mut control = ControlClient::connect(config)?;
+    let mut pairing_uri = match control.call(MessageData::BeginEnroll(()))? {
+        ReplyData::BeginEnroll(uri) => *uri,
+        _ => return Err(ErrorKind::UnexpectedMessage.into()),
+    };
 
-    req.to_writer(&mut stream)?;
-    stream.shutdown(Shutdown::Write)?;
-    let response = Response::<{ 64 * 2 }>::from_stream(&mut stream)?;
+    if let Some(ref host) = args.host {
+        pairing_uri.set_host(host)?;
+    }
 
-    if response.status != Status::Ok {
-        let err = Error::new(ErrorKind::Server, Some(response.status.to_string()));
-        return Err(err);
+    if !args.no_qr {
+        println!("{}", qr::render_terminal(&pairing_uri)?);
     }
 
-    let code = match serde_json::from_slice::<EnrollmentCode>(&response.body[..response.body_len]) {
-        Ok(c) => c,
-        Err(e) => {
-            error!("Error parsing response: {}", e);
-            debug!("Response: {:?}", response);
-            debug!("Headers: {:?}", response.headers);
-            debug!("Body: {:?}", std::str::from_utf8(&response.body)?);
-            return Err(e.into());
-        }
-    };
+    let expires = Utc
+        .timestamp_opt(pairing_uri.expires(), 0)
+        .single()
+        .ok_or(Error::new(ErrorKind::Server, Some("Invalid expiry")))?;
+    println!("Code: {}\nExpires: {}", pairing_uri.code(), expires);
+    println!("URI: {}", pairing_uri);
 
-    println!("{}", code);
+    if let Some(ref output) = args.output {
+        let path = Path::new(output);
+        match args.format {
+            QrFormat::Png => qr::write_png(&pairing_uri, path)?,
+            QrFormat::Svg => qr::write_svg(&pairing_uri, path)?,
+        }
+        println!("QR code written to {}", output);
+    }
 
     Ok(())
 }
@@ -0,0 +1,57 @@
+use crate::args::{CodesCancelCommand, CodesCommand};
+use crate::control::ControlClient;
+use crate::table::print_table;
+use chrono::{TimeZone, Utc};
+use remote_unlock_lib::messages::{MessageData, PendingCode, ReplyData};
+use remote_unlock_lib::prelude::*;
+
+const COLUMNS: [&str; 2] = ["CODE", "EXPIRES"];
+
+fn row(code: &PendingCode) -> [String; 2] {
+    let expires = Utc
+        .timestamp_opt(code.expires, 0)
+        .single()
+        .map(|expires| expires.to_string())
+        .unwrap_or("?".to_string());
+
+    [code.code.to_string(), expires]
+}
+
+fn list(control: &mut ControlClient) -> Result<(), Error> {
+    let codes = match control.call(MessageData::ListCodes(()))? {
+        ReplyData::ListCodes(codes) => codes,
+        _ => return Err(ErrorKind::UnexpectedMessage.into()),
+    };
+
+    if codes.is_empty() {
+        eprintln!("No pending enrollment codes");
+    } else {
+        let rows: Vec<[String; 2]> = codes.iter().map(row).collect();
+        print_table(COLUMNS, &rows);
+    }
+
+    Ok(())
+}
+
+fn cancel(control: &mut ControlClient, args: CodesCancelCommand) -> Result<(), Error> {
+    match control.call(MessageData::CancelCode(args.code))? {
+        ReplyData::CancelCode(true) => println!("Cancelled code {}", args.code),
+        ReplyData::CancelCode(false) => {
+            return Err(Error::new(
+                ErrorKind::InvalidArgument,
+                Some("No such pending code"),
+            ))
+        }
+        _ => return Err(ErrorKind::UnexpectedMessage.into()),
+    }
+
+    Ok(())
+}
+
+pub fn codes(config: &ClientConfig, command: CodesCommand) -> Result<(), Error> {
+    let mut control = ControlClient::connect(config)?;
+    match command {
+        CodesCommand::List(_) => list(&mut control),
+        CodesCommand::Cancel(args) => cancel(&mut control, args),
+    }
+}
@@ -0,0 +1,48 @@
+use crate::args::{ConfigCommand, ConfigShowCommand};
+use crate::table::print_table;
+use remote_unlock_lib::config::{config_files, Entry, Overrides};
+use remote_unlock_lib::prelude::*;
+
+const COLUMNS: [&str; 3] = ["KEY", "VALUE", "SOURCE"];
+
+fn row(entry: &Entry) -> [String; 3] {
+    [
+        entry.key.to_string(),
+        entry.value.clone(),
+        entry.source.to_string(),
+    ]
+}
+
+fn show(
+    config: &ClientConfig,
+    overrides: &Overrides,
+    args: ConfigShowCommand,
+) -> Result<(), Error> {
+    let entries = if args.server {
+        ServerConfig::load(overrides)?.entries()
+    } else {
+        config.entries()
+    };
+
+    let rows: Vec<[String; 3]> = entries.iter().map(row).collect();
+    print_table(COLUMNS, &rows);
+
+    println!();
+    println!("Config files, later files take precedence:");
+    for path in config_files() {
+        let status = if path.exists() { "" } else { " (not found)" };
+        println!("  {}{}", path.display(), status);
+    }
+
+    Ok(())
+}
+
+pub fn config(
+    config: &ClientConfig,
+    overrides: &Overrides,
+    command: ConfigCommand,
+) -> Result<(), Error> {
+    match command {
+        ConfigCommand::Show(args) => show(config, overrides, args),
+    }
+}
@@ -0,0 +1,110 @@
+use crate::args::{DevicesCommand, DevicesExportCommand, DevicesImportCommand, OnConflict};
+use crate::control::ControlClient;
+use crate::profile::write_private;
+use crate::table::print_table;
+use chrono::{TimeZone, Utc};
+use remote_unlock_lib::clock::{Clock, SystemClock};
+use remote_unlock_lib::config::Overrides;
+use remote_unlock_lib::messages::{Device, MessageData, ReplyData};
+use remote_unlock_lib::prelude::*;
+use remote_unlock_lib::store::{self, bundle::Bundle, bundle::Conflict, bundle::Imported};
+use zeroize::Zeroizing;
+
+const COLUMNS: [&str; 2] = ["ID", "ENROLLED"];
+
+fn row(device: &Device) -> [String; 2] {
+    let enrolled = Utc
+        .timestamp_opt(device.enrolled, 0)
+        .single()
+        .map(|enrolled| enrolled.to_string())
+        .unwrap_or("?".to_string());
+
+    [device.id.clone(), enrolled]
+}
+
+fn list(control: &mut ControlClient) -> Result<(), Error> {
+    let devices = match control.call(MessageData::Devices(()))? {
+        ReplyData::Devices(devices) => devices,
+        _ => return Err(ErrorKind::UnexpectedMessage.into()),
+    };
+
+    if devices.is_empty() {
+        eprintln!("No enrolled devices");
+    } else {
+        let rows: Vec<[String; 2]> = devices.iter().map(row).collect();
+        print_table(COLUMNS, &rows);
+    }
+
+    Ok(())
+}
+
+fn passphrase(confirm: bool) -> Result<Zeroizing<String>, Error> {
+    let passphrase = Zeroizing::new(rpassword::prompt_password("Passphrase: ")?);
+    if confirm && *passphrase != rpassword::prompt_password("Repeat passphrase: ")? {
+        return Err(Error::new(
+            ErrorKind::InvalidArgument,
+            Some("Passphrases do not match"),
+        ));
+    }
+    Ok(passphrase)
+}
+
+// Export and import work on the storage directly, the daemon has to be
+// stopped first as it holds the storage lock
+fn export(config: &ServerConfig, args: DevicesExportCommand) -> Result<(), Error> {
+    let bundle = Bundle::export(store::open(config)?.as_ref(), SystemClock.now())?;
+    let passphrase = match args.encrypt {
+        true => Some(passphrase(true)?),
+        false => None,
+    };
+
+    write_private(
+        std::path::Path::new(&args.output),
+        &bundle.to_bytes(passphrase.as_deref().map(String::as_str))?,
+    )?;
+    eprintln!(
+        "Exported {} devices to {}",
+        bundle.devices.len(),
+        args.output
+    );
+    Ok(())
+}
+
+fn import(config: &ServerConfig, args: DevicesImportCommand) -> Result<(), Error> {
+    let bytes = std::fs::read(&args.input)?;
+    let passphrase = match Bundle::is_encrypted(&bytes)? {
+        true => Some(passphrase(false)?),
+        false => None,
+    };
+    let bundle = Bundle::from_bytes(&bytes, passphrase.as_deref().map(String::as_str))?;
+
+    let conflict = match args.on_conflict {
+        OnConflict::Fail => Conflict::Fail,
+        OnConflict::Skip => Conflict::Skip,
+        OnConflict::Replace => Conflict::Replace,
+    };
+    for (id, outcome) in
+        bundle.import(store::open(config)?.as_ref(), conflict, SystemClock.now())?
+    {
+        let outcome = match outcome {
+            Imported::Added => "added",
+            Imported::Merged => "already enrolled",
+            Imported::Replaced => "replaced",
+            Imported::Skipped => "skipped, enrolled with another key",
+        };
+        println!("{} {}", id, outcome);
+    }
+    Ok(())
+}
+
+pub fn devices(
+    config: &ClientConfig,
+    overrides: &Overrides,
+    command: DevicesCommand,
+) -> Result<(), Error> {
+    match command {
+        DevicesCommand::List(_) => list(&mut ControlClient::connect(config)?),
+        DevicesCommand::Export(args) => export(&ServerConfig::load(overrides)?, args),
+        DevicesCommand::Import(args) => import(&ServerConfig::load(overrides)?, args),
+    }
+}
@@ -0,0 +1,86 @@
+use crate::args::DiscoverCommand;
+use crate::table::print_table;
+use remote_unlock_lib::discovery::{self, DiscoveredService};
+use remote_unlock_lib::prelude::*;
+use std::time::Duration;
+
+const COLUMNS: [&str; 7] = [
+    "NAME",
+    "HOST",
+    "ADDRESSES",
+    "PORT",
+    "VERSION",
+    "ENROLL",
+    "FINGERPRINT",
+];
+
+fn row(service: &DiscoveredService) -> [String; 7] {
+    let addresses = service
+        .addresses
+        .iter()
+        .map(|addr| addr.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let (version, enroll, fingerprint) = match &service.advertisement {
+        Some(ad) => (
+            ad.version().to_string(),
+            if ad.enrollment_open() {
+                "open"
+            } else {
+                "closed"
+            }
+            .to_string(),
+            ad.fingerprint()
+                .map(|fp| fp.to_string())
+                .unwrap_or("-".to_string()),
+        ),
+        None => ("?".to_string(), "?".to_string(), "?".to_string()),
+    };
+
+    [
+        service.instance_name.clone(),
+        service.host_name.clone(),
+        addresses,
+        service.port.to_string(),
+        version,
+        enroll,
+        fingerprint,
+    ]
+}
+
+fn to_json(service: &DiscoveredService) -> serde_json::Value {
+    let advertisement = service.advertisement.map(|ad| {
+        serde_json::json!({
+            "version": ad.version(),
+            "signature_algorithms": ad.signature_algorithms().collect::<Vec<_>>(),
+            "fingerprint": ad.fingerprint(),
+            "tls": ad.tls(),
+            "enrollment_open": ad.enrollment_open(),
+        })
+    });
+
+    serde_json::json!({
+        "name": service.instance_name,
+        "host": service.host_name,
+        "addresses": service.addresses,
+        "port": service.port,
+        "advertisement": advertisement,
+    })
+}
+
+pub fn discover(config: &ClientConfig, args: DiscoverCommand) -> Result<(), Error> {
+    let services = discovery::browse(config.service_type(), Duration::from_secs(args.timeout))?;
+
+    if args.json {
+        let services: Vec<serde_json::Value> = services.iter().map(to_json).collect();
+        println!("{}", serde_json::to_string_pretty(&services)?);
+    } else if services.is_empty() {
+        eprintln!("No daemons found for {}", config.service_type());
+    } else {
+        let rows: Vec<[String; 7]> = services.iter().map(row).collect();
+        print_table(COLUMNS, &rows);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use crate::args::EnrollCommand;
+use crate::profile::{self, Profile};
+use p256::ecdsa::SigningKey;
+use p256::pkcs8::{DecodePrivateKey, EncodePrivateKey};
+use rand::rngs::OsRng;
+use remote_unlock_lib::client::{Client, MemoryNonceStore};
+use remote_unlock_lib::crypto::key::PrivateKey;
+use remote_unlock_lib::pairing_uri::PairingUri;
+use remote_unlock_lib::prelude::*;
+use std::path::Path;
+
+pub fn enroll(config: &ClientConfig, args: EnrollCommand) -> Result<(), Error> {
+    if Profile::exists(config, &args.profile)? && !args.force {
+        return Err(Error::new(ErrorKind::ProfileExists, Some(&args.profile)));
+    }
+
+    let (host, port, code) = match args.uri {
+        Some(ref uri) => {
+            let uri = PairingUri::parse(uri)?;
+            (uri.host().to_string(), uri.port(), uri.code())
+        }
+        None => (
+            args.host.clone().ok_or(Error::new(
+                ErrorKind::IncompleteRequest,
+                Some("Missing host"),
+            ))?,
+            args.port.unwrap_or(config.server_port()),
+            args.code.ok_or(Error::new(
+                ErrorKind::IncompleteRequest,
+                Some("Missing enrollment code"),
+            ))?,
+        ),
+    };
+
+    let privkey = match args.key {
+        Some(ref path) => PrivateKey::read_pem_file(Path::new(path))?,
+        None => {
+            let secret = p256::SecretKey::random(&mut OsRng);
+            PrivateKey::from_der(secret.to_pkcs8_der()?.as_bytes())?
+        }
+    };
+    let signing_key = SigningKey::from_pkcs8_der(privkey.der()?.as_bytes())?;
+
+    let mut client = Client::new(&host, port, signing_key, MemoryNonceStore::new());
+    let id = client.enroll(code)?;
+
+    profile::write_private(
+        &Profile::key_path(config, &args.profile)?,
+        privkey.pem()?.as_bytes(),
+    )?;
+
+    let profile = Profile {
+        id,
+        host,
+        port,
+        nonce: 0,
+    };
+    profile.save(config, &args.profile)?;
+
+    println!(
+        "Enrolled with {}:{} as {} (profile \"{}\")",
+        profile.host, profile.port, profile.id, args.profile
+    );
+
+    Ok(())
+}
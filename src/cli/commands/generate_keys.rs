@@ -4,7 +4,7 @@ use remote_unlock_lib::prelude::*;
 use std::path::Path;
 
 #[cfg(debug_assertions)]
-pub fn generate_keys(config: &Config, args: GenerateKeysCommand) -> Result<(), Error> {
+pub fn generate_keys(config: &ClientConfig, args: GenerateKeysCommand) -> Result<(), Error> {
     use pkcs8::{EncodePrivateKey, EncodePublicKey};
     use remote_unlock_lib::crypto::key::{PrivateKey, PublicKey};
 
@@ -0,0 +1,18 @@
+use crate::args::LockCommand;
+use crate::profile;
+use remote_unlock_lib::prelude::*;
+
+pub fn lock(config: &ClientConfig, args: LockCommand) -> Result<(), Error> {
+    let mut client = profile::client(config, &args.profile)?;
+    if args.challenge {
+        client = client.with_challenges();
+    }
+    if args.timestamp {
+        client = client.with_timestamps();
+    }
+    client.lock()?;
+
+    println!("Locked {}:{}", client.host(), client.port());
+
+    Ok(())
+}
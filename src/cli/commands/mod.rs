@@ -1,7 +1,33 @@
 mod begin_enroll;
+mod codes;
+mod config;
+mod devices;
+mod discover;
+mod enroll;
 mod generate_keys;
+mod lock;
+mod reload;
+mod server_status;
+mod status;
+mod storage;
+mod terminate;
+mod unlock;
+mod wake;
 
 pub use begin_enroll::begin_enroll;
+pub use codes::codes;
+pub use config::config;
+pub use devices::devices;
+pub use discover::discover;
+pub use enroll::enroll;
+pub use lock::lock;
+pub use reload::reload;
+pub use server_status::server_status;
+pub use status::status;
+pub use storage::storage;
+pub use terminate::terminate;
+pub use unlock::unlock;
+pub use wake::wake;
 
 #[cfg(debug_assertions)]
 pub use generate_keys::generate_keys;
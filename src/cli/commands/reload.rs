@@ -0,0 +1,14 @@
+use crate::args::ReloadCommand;
+use crate::control::ControlClient;
+use remote_unlock_lib::messages::{MessageData, ReplyData};
+use remote_unlock_lib::prelude::*;
+
+pub fn reload(config: &ClientConfig, _args: ReloadCommand) -> Result<(), Error> {
+    let mut control = ControlClient::connect(config)?;
+    match control.call(MessageData::Reload(()))? {
+        ReplyData::Reload(()) => println!("Configuration reloaded"),
+        _ => return Err(ErrorKind::UnexpectedMessage.into()),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,21 @@
+use crate::args::ServerStatusCommand;
+use crate::control::ControlClient;
+use remote_unlock_lib::messages::{MessageData, ReplyData};
+use remote_unlock_lib::prelude::*;
+
+pub fn server_status(config: &ClientConfig, _args: ServerStatusCommand) -> Result<(), Error> {
+    let mut control = ControlClient::connect(config)?;
+    let status = match control.call(MessageData::Status(()))? {
+        ReplyData::Status(status) => status,
+        _ => return Err(ErrorKind::UnexpectedMessage.into()),
+    };
+
+    println!("Version: {}", status.version);
+    println!("Control protocol: {}", status.control_version);
+    println!("Fingerprint: {}", status.fingerprint);
+    println!("Uptime: {}s", status.uptime_secs);
+    println!("Pending codes: {}", status.pending_codes);
+    println!("Enrolled devices: {}", status.enrolled_devices);
+
+    Ok(())
+}
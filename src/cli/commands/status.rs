@@ -0,0 +1,23 @@
+use crate::args::StatusCommand;
+use crate::profile;
+use remote_unlock_lib::prelude::*;
+
+pub fn status(config: &ClientConfig, args: StatusCommand) -> Result<(), Error> {
+    let mut client = profile::client(config, &args.profile)?;
+    if args.challenge {
+        client = client.with_challenges();
+    }
+    if args.timestamp {
+        client = client.with_timestamps();
+    }
+    let status = client.status()?;
+
+    let state = if status.locked() {
+        "locked"
+    } else {
+        "unlocked"
+    };
+    println!("{}:{} is {}", client.host(), client.port(), state);
+
+    Ok(())
+}
@@ -0,0 +1,98 @@
+use crate::args::{StorageCommand, StorageMigrateCommand, StorageRepairCommand};
+use remote_unlock_lib::clock::{Clock, SystemClock};
+use remote_unlock_lib::config::Overrides;
+use remote_unlock_lib::prelude::*;
+use remote_unlock_lib::store::migrate::{self, MigrationPlan};
+use remote_unlock_lib::store::{self, RegistryKey, StorageLock, Transaction};
+
+fn print_plan(plan: &MigrationPlan) {
+    println!(
+        "Storage is at version {}, this build uses version {}",
+        plan.from, plan.to
+    );
+    for migration in &plan.migrations {
+        println!();
+        println!("Version {}: {}", migration.version, migration.description);
+        if migration.changes.is_empty() {
+            println!("  Nothing to change");
+        }
+        for change in &migration.changes {
+            println!("  {}", change);
+        }
+    }
+}
+
+fn run_migrate(
+    config: &ServerConfig,
+    key: &RegistryKey,
+    args: StorageMigrateCommand,
+) -> Result<(), Error> {
+    let _lock = StorageLock::acquire(config)?;
+    let plan = migrate::plan(config, key)?;
+    if plan.is_empty() {
+        eprintln!("Storage is up to date at version {}", plan.to);
+        return Ok(());
+    }
+
+    print_plan(&plan);
+    println!();
+    if args.dry_run {
+        println!(
+            "A backup would be written to {} first",
+            migrate::backups_dir(config).display()
+        );
+        return Ok(());
+    }
+
+    if let Some(backup) = migrate::migrate(config, key)? {
+        println!("Backup written to {}", backup.display());
+    }
+    println!("Storage migrated to version {}", plan.to);
+    Ok(())
+}
+
+// Reads the storage as it is, opening it normally could migrate it first
+fn run_verify(config: &ServerConfig) -> Result<(), Error> {
+    let rejected = store::open_existing(config)?.rejected()?;
+    if rejected.is_empty() {
+        println!("All devices and nonces verified");
+        return Ok(());
+    }
+
+    for id in &rejected {
+        println!("{}", id);
+    }
+    Err(Error::new(
+        ErrorKind::Tampered,
+        Some("Records failed verification"),
+    ))
+}
+
+// Rejected devices are removed unless trusted, in which case they are sealed
+// again as they are. Needs the daemon stopped, it holds the storage lock.
+fn run_repair(config: &ServerConfig, args: StorageRepairCommand) -> Result<(), Error> {
+    let store = store::open(config)?;
+    for id in store.rejected()? {
+        if args.trust.contains(&id) {
+            store.reseal(&id)?;
+            println!("Resealed {}", id);
+        } else {
+            store.commit(Transaction::new().remove_device(id).audit(
+                id,
+                "remove",
+                SystemClock.now(),
+            ))?;
+            println!("Removed {}", id);
+        }
+    }
+    Ok(())
+}
+
+pub fn storage(overrides: &Overrides, command: StorageCommand) -> Result<(), Error> {
+    let config = ServerConfig::load(overrides)?;
+    match command {
+        StorageCommand::Migrate(args) => run_migrate(&config, &RegistryKey::load(&config)?, args),
+        StorageCommand::Verify(_) => run_verify(&config),
+        StorageCommand::Repair(args) => run_repair(&config, args),
+    }
+}
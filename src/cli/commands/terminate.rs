@@ -0,0 +1,14 @@
+use crate::args::TerminateCommand;
+use crate::control::ControlClient;
+use remote_unlock_lib::messages::{MessageData, ReplyData};
+use remote_unlock_lib::prelude::*;
+
+pub fn terminate(config: &ClientConfig, _args: TerminateCommand) -> Result<(), Error> {
+    let mut control = ControlClient::connect(config)?;
+    match control.call(MessageData::Terminate(()))? {
+        ReplyData::Terminate(()) => println!("Server stopping"),
+        _ => return Err(ErrorKind::UnexpectedMessage.into()),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,18 @@
+use crate::args::UnlockCommand;
+use crate::profile;
+use remote_unlock_lib::prelude::*;
+
+pub fn unlock(config: &ClientConfig, args: UnlockCommand) -> Result<(), Error> {
+    let mut client = profile::client(config, &args.profile)?;
+    if args.challenge {
+        client = client.with_challenges();
+    }
+    if args.timestamp {
+        client = client.with_timestamps();
+    }
+    client.unlock()?;
+
+    println!("Unlocked {}:{}", client.host(), client.port());
+
+    Ok(())
+}
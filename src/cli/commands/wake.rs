@@ -0,0 +1,18 @@
+use crate::args::WakeCommand;
+use crate::profile;
+use remote_unlock_lib::prelude::*;
+
+pub fn wake(config: &ClientConfig, args: WakeCommand) -> Result<(), Error> {
+    let mut client = profile::client(config, &args.profile)?;
+    if args.challenge {
+        client = client.with_challenges();
+    }
+    if args.timestamp {
+        client = client.with_timestamps();
+    }
+    client.wake()?;
+
+    println!("Woke {}:{}", client.host(), client.port());
+
+    Ok(())
+}
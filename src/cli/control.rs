@@ -0,0 +1,32 @@
+use remote_unlock_lib::messages::{
+    read_reply, write_frame, Message, MessageData, MessageType, ReplyData, VersionRange,
+};
+use remote_unlock_lib::prelude::*;
+use std::os::unix::net::UnixStream;
+
+// Connection to the local daemon's control socket
+pub struct ControlClient {
+    stream: UnixStream,
+}
+
+impl ControlClient {
+    pub fn connect(config: &ClientConfig) -> Result<ControlClient, Error> {
+        let stream = UnixStream::connect(config.socket_path())?;
+        let mut client = ControlClient { stream };
+
+        let hello = MessageData::Hello(VersionRange::supported());
+        match client.call(hello)? {
+            ReplyData::Hello(version) => debug!("Control protocol version {}", version),
+            _ => return Err(ErrorKind::UnexpectedMessage.into()),
+        }
+
+        Ok(client)
+    }
+
+    // Sends a request, the reply is checked to answer it
+    pub fn call(&mut self, data: MessageData) -> Result<ReplyData, Error> {
+        let message_type: MessageType = data.message_type();
+        write_frame(&mut self.stream, &Message::new(data))?;
+        read_reply(&mut self.stream)?.into_data(message_type)
+    }
+}
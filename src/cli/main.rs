@@ -1,22 +1,84 @@
 mod args;
 mod commands;
+mod control;
+mod profile;
+mod qr;
+mod table;
 use args::{Cli, Command};
 use clap::Parser;
+use remote_unlock_lib::config::Overrides;
 use remote_unlock_lib::prelude::*;
 
+fn overrides(args: &Cli) -> Result<Overrides, Error> {
+    let mut overrides = Overrides::new();
+    if let Some(ref file) = args.config {
+        overrides = overrides.file(file);
+    }
+    for assignment in args.set.iter() {
+        overrides = overrides.assign(assignment)?;
+    }
+
+    Ok(overrides)
+}
+
 fn main() -> Result<(), Error> {
-    let config = Config::new();
     let args = Cli::parse();
+    let overrides = overrides(&args)?;
+    let config = match ClientConfig::load(&overrides) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
 
     match args.command {
-        Command::BeginEnroll(_) => {
-            commands::begin_enroll(&config).unwrap();
+        Command::BeginEnroll(begin_enroll) => {
+            commands::begin_enroll(&config, begin_enroll).unwrap();
+        }
+        Command::Discover(discover) => {
+            commands::discover(&config, discover).unwrap();
+        }
+        Command::Enroll(enroll) => {
+            commands::enroll(&config, enroll).unwrap();
+        }
+        Command::Unlock(unlock) => {
+            commands::unlock(&config, unlock).unwrap();
+        }
+        Command::Lock(lock) => {
+            commands::lock(&config, lock).unwrap();
+        }
+        Command::Wake(wake) => {
+            commands::wake(&config, wake).unwrap();
+        }
+        Command::Status(status) => {
+            commands::status(&config, status).unwrap();
+        }
+        Command::Config(command) => {
+            commands::config(&config, &overrides, command).unwrap();
+        }
+        Command::Codes(command) => {
+            commands::codes(&config, command).unwrap();
+        }
+        Command::Devices(command) => {
+            commands::devices(&config, &overrides, command).unwrap();
+        }
+        Command::Storage(command) => {
+            commands::storage(&overrides, command).unwrap();
+        }
+        Command::ServerStatus(server_status) => {
+            commands::server_status(&config, server_status).unwrap();
+        }
+        Command::Reload(reload) => {
+            commands::reload(&config, reload).unwrap();
         }
         #[cfg(debug_assertions)]
         Command::GenerateKeys(generate_keys) => {
             commands::generate_keys(&config, generate_keys).unwrap();
         }
-        Command::Terminate(_) => {}
+        Command::Terminate(terminate) => {
+            commands::terminate(&config, terminate).unwrap();
+        }
     };
 
     Ok(())
@@ -0,0 +1,130 @@
+use std::ffi::OsString;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use p256::ecdsa::SigningKey;
+use p256::pkcs8::DecodePrivateKey;
+use remote_unlock_lib::client::{Client, NonceStore};
+use remote_unlock_lib::crypto::key::PrivateKey;
+use remote_unlock_lib::prelude::*;
+use serde::{Deserialize, Serialize};
+
+// A server this client has enrolled with
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Profile {
+    pub id: uuid::Uuid,
+    pub host: String,
+    pub port: u16,
+    // Next nonce to sign, always ahead of the last one sent
+    pub nonce: u128,
+}
+
+impl Profile {
+    // Names become file names, so they must stay inside the profiles dir
+    fn file_path(config: &ClientConfig, name: &str, extension: &str) -> Result<PathBuf, Error> {
+        if name.is_empty() || name.contains('/') || name.contains("..") {
+            return Err(Error::new(
+                ErrorKind::InvalidArgument,
+                Some("Invalid profile name"),
+            ));
+        }
+
+        Ok(config
+            .profiles_dir()
+            .join(format!("{}.{}", name, extension)))
+    }
+
+    fn path(config: &ClientConfig, name: &str) -> Result<PathBuf, Error> {
+        Self::file_path(config, name, "json")
+    }
+
+    pub fn key_path(config: &ClientConfig, name: &str) -> Result<PathBuf, Error> {
+        Self::file_path(config, name, "pem")
+    }
+
+    pub fn exists(config: &ClientConfig, name: &str) -> Result<bool, Error> {
+        Ok(Self::path(config, name)?.exists())
+    }
+
+    pub fn load(config: &ClientConfig, name: &str) -> Result<Profile, Error> {
+        let path = Self::path(config, name)?;
+        debug!("Loading profile from {:?}", &path);
+
+        let file = match std::fs::File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(Error::new(ErrorKind::ProfileNotFound, Some(name)));
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(serde_json::from_reader(file)?)
+    }
+
+    pub fn save(&self, config: &ClientConfig, name: &str) -> Result<(), Error> {
+        let path = Self::path(config, name)?;
+        debug!("Saving profile to {:?}", &path);
+
+        write_private(&path, serde_json::to_string_pretty(self)?.as_bytes())
+    }
+}
+
+// Persists the nonce with the rest of the profile
+pub struct ProfileNonceStore<'a> {
+    config: &'a ClientConfig,
+    name: String,
+    profile: Profile,
+}
+
+impl NonceStore for ProfileNonceStore<'_> {
+    fn next_nonce(&mut self, _id: &uuid::Uuid) -> Result<u128, Error> {
+        let nonce = self.profile.nonce;
+        self.profile.nonce += 1;
+        self.profile.save(self.config, &self.name)?;
+        Ok(nonce)
+    }
+}
+
+// Client for a previously enrolled profile
+pub fn client<'a>(
+    config: &'a ClientConfig,
+    name: &str,
+) -> Result<Client<SigningKey, ProfileNonceStore<'a>>, Error> {
+    let profile = Profile::load(config, name)?;
+    let privkey = PrivateKey::read_pem_file(&Profile::key_path(config, name)?)?;
+    let signing_key = SigningKey::from_pkcs8_der(privkey.der()?.as_bytes())?;
+
+    let (host, port, id) = (profile.host.clone(), profile.port, profile.id);
+    let nonce_store = ProfileNonceStore {
+        config,
+        name: name.to_string(),
+        profile,
+    };
+
+    Ok(Client::new(&host, port, signing_key, nonce_store).with_id(id))
+}
+
+// Replaces the file atomically, readable only by the current user
+pub fn write_private(path: &Path, contents: &[u8]) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    // Keep the full file name so the profile and its key get separate files
+    let mut tmp_name = OsString::from(path.as_os_str());
+    tmp_name.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_name);
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+
+    std::fs::rename(&tmp_path, path)?;
+
+    Ok(())
+}
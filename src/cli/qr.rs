@@ -0,0 +1,69 @@
+use std::io::BufWriter;
+use std::path::Path;
+
+use qrcode::render::{svg, unicode};
+use qrcode::{Color, QrCode};
+use remote_unlock_lib::pairing_uri::PairingUri;
+use remote_unlock_lib::prelude::*;
+
+// Modules of light border required around the code by the QR spec
+const QUIET_ZONE: usize = 4;
+const PNG_MODULE_PIXELS: usize = 8;
+
+fn encode(uri: &PairingUri) -> Result<QrCode, Error> {
+    QrCode::new(uri.to_string().as_bytes())
+        .map_err(|_| Error::new(ErrorKind::QrCode, Some("Pairing URI too long to encode")))
+}
+
+// Renders with half-block characters, inverted so it scans on dark terminals
+pub fn render_terminal(uri: &PairingUri) -> Result<String, Error> {
+    Ok(encode(uri)?
+        .render::<unicode::Dense1x2>()
+        .dark_color(unicode::Dense1x2::Light)
+        .light_color(unicode::Dense1x2::Dark)
+        .build())
+}
+
+pub fn write_svg(uri: &PairingUri, path: &Path) -> Result<(), Error> {
+    let image = encode(uri)?
+        .render::<svg::Color>()
+        .min_dimensions(256, 256)
+        .build();
+
+    std::fs::write(path, image)?;
+
+    Ok(())
+}
+
+pub fn write_png(uri: &PairingUri, path: &Path) -> Result<(), Error> {
+    let code = encode(uri)?;
+    let colors = code.to_colors();
+    let modules = code.width();
+    let size = (modules + QUIET_ZONE * 2) * PNG_MODULE_PIXELS;
+
+    let mut pixels = vec![0xff_u8; size * size];
+    for (idx, color) in colors.iter().enumerate() {
+        if *color != Color::Dark {
+            continue;
+        }
+
+        let x0 = (idx % modules + QUIET_ZONE) * PNG_MODULE_PIXELS;
+        let y0 = (idx / modules + QUIET_ZONE) * PNG_MODULE_PIXELS;
+        for y in y0..y0 + PNG_MODULE_PIXELS {
+            pixels[y * size + x0..y * size + x0 + PNG_MODULE_PIXELS].fill(0);
+        }
+    }
+
+    let png_error = |_| Error::new(ErrorKind::QrCode, Some("Failed to write PNG"));
+
+    let file = std::fs::File::create(path)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), size as u32, size as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(png_error)?;
+    writer.write_image_data(&pixels).map_err(png_error)?;
+    writer.finish().map_err(png_error)?;
+
+    Ok(())
+}
@@ -0,0 +1,24 @@
+// Prints rows under left aligned headings, columns sized to fit
+pub fn print_table<const N: usize>(columns: [&str; N], rows: &[[String; N]]) {
+    let mut widths = columns.map(|column| column.len());
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let print_row = |cells: &[&str]| {
+        let line = cells
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    };
+
+    print_row(&columns);
+    for row in rows.iter() {
+        print_row(&row.each_ref().map(|cell| cell.as_str()));
+    }
+}
@@ -0,0 +1,27 @@
+use remote_unlock_lib::config::Overrides;
+use remote_unlock_lib::prelude::*;
+
+const USAGE: &str = "Usage: server [--config <file>] [--set <key>=<value>]...";
+
+// The daemon only takes config overrides, parsed by hand to keep clap out of it
+pub fn overrides(mut args: impl Iterator<Item = String>) -> Result<Overrides, Error> {
+    let mut overrides = Overrides::new();
+    let missing = || Error::new(ErrorKind::InvalidArgument, Some("Missing value"));
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-c" | "--config" => overrides = overrides.file(args.next().ok_or_else(missing)?),
+            "--set" => overrides = overrides.assign(&args.next().ok_or_else(missing)?)?,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            _ => {
+                eprintln!("{}", USAGE);
+                return Err(Error::new(ErrorKind::InvalidArgument, Some(&arg)));
+            }
+        }
+    }
+
+    Ok(overrides)
+}
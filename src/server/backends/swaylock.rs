@@ -26,12 +26,61 @@ impl SwaylockBackend {
         self.wake_screen()
     }
 
+    pub fn lock(&mut self) -> Result<(), Error> {
+        trace!("Locking with swaylock");
+
+        // Forks once the screen is locked, the child must not hold our pipes open
+        let lock_result = std::process::Command::new("swaylock")
+            .arg("--daemonize")
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()?;
+
+        if lock_result.success() {
+            trace!("Swaylock locked");
+            Ok(())
+        } else {
+            error!("Failed to lock swaylock: {:?}", lock_result);
+            Err(Error::new(
+                ErrorKind::SwaylockBackend,
+                Some("Failed to lock swaylock"),
+            ))
+        }
+    }
+
+    pub fn wake(&mut self) -> Result<(), Error> {
+        self.wake_screen()
+    }
+
+    pub fn locked(&self) -> bool {
+        !swaylock_starts().is_empty()
+    }
+
+    // Unix time the newest running swaylock started, None when none is
+    // running or /proc cannot tell
+    pub fn lock_started(&self) -> Option<i64> {
+        let boot_time = boot_time(&std::fs::read_to_string("/proc/stat").ok()?)?;
+        // SAFETY: sysconf has no preconditions
+        let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+        if ticks <= 0 {
+            return None;
+        }
+
+        let start = swaylock_starts().into_iter().max()?;
+        Some(boot_time + (start / ticks as u64) as i64)
+    }
+
     fn unlock_swaylock(&self) -> Result<(), Error> {
         trace!("Unlocking swaylock");
 
         trace!("Sending USR1 signal to swaylock");
+        // SAFETY: getuid has no preconditions and cannot fail
+        let uid = unsafe { libc::getuid() };
         let unlock_result = std::process::Command::new("pkill")
             .arg("-USR1")
+            .arg("-U")
+            .arg(uid.to_string())
+            .arg("-x")
             .arg("swaylock")
             .output()?;
 
@@ -66,3 +115,93 @@ impl SwaylockBackend {
         Ok(())
     }
 }
+
+// Start times in clock ticks after boot of the swaylocks running as the
+// daemon's user, another user's lock screen says nothing about ours
+fn swaylock_starts() -> Vec<u64> {
+    // SAFETY: getuid has no preconditions and cannot fail
+    let uid = unsafe { libc::getuid() };
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+
+    let mut starts = Vec::new();
+    for entry in entries.flatten() {
+        let is_pid = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.bytes().all(|b| b.is_ascii_digit()));
+        if !is_pid {
+            continue;
+        }
+        // Processes may exit while being listed
+        let Ok(stat) = std::fs::read_to_string(entry.path().join("stat")) else {
+            continue;
+        };
+        let Some(("swaylock", start)) = process_start(&stat) else {
+            continue;
+        };
+        let Ok(status) = std::fs::read_to_string(entry.path().join("status")) else {
+            continue;
+        };
+        if process_uid(&status) == Some(uid) {
+            starts.push(start);
+        }
+    }
+
+    starts
+}
+
+// Real uid from `/proc/<pid>/status`
+fn process_uid(status: &str) -> Option<u32> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Uid:"))?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+// Name and start time in clock ticks after boot from `/proc/<pid>/stat`. The
+// name is in parentheses and may hold anything, fields follow the last one.
+fn process_start(stat: &str) -> Option<(&str, u64)> {
+    let (head, fields) = stat.rsplit_once(')')?;
+    let (_, name) = head.split_once('(')?;
+    // `starttime` is field 22, the state after the name is field 3
+    let start = fields.split_whitespace().nth(19)?.parse().ok()?;
+    Some((name, start))
+}
+
+// Unix time of boot from `/proc/stat`
+fn boot_time(stat: &str) -> Option<i64> {
+    stat.lines()
+        .find_map(|line| line.strip_prefix("btime "))?
+        .trim()
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process_start() {
+        let stat = "4242 (sway lock) S 1 4242 4242 0 -1 4194560 1200 0 0 0 3 1 0 0 20 0 1 0 98765 1000 100";
+        assert_eq!(process_start(stat), Some(("sway lock", 98765)));
+        assert_eq!(process_start("4242 (swaylock) S 1"), None);
+        assert_eq!(
+            boot_time("cpu  1 2 3\nbtime 1700000000\nprocesses 10\n"),
+            Some(1_700_000_000)
+        );
+        assert_eq!(boot_time("cpu  1 2 3\n"), None);
+    }
+
+    #[test]
+    fn test_process_uid() {
+        let status = "Name:\tswaylock\nUmask:\t0022\nState:\tS (sleeping)\nUid:\t1000\t1001\t1001\t1001\nGid:\t100\t100\t100\t100\n";
+        assert_eq!(process_uid(status), Some(1000));
+        assert_eq!(process_uid("Name:\tswaylock\n"), None);
+    }
+}
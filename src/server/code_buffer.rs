@@ -1,13 +1,24 @@
+use remote_unlock_lib::clock::SharedClock;
 use remote_unlock_lib::enrollment_code::EnrollmentCode;
 use remote_unlock_lib::prelude::*;
 
+// Sent by the control socket, applied before the next request is handled
+pub enum CodeEvent {
+    Issued(EnrollmentCode),
+    Cancelled(u32),
+}
+
 pub struct CodeBuffer {
     codes: [Option<EnrollmentCode>; 16],
+    clock: SharedClock,
 }
 
 impl CodeBuffer {
-    pub fn new() -> CodeBuffer {
-        CodeBuffer { codes: [None; 16] }
+    pub fn new(clock: SharedClock) -> CodeBuffer {
+        CodeBuffer {
+            codes: [None; 16],
+            clock,
+        }
     }
 
     pub fn insert(&mut self, code: EnrollmentCode) -> Result<(), Error> {
@@ -28,15 +39,10 @@ impl CodeBuffer {
     pub fn clear_expired(&mut self) {
         let mut removed = 0;
         for code_opt in self.codes.iter_mut() {
-            match code_opt {
-                Some(c) => {
-                    if c.expired() {
-                        *code_opt = None;
-                        removed += 1;
-                    }
-                }
-                None => {}
-            };
+            if code_opt.is_some_and(|c| c.expired(self.clock.as_ref())) {
+                *code_opt = None;
+                removed += 1;
+            }
         }
 
         if removed > 0 {
@@ -44,10 +50,26 @@ impl CodeBuffer {
         }
     }
 
+    pub fn remove(&mut self, code: u32) -> bool {
+        let found = self
+            .codes
+            .iter_mut()
+            .find(|code_opt| code_opt.is_some_and(|c| c.code() == code));
+
+        match found {
+            Some(c) => {
+                *c = None;
+                true
+            }
+            None => false,
+        }
+    }
+
     // Verifies and removes the code from the buffer if it is valid
     pub fn verify(&mut self, code: &u32) -> bool {
+        let clock = self.clock.as_ref();
         let found = self.codes.iter_mut().find(|code_opt| match code_opt {
-            Some(c) => c.verify(code),
+            Some(c) => c.verify(code, clock),
             None => false,
         });
 
@@ -64,3 +86,45 @@ impl CodeBuffer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use remote_unlock_lib::clock::ManualClock;
+
+    #[test]
+    fn test_clear_expired() {
+        let clock = Arc::new(ManualClock::new(1_000_000));
+        let mut buffer = CodeBuffer::new(clock.clone());
+
+        let first = EnrollmentCode::new(clock.as_ref());
+        buffer.insert(first).unwrap();
+        clock.advance(60);
+        let second = EnrollmentCode::new(clock.as_ref());
+        buffer.insert(second).unwrap();
+
+        // The first code is still valid on the second it expires at
+        clock.set(first.expires());
+        buffer.clear_expired();
+        assert!(buffer.codes.contains(&Some(first)));
+
+        clock.advance(1);
+        buffer.clear_expired();
+        assert!(!buffer.codes.contains(&Some(first)));
+        assert!(buffer.verify(&second.code()));
+    }
+
+    #[test]
+    fn test_expired_code_refused() {
+        let clock = Arc::new(ManualClock::new(1_000_000));
+        let mut buffer = CodeBuffer::new(clock.clone());
+        let code = EnrollmentCode::new(clock.as_ref());
+        buffer.insert(code).unwrap();
+
+        // Refused even before the buffer is next cleared
+        clock.set(code.expires() + 1);
+        assert!(!buffer.verify(&code.code()));
+    }
+}
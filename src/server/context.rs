@@ -1,28 +1,46 @@
+use std::borrow::Cow;
 use std::io::Write;
-use std::sync::mpsc::Receiver;
+use std::net::IpAddr;
+use std::sync::mpsc::{Receiver, Sender};
 
-use remote_unlock_lib::enrollment_code::EnrollmentCode;
+use remote_unlock_lib::clock::{SharedClock, SystemClock};
+use remote_unlock_lib::policy::Policies;
 use remote_unlock_lib::prelude::*;
+use remote_unlock_lib::store::SharedStore;
 
 use crate::backends::swaylock::SwaylockBackend;
+use crate::code_buffer::CodeEvent;
+use crate::discovery::DiscoveryEvent;
 use crate::logging;
-use crate::state::State;
+use crate::reload::Reload;
+use crate::state::{NoncePolicy, State, TimestampPolicy};
 
 pub struct ServerContext<'a, T: Write> {
     state: State,
-    code_receiver: Receiver<EnrollmentCode>,
-    config: &'a Config,
+    code_receiver: Receiver<CodeEvent>,
+    consumed_codes: Option<Sender<u32>>,
+    config: Cow<'a, ServerConfig>,
+    config_receiver: Option<Receiver<Reload>>,
+    policies: Policies,
     stream: Option<T>,
+    // Source address of the connection being handled
+    peer: Option<IpAddr>,
     backend: Option<SwaylockBackend>,
+    discovery: Option<Sender<DiscoveryEvent>>,
 }
 
 impl<'a, T: Write> ServerContext<'a, T> {
     pub fn builder() -> ServerContextBuilder<'a, T> {
         ServerContextBuilder {
-            state: None,
+            store: None,
+            clock: None,
             code_receiver: None,
+            consumed_codes: None,
             config: None,
+            config_receiver: None,
+            policies: None,
             stream: None,
+            discovery: None,
         }
     }
 
@@ -31,12 +49,17 @@ impl<'a, T: Write> ServerContext<'a, T> {
     }
 
     #[allow(dead_code)]
-    pub fn code_receiver(&self) -> &Receiver<EnrollmentCode> {
+    pub fn code_receiver(&self) -> &Receiver<CodeEvent> {
         &self.code_receiver
     }
 
-    pub fn config(&self) -> &Config {
-        self.config
+    #[allow(dead_code)]
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    pub fn policies(&self) -> &Policies {
+        &self.policies
     }
 
     pub fn stream(&mut self) -> Result<&mut T, Error> {
@@ -45,31 +68,36 @@ impl<'a, T: Write> ServerContext<'a, T> {
             .ok_or(Error::new(ErrorKind::Server, Some("Unset stream")))
     }
 
-    pub fn replace_stream(&mut self, stream: T) {
+    pub fn replace_stream(&mut self, stream: T, peer: Option<IpAddr>) {
         self.stream.replace(stream);
+        self.peer = peer;
     }
 
     pub fn remove_stream(&mut self) {
         self.stream = None;
+        self.peer = None;
     }
 
-    pub fn create_storage_dirs(&mut self) -> Result<(), Error> {
-        let keys_dir = self.config.keys_dir();
-        debug!(
-            "Creating keys directory: {}",
-            keys_dir.to_str().unwrap_or("Malformed path")
-        );
-
-        std::fs::create_dir_all(keys_dir)?;
+    pub fn peer(&self) -> Option<IpAddr> {
+        self.peer
+    }
 
-        let nonce_dir = self.config.nonce_dir();
-        debug!(
-            "Creating nonce directory: {}",
-            nonce_dir.to_str().unwrap_or("Malformed path")
-        );
-        std::fs::create_dir_all(nonce_dir)?;
+    pub fn notify_discovery(&self, event: DiscoveryEvent) {
+        if let Some(discovery) = &self.discovery {
+            if discovery.send(event).is_err() {
+                warn!("Discovery daemon not running, advertisement not updated");
+            }
+        }
+    }
 
-        Ok(())
+    // Withdraws the code from the advertisement and the control socket's list
+    pub fn code_consumed(&self, code: u32) {
+        self.notify_discovery(DiscoveryEvent::CodeConsumed(code));
+        if let Some(consumed_codes) = &self.consumed_codes {
+            if consumed_codes.send(code).is_err() {
+                warn!("Control socket not running, code {} not withdrawn", code);
+            }
+        }
     }
 
     fn register_backend(&mut self) -> Result<(), Error> {
@@ -90,9 +118,47 @@ impl<'a, T: Write> ServerContext<'a, T> {
         Ok(())
     }
 
+    pub fn lock(&mut self) -> Result<(), Error> {
+        self.backend
+            .as_mut()
+            .ok_or(Error::new(
+                ErrorKind::Server,
+                Some("Backend not initialized"),
+            ))?
+            .lock()?;
+        self.state.record_lock();
+
+        Ok(())
+    }
+
+    pub fn wake(&mut self) -> Result<(), Error> {
+        self.backend
+            .as_mut()
+            .ok_or(Error::new(
+                ErrorKind::Server,
+                Some("Backend not initialized"),
+            ))?
+            .wake()?;
+
+        Ok(())
+    }
+
+    // When the screen was locked, by the server or anything else
+    pub fn lock_started(&self) -> Option<i64> {
+        self.backend
+            .as_ref()
+            .and_then(|backend| backend.lock_started())
+    }
+
+    pub fn locked(&self) -> bool {
+        self.backend
+            .as_ref()
+            .map(|backend| backend.locked())
+            .unwrap_or(false)
+    }
+
     pub fn init(&mut self) -> Result<(), Error> {
-        logging::Logger::init(self.config)?;
-        self.create_storage_dirs()?;
+        logging::Logger::init(&self.config)?;
         self.register_backend()?;
         Ok(())
     }
@@ -107,45 +173,115 @@ impl<'a, T: Write> ServerContext<'a, T> {
         code_buffer.clear_expired();
 
         // Drain the code channel into the buffer
-        'buffer_drain: while let Ok(code) = code_receiver.try_recv() {
-            match code_buffer.insert(code) {
-                Ok(_) => {
-                    debug!("Inserted code into buffer: {}", code);
-                }
-                Err(_) => {
-                    warn!("Code buffer full, ignoring code {:?}", code);
-                    break 'buffer_drain;
+        while let Ok(event) = code_receiver.try_recv() {
+            match event {
+                CodeEvent::Issued(code) => match code_buffer.insert(code) {
+                    Ok(_) => {
+                        debug!("Inserted code into buffer: {}", code);
+                    }
+                    Err(_) => {
+                        warn!("Code buffer full, ignoring code {:?}", code);
+                    }
+                },
+                CodeEvent::Cancelled(code) => {
+                    if code_buffer.remove(code) {
+                        debug!("Cancelled code: {}", code);
+                    }
                 }
             }
         }
 
         Ok(())
     }
+
+    // Swaps in the most recently reloaded config, state is left untouched
+    pub fn process_reloads(&mut self) {
+        let Some(config_receiver) = &self.config_receiver else {
+            return;
+        };
+        let reloads = config_receiver.try_iter().collect::<Vec<_>>();
+        let Some(config) = reloads.last().map(|reload| reload.config.clone()) else {
+            return;
+        };
+
+        debug!("Applying reloaded configuration");
+        self.state
+            .set_nonce_policy(NoncePolicy::from_config(&config));
+        self.state
+            .set_timestamp_policy(TimestampPolicy::from_config(&config));
+        match Policies::load(&config) {
+            Ok(policies) => self.policies = policies,
+            Err(e) => error!("Failed to reload policies, keeping the old ones: {}", e),
+        }
+        let backend_changed = config.backend_changed(&self.config);
+        self.config = Cow::Owned(config);
+        if backend_changed {
+            if let Err(e) = self.register_backend() {
+                error!("Failed to re-register backend, keeping the old one: {}", e);
+            }
+        }
+
+        for reload in reloads {
+            // The reloader may have given up waiting
+            let _ = reload.applied.send(());
+        }
+    }
 }
 
 pub struct ServerContextBuilder<'a, T: Write> {
-    state: Option<State>,
-    code_receiver: Option<Receiver<EnrollmentCode>>,
-    config: Option<&'a Config>,
+    store: Option<SharedStore>,
+    clock: Option<SharedClock>,
+    code_receiver: Option<Receiver<CodeEvent>>,
+    consumed_codes: Option<Sender<u32>>,
+    config: Option<&'a ServerConfig>,
+    config_receiver: Option<Receiver<Reload>>,
+    policies: Option<Policies>,
     stream: Option<T>,
+    discovery: Option<Sender<DiscoveryEvent>>,
 }
 
 impl<'a, T: Write> ServerContextBuilder<'a, T> {
-    pub fn state(mut self, state: State) -> Self {
-        self.state = Some(state);
+    pub fn store(mut self, store: SharedStore) -> Self {
+        self.store = Some(store);
         self
     }
 
-    pub fn code_receiver(mut self, code_receiver: Receiver<EnrollmentCode>) -> Self {
+    // Every expiry in the server loop is measured with this clock
+    pub fn clock(mut self, clock: SharedClock) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    pub fn code_receiver(mut self, code_receiver: Receiver<CodeEvent>) -> Self {
         self.code_receiver = Some(code_receiver);
         self
     }
 
-    pub fn config(mut self, config: &'a Config) -> Self {
+    pub fn consumed_codes(mut self, consumed_codes: Sender<u32>) -> Self {
+        self.consumed_codes = Some(consumed_codes);
+        self
+    }
+
+    pub fn config(mut self, config: &'a ServerConfig) -> Self {
         self.config = Some(config);
         self
     }
 
+    pub fn config_receiver(mut self, config_receiver: Receiver<Reload>) -> Self {
+        self.config_receiver = Some(config_receiver);
+        self
+    }
+
+    pub fn policies(mut self, policies: Policies) -> Self {
+        self.policies = Some(policies);
+        self
+    }
+
+    pub fn discovery(mut self, discovery: Sender<DiscoveryEvent>) -> Self {
+        self.discovery = Some(discovery);
+        self
+    }
+
     #[allow(dead_code)]
     pub fn stream(mut self, stream: T) -> Self {
         self.stream = Some(stream);
@@ -153,18 +289,30 @@ impl<'a, T: Write> ServerContextBuilder<'a, T> {
     }
 
     pub fn build(self) -> Result<ServerContext<'a, T>, Error> {
+        let config = self
+            .config
+            .ok_or(Error::new(ErrorKind::Server, Some("Config not set")))?;
+        let store = self
+            .store
+            .ok_or(Error::new(ErrorKind::Server, Some("Store not set")))?;
+        let clock = self.clock.unwrap_or_else(SystemClock::shared);
+        let mut state = State::new(store, clock);
+        state.set_nonce_policy(NoncePolicy::from_config(config));
+        state.set_timestamp_policy(TimestampPolicy::from_config(config));
+
         Ok(ServerContext {
-            state: self
-                .state
-                .ok_or(Error::new(ErrorKind::Server, Some("State not set")))?,
+            state,
             code_receiver: self
                 .code_receiver
                 .ok_or(Error::new(ErrorKind::Server, Some("Receiver not set")))?,
-            config: self
-                .config
-                .ok_or(Error::new(ErrorKind::Server, Some("Config not set")))?,
+            consumed_codes: self.consumed_codes,
+            config: Cow::Borrowed(config),
+            config_receiver: self.config_receiver,
+            policies: self.policies.unwrap_or_default(),
             backend: None,
             stream: self.stream,
+            peer: None,
+            discovery: self.discovery,
         })
     }
 }
@@ -1,31 +1,348 @@
-use mdns_sd::{ServiceDaemon, ServiceInfo};
+use std::collections::HashMap;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
+use mdns_sd::{IfKind, Receiver, ServiceDaemon, ServiceEvent, ServiceInfo};
+
+use remote_unlock_lib::advertisement::Advertisement;
+use remote_unlock_lib::clock::SharedClock;
+use remote_unlock_lib::crypto::fingerprint::Fingerprint;
+use remote_unlock_lib::discovery::{self as browse, DiscoveredService};
+use remote_unlock_lib::enrollment_code::EnrollmentCode;
 use remote_unlock_lib::prelude::*;
 
-pub fn service_host_name(config: &Config) -> Result<ByteArray<{ Config::BUFFER_SIZE }>, Error> {
-    let mut buff = ByteArray::<{ Config::BUFFER_SIZE }>::new();
-    buff.append_slice(b"remote-unlock.")?;
+// How often pending codes are checked for expiry to close enrollment
+const EXPIRY_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// How long to listen for other daemons before picking an instance name
+const NAME_PROBE_DURATION: Duration = Duration::from_secs(1);
+
+pub enum DiscoveryEvent {
+    CodeIssued(EnrollmentCode),
+    CodeConsumed(u32),
+    CodeCancelled(u32),
+    Reconfigure(Box<ServerConfig>),
+    Shutdown,
+}
+
+pub struct Discovery {
+    sender: Sender<DiscoveryEvent>,
+    handle: JoinHandle<()>,
+}
+
+impl Discovery {
+    pub fn sender(&self) -> Sender<DiscoveryEvent> {
+        self.sender.clone()
+    }
+
+    pub fn shutdown(self) -> Result<(), Error> {
+        // The advertiser may already have exited if the daemon failed
+        let _ = self.sender.send(DiscoveryEvent::Shutdown);
+        self.handle
+            .join()
+            .map_err(|_| Error::new(ErrorKind::Server, Some("Discovery thread panicked")))
+    }
+}
+
+struct Advertiser {
+    daemon: ServiceDaemon,
+    service_type: String,
+    // Configured name, the advertised one gains a suffix on collisions
+    base_name: String,
+    instance_name: String,
+    hostname: String,
+    host_name: String,
+    fingerprint: Fingerprint,
+    browser: Receiver<ServiceEvent>,
+    // Instance names of other daemons on the LAN
+    peers: HashMap<String, Option<Fingerprint>>,
+    port: u16,
+    advertisement: Advertisement,
+    pending_codes: HashMap<u32, EnrollmentCode>,
+    clock: SharedClock,
+}
+
+impl Advertiser {
+    fn register(&self) -> Result<(), Error> {
+        let properties = self.advertisement.txt_properties();
+        // Addresses follow the enabled interfaces as they change, so a new
+        // DHCP lease or network is picked up without a reload
+        let service_info = ServiceInfo::new(
+            &self.service_type,
+            &self.instance_name,
+            &self.host_name,
+            "",
+            self.port,
+            &properties[..],
+        )?
+        .enable_addr_auto();
+
+        // Registering again re-announces the service with the new TXT records
+        self.daemon.register(service_info)?;
+        Ok(())
+    }
+
+    fn fullname(&self) -> String {
+        format!("{}.{}", self.instance_name, self.service_type)
+    }
+
+    fn handle_browse_event(&mut self, event: ServiceEvent) {
+        match event {
+            ServiceEvent::ServiceResolved(info) => {
+                let service = DiscoveredService::from_info(&self.service_type, &info);
+                let fingerprint = service
+                    .advertisement
+                    .and_then(|ad| ad.fingerprint().copied());
+                // Our own announcements are seen by the browser too
+                if fingerprint != Some(self.fingerprint) {
+                    self.peers.insert(service.instance_name, fingerprint);
+                }
+            }
+            ServiceEvent::ServiceRemoved(_, fullname) => {
+                self.peers
+                    .remove(browse::instance_name(&self.service_type, &fullname));
+            }
+            _ => {}
+        }
+    }
+
+    fn probe_peers(&mut self, duration: Duration) {
+        let deadline = Instant::now() + duration;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.browser.recv_timeout(remaining) {
+                Ok(event) => self.handle_browse_event(event),
+                Err(_) => break,
+            }
+        }
+    }
+
+    fn pick_name(&mut self) {
+        let name =
+            browse::unique_instance_name(&self.base_name, |name| self.peers.contains_key(name));
+        if name != self.base_name {
+            warn!(
+                "mDNS name {} is taken, advertising as {}",
+                self.base_name, name
+            );
+            // Hosts sharing a hostname would merge their address records
+            let tag = &self.fingerprint.to_string()[..8];
+            self.host_name = service_host_name(&self.hostname, Some(tag));
+        }
+        self.instance_name = name;
+    }
+
+    // Both daemons see the collision, only the greater fingerprint yields
+    fn resolve_collisions(&mut self) {
+        while let Ok(event) = self.browser.try_recv() {
+            self.handle_browse_event(event);
+        }
+
+        let must_rename = match self.peers.get(&self.instance_name) {
+            Some(Some(theirs)) => self.fingerprint > *theirs,
+            Some(None) => true,
+            None => false,
+        };
+        if !must_rename {
+            return;
+        }
+
+        if let Err(e) = self.daemon.unregister(&self.fullname()) {
+            warn!("Failed to unregister mDNS service: {}", e);
+        }
+        self.pick_name();
+        if let Err(e) = self.register() {
+            error!("Failed to re-register mDNS service: {}", e);
+        }
+    }
 
-    buff.append_slice(config.server_hostname().as_bytes())?;
-    buff.append_slice(b".local.")?;
+    // Pending codes survive, only the name and interfaces change
+    fn reconfigure(&mut self, config: &ServerConfig) -> Result<(), Error> {
+        if let Err(e) = self.daemon.unregister(&self.fullname()) {
+            warn!("Failed to unregister mDNS service: {}", e);
+        }
 
-    Ok(buff)
+        select_interfaces(&self.daemon, config)?;
+        if config.service_type() != self.service_type {
+            if let Err(e) = self.daemon.stop_browse(&self.service_type) {
+                warn!("Failed to stop browsing: {}", e);
+            }
+            self.browser = self.daemon.browse(config.service_type())?;
+            self.service_type = config.service_type().to_string();
+            self.peers.clear();
+            self.probe_peers(NAME_PROBE_DURATION);
+        }
+
+        self.hostname = config.server_hostname();
+        self.host_name = service_host_name(&self.hostname, None);
+        self.base_name = config.mdns_instance_name();
+        self.pick_name();
+        self.register()?;
+        info!("Advertising as {}", self.instance_name);
+
+        Ok(())
+    }
+
+    fn update_enrollment_open(&mut self) {
+        let clock = self.clock.as_ref();
+        self.pending_codes.retain(|_, code| !code.expired(clock));
+
+        let enrollment_open = !self.pending_codes.is_empty();
+        if enrollment_open == self.advertisement.enrollment_open() {
+            return;
+        }
+
+        debug!("Advertising enrollment open: {}", enrollment_open);
+        self.advertisement.set_enrollment_open(enrollment_open);
+        if let Err(e) = self.register() {
+            error!("Failed to update mDNS advertisement: {}", e);
+        }
+    }
+
+    fn run(mut self, receiver: mpsc::Receiver<DiscoveryEvent>) {
+        loop {
+            match receiver.recv_timeout(EXPIRY_POLL_INTERVAL) {
+                Ok(DiscoveryEvent::CodeIssued(code)) => {
+                    self.pending_codes.insert(code.code(), code);
+                }
+                Ok(DiscoveryEvent::CodeConsumed(code) | DiscoveryEvent::CodeCancelled(code)) => {
+                    self.pending_codes.remove(&code);
+                }
+                Ok(DiscoveryEvent::Reconfigure(config)) => {
+                    if let Err(e) = self.reconfigure(&config) {
+                        error!("Failed to reconfigure mDNS advertisement: {}", e);
+                    }
+                }
+                Ok(DiscoveryEvent::Shutdown) | Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+
+            self.resolve_collisions();
+            self.update_enrollment_open();
+        }
+
+        debug!("Shutting down discovery daemon");
+        if let Err(e) = self.daemon.stop_browse(&self.service_type) {
+            warn!("Failed to stop browsing: {}", e);
+        }
+        if let Err(e) = self.daemon.unregister(&self.fullname()) {
+            warn!("Failed to unregister mDNS service: {}", e);
+        }
+        if let Err(e) = self.daemon.shutdown() {
+            warn!("Failed to shut down mDNS daemon: {}", e);
+        }
+    }
+}
+
+pub fn service_host_name(hostname: &str, tag: Option<&str>) -> String {
+    match tag {
+        Some(tag) => format!("remote-unlock-{}.{}.local.", tag, hostname),
+        None => format!("remote-unlock.{}.local.", hostname),
+    }
 }
-pub fn start_discovery_daemon(config: &Config) -> Result<ServiceDaemon, Error> {
-    let service_host_name_buff = service_host_name(config)?;
 
-    let service_info = ServiceInfo::new(
-        config.service_type(),
-        config.server_hostname(),
-        service_host_name_buff.as_str()?,
-        "",
-        config.server_port(),
-        None,
-    )?
-    .enable_addr_auto();
+fn select_interfaces(daemon: &ServiceDaemon, config: &ServerConfig) -> Result<(), Error> {
+    // Selections apply in order, so narrow down from all interfaces. Starting
+    // over also lets a reload widen a previous selection.
+    daemon.enable_interface(IfKind::All)?;
+    if !config.mdns_interfaces().is_empty() {
+        daemon.disable_interface(IfKind::All)?;
+        for name in config.mdns_interfaces() {
+            daemon.enable_interface(IfKind::Name(name.clone()))?;
+        }
+    }
+    if !config.mdns_ipv4() {
+        daemon.disable_interface(IfKind::IPv4)?;
+    }
+    if !config.mdns_ipv6() {
+        daemon.disable_interface(IfKind::IPv6)?;
+    }
 
+    Ok(())
+}
+
+pub fn start_discovery_daemon(
+    config: &ServerConfig,
+    fingerprint: Fingerprint,
+    clock: SharedClock,
+) -> Result<Discovery, Error> {
     let daemon = ServiceDaemon::new()?;
-    daemon.register(service_info)?;
+    select_interfaces(&daemon, config)?;
+    let browser = daemon.browse(config.service_type())?;
+
+    let hostname = config.server_hostname();
+    let instance_name = config.mdns_instance_name();
+    let mut advertiser = Advertiser {
+        daemon,
+        service_type: config.service_type().to_string(),
+        base_name: instance_name.clone(),
+        instance_name,
+        host_name: service_host_name(&hostname, None),
+        hostname,
+        fingerprint,
+        browser,
+        peers: HashMap::new(),
+        port: config.server_port(),
+        advertisement: Advertisement::new(fingerprint, false, false),
+        pending_codes: HashMap::new(),
+        clock,
+    };
+    advertiser.probe_peers(NAME_PROBE_DURATION);
+    advertiser.pick_name();
+    advertiser.register()?;
+    info!("Advertising as {}", advertiser.instance_name);
+
+    let (sender, receiver) = mpsc::channel::<DiscoveryEvent>();
+    let handle = thread::spawn(move || advertiser.run(receiver));
+
+    Ok(Discovery { sender, handle })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use remote_unlock_lib::config::Overrides;
+    use remote_unlock_lib::discovery::browse;
+
+    #[test]
+    fn test_renames_on_collision() {
+        let service_type = "_unlock-collide._tcp.local.";
+        let peer_ad = Advertisement::new(Fingerprint::of(b"peer key"), false, false);
+        let properties = peer_ad.txt_properties();
+        let peer_info = ServiceInfo::new(
+            service_type,
+            "collide-test",
+            "collide-peer.local.",
+            "",
+            9143,
+            &properties[..],
+        )
+        .unwrap()
+        .enable_addr_auto();
+        let peer = ServiceDaemon::new().unwrap();
+        peer.register(peer_info).unwrap();
+
+        let overrides = Overrides::new()
+            .set("service_type", service_type)
+            .set("instance_name", "collide-test")
+            .set("server_port", "9144");
+        let config = ServerConfig::load_from(&[], |_| None, &overrides).unwrap();
+        let discovery = start_discovery_daemon(
+            &config,
+            Fingerprint::of(b"our key"),
+            remote_unlock_lib::clock::SystemClock::shared(),
+        )
+        .unwrap();
+
+        let services = browse(service_type, Duration::from_secs(3)).unwrap();
+        discovery.shutdown().unwrap();
+        peer.shutdown().unwrap();
 
-    Ok(daemon)
+        let ours = services
+            .iter()
+            .find(|s| s.port == 9144)
+            .expect("advertised service not discovered");
+        assert_eq!(ours.instance_name, "collide-test (2)");
+    }
 }
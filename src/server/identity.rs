@@ -0,0 +1,63 @@
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+
+use p256::pkcs8::{DecodePrivateKey, EncodePrivateKey, EncodePublicKey};
+use rand::rngs::OsRng;
+use remote_unlock_lib::crypto::fingerprint::Fingerprint;
+use remote_unlock_lib::crypto::key::{PrivateKey, PublicKey};
+use remote_unlock_lib::prelude::*;
+use remote_unlock_lib::store::seal::check_identity_file;
+
+// Long-lived keypair identifying this server to enrolled clients
+pub struct ServerIdentity {
+    #[allow(dead_code)]
+    private_key: PrivateKey,
+    public_key: PublicKey,
+}
+
+impl ServerIdentity {
+    pub fn load_or_generate(config: &ServerConfig) -> Result<Self, Error> {
+        let path = config.identity_key_path();
+
+        let private_key = if path.exists() {
+            debug!("Loading server identity from {:?}", &path);
+            PrivateKey::read_pem_file(&path)?
+        } else {
+            info!("Generating new server identity at {:?}", &path);
+            let private_key = Self::generate()?;
+
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+
+            // Only the daemon user may read the identity key
+            let mut file = std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&path)?;
+            file.write_all(private_key.pem()?.as_bytes())?;
+            file.sync_all()?;
+
+            private_key
+        };
+        check_identity_file(&path)?;
+
+        let secret = p256::SecretKey::from_pkcs8_der(private_key.der()?.as_bytes())?;
+        let public_key = PublicKey::from_der(secret.public_key().to_public_key_der()?.as_bytes())?;
+
+        Ok(Self {
+            private_key,
+            public_key,
+        })
+    }
+
+    fn generate() -> Result<PrivateKey, Error> {
+        let secret = p256::SecretKey::random(&mut OsRng);
+        PrivateKey::from_der(secret.to_pkcs8_der()?.as_bytes())
+    }
+
+    pub fn fingerprint(&self) -> Result<Fingerprint, Error> {
+        self.public_key.fingerprint()
+    }
+}
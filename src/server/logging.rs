@@ -3,7 +3,7 @@ use systemd_journal_logger::{connected_to_journal, JournalLog};
 pub struct Logger {}
 
 impl Logger {
-    pub fn init(config: &Config) -> Result<(), Error> {
+    pub fn init(config: &ServerConfig) -> Result<(), Error> {
         let error_mapper = || Error::new(ErrorKind::Server, Some("Failed to initialize logger"));
         if connected_to_journal() {
             JournalLog::new()
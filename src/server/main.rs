@@ -1,52 +1,143 @@
-use remote_unlock_lib::enrollment_code::EnrollmentCode;
+use code_buffer::CodeEvent;
+use remote_unlock_lib::clock::SystemClock;
 use remote_unlock_lib::net::request::Request;
 use remote_unlock_lib::net::response::Response;
 use remote_unlock_lib::net::status::Status;
+use remote_unlock_lib::policy::Policies;
 use remote_unlock_lib::prelude::*;
+use sd_notify::NotifyState;
 use std::net::TcpListener;
+use std::path::PathBuf;
 use std::sync::mpsc;
 
+mod args;
 mod backends;
 mod code_buffer;
 mod context;
 mod discovery;
+mod identity;
 mod logging;
+mod peer;
+mod reload;
 mod router;
 mod routes;
+mod shutdown;
+mod signals;
 mod socket;
 mod state;
+mod systemd;
 
 fn main() -> Result<(), Error> {
-    let config = Config::new();
+    let overrides = args::overrides(std::env::args().skip(1))?;
+    let config = match ServerConfig::load(&overrides) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
 
     // TODO: Convert to crossbeam MPMC bounded channel
-    let (sock_sender, server_recv) = mpsc::channel::<EnrollmentCode>();
-
-    let sock_handle = socket::run_socket(sock_sender)?;
-    let discovery = discovery::start_discovery_daemon(&config)?;
+    let (sock_sender, server_recv) = mpsc::channel::<CodeEvent>();
+    let (consumed_sender, consumed_recv) = mpsc::channel::<u32>();
+
+    let clock = SystemClock::shared();
+    let identity = identity::ServerIdentity::load_or_generate(&config)?;
+    let store = remote_unlock_lib::store::open(&config)?;
+    let policies = Policies::load(&config)?;
+
+    let discovery =
+        discovery::start_discovery_daemon(&config, identity.fingerprint()?, clock.clone())?;
+
+    // Sockets systemd passed in are its to clean up
+    let activation = systemd::Activation::take()?;
+    let socket_file = activation
+        .unix
+        .is_none()
+        .then(|| PathBuf::from(config.socket_path()));
+    let control_listener = match activation.unix {
+        Some(listener) => listener,
+        None => socket::open_socket(&config)?,
+    };
+    let listener = match activation.tcp {
+        Some(listener) => listener,
+        None => TcpListener::bind((config.server_ip(), config.server_port()))?,
+    };
+
+    let waker = shutdown::ServerWaker::new(&listener)?;
+    let shutdown = shutdown::Shutdown::new(waker.clone(), &control_listener)?;
+    let (config_sender, config_recv) = mpsc::channel::<reload::Reload>();
+    let reloader = reload::Reloader::new(
+        overrides,
+        config.clone(),
+        config_sender,
+        discovery.sender(),
+        waker.clone(),
+    );
+    signals::handle_signals(reloader.clone(), shutdown.clone())?;
+    let heartbeat = systemd::Heartbeat::new();
+    let control_heartbeat = systemd::Heartbeat::new();
+    let sock_handle = socket::run_socket(
+        control_listener,
+        socket::ControlSocket {
+            config: config.clone(),
+            fingerprint: identity.fingerprint()?,
+            code_sender: sock_sender,
+            consumed_codes: consumed_recv,
+            discovery: discovery.sender(),
+            reloader,
+            shutdown: shutdown.clone(),
+            store: store.clone(),
+            clock: clock.clone(),
+            heartbeat: control_heartbeat.clone(),
+        },
+    )?;
 
     let mut context = context::ServerContext::builder()
         .config(&config)
+        .config_receiver(config_recv)
         .code_receiver(server_recv)
-        .state(state::State::new())
+        .consumed_codes(consumed_sender)
+        .discovery(discovery.sender())
+        .policies(policies)
+        .store(store)
+        .clock(clock)
         .build()?;
 
     context.init()?;
 
-    debug!("Starting server");
-    let listener: TcpListener = TcpListener::bind((config.server_ip(), config.server_port()))?;
-    info!(
-        "Server started on {}:{}",
-        config.server_ip(),
-        config.server_port()
-    );
-
-    for stream in listener.incoming() {
-        let stream = stream?;
-        trace!("New connection from: {}", stream.peer_addr()?);
+    let local_addr = listener.local_addr()?;
+    if local_addr.port() != config.server_port() {
+        // Pairing URIs and mDNS carry the configured port
+        warn!(
+            "Listening on port {} but advertising {}, set server_port to match",
+            local_addr.port(),
+            config.server_port()
+        );
+    }
+    let status = format!("Listening on {}", local_addr);
+    info!("Server started, {}", status.to_lowercase());
+    systemd::notify(&[NotifyState::Ready, NotifyState::Status(&status)]);
+    systemd::start_watchdog(shutdown.clone(), vec![heartbeat.clone(), control_heartbeat]);
+
+    while !shutdown.requested() {
+        heartbeat.beat();
+        if !systemd::wait_for_connection(&listener, systemd::LOOP_TICK)? {
+            continue;
+        }
+        let (stream, peer) = listener.accept()?;
+        if shutdown.requested() {
+            break;
+        }
+        context.process_reloads();
+        if waker.is_wakeup(peer) {
+            trace!("Woken up to apply a reload");
+            continue;
+        }
+
+        trace!("New connection from: {}", peer);
         stream.set_nonblocking(true)?;
-        context.replace_stream(stream);
-
+        context.replace_stream(stream, Some(peer.ip()));
         context.process_codes()?;
 
         let req = match Request::from_stream(context.stream()?) {
@@ -75,8 +166,16 @@ fn main() -> Result<(), Error> {
     }
 
     info!("Shutting down server");
+    drop(listener);
     sock_handle.join().unwrap();
-    discovery.shutdown().unwrap();
+    if let Some(socket_file) = socket_file {
+        debug!("Removing socket {}", socket_file.display());
+        if let Err(e) = std::fs::remove_file(&socket_file) {
+            warn!("Failed to remove socket: {}", e);
+        }
+    }
+    discovery.shutdown()?;
+    info!("Server stopped");
 
     Ok(())
 }
@@ -0,0 +1,127 @@
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+
+use remote_unlock_lib::prelude::*;
+
+// Process on the other end of a control connection, as seen by the kernel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Peer {
+    pub pid: i32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Peer {
+    pub fn of(stream: &UnixStream) -> Result<Peer, Error> {
+        let mut cred = libc::ucred {
+            pid: 0,
+            uid: 0,
+            gid: 0,
+        };
+        let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+        // SAFETY: the kernel writes at most `len` bytes into the local ucred
+        let result = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                (&mut cred as *mut libc::ucred).cast(),
+                &mut len,
+            )
+        };
+        if result != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        Ok(Peer {
+            pid: cred.pid,
+            uid: cred.uid,
+            gid: cred.gid,
+        })
+    }
+
+    // Supplementary groups aren't part of the credentials, read them from procfs
+    fn groups(&self) -> Vec<u32> {
+        let status = match std::fs::read_to_string(format!("/proc/{}/status", self.pid)) {
+            Ok(status) => status,
+            Err(_) => return Vec::new(),
+        };
+
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix("Groups:"))
+            .map(|groups| {
+                groups
+                    .split_whitespace()
+                    .filter_map(|gid| gid.parse().ok())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn in_group(&self, gid: u32) -> bool {
+        self.gid == gid || self.groups().contains(&gid)
+    }
+}
+
+// Decides who may use the control socket: the daemon's user and members of
+// the configured control group
+pub struct Authorizer {
+    uid: u32,
+    gid: Option<u32>,
+}
+
+impl Authorizer {
+    pub fn new(config: &ServerConfig) -> Authorizer {
+        // SAFETY: geteuid cannot fail
+        let uid = unsafe { libc::geteuid() };
+        Authorizer {
+            uid,
+            gid: config.control_gid(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn deny_all() -> Authorizer {
+        Authorizer {
+            uid: u32::MAX,
+            gid: None,
+        }
+    }
+
+    pub fn allows(&self, peer: &Peer) -> bool {
+        peer.uid == self.uid || self.gid.is_some_and(|gid| peer.in_group(gid))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peer_credentials() {
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        let peer = Peer::of(&ours).unwrap();
+        assert_eq!(peer, Peer::of(&theirs).unwrap());
+        assert_eq!(peer.pid, std::process::id() as i32);
+        assert_eq!(peer.uid, unsafe { libc::geteuid() });
+
+        let stranger = Peer {
+            pid: peer.pid,
+            uid: peer.uid + 1,
+            gid: u32::MAX,
+        };
+        let own_user = Authorizer {
+            uid: peer.uid,
+            gid: None,
+        };
+        assert!(own_user.allows(&peer));
+        assert!(!own_user.allows(&stranger));
+
+        let with_group = Authorizer {
+            uid: peer.uid,
+            gid: Some(u32::MAX),
+        };
+        assert!(with_group.allows(&stranger));
+    }
+}
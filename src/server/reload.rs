@@ -0,0 +1,141 @@
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use remote_unlock_lib::config::{ConfigError, Overrides};
+use remote_unlock_lib::policy::Policies;
+use remote_unlock_lib::prelude::*;
+use sd_notify::NotifyState;
+
+use crate::discovery::DiscoveryEvent;
+use crate::shutdown::ServerWaker;
+use crate::systemd;
+
+// How long a reload waits for the server loop, which may be busy with a request
+const APPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// A reloaded config for the server loop, which reports back once it is in use
+pub struct Reload {
+    pub config: ServerConfig,
+    pub applied: Sender<()>,
+}
+
+// Re-reads the configuration with the overrides the daemon started with.
+// The log level and mDNS advertisement change right away, the server loop
+// is woken to take the rest and a reload only succeeds once it has.
+#[derive(Clone)]
+pub struct Reloader {
+    overrides: Overrides,
+    load: fn(&Overrides) -> Result<ServerConfig, ConfigError>,
+    running: Arc<Mutex<ServerConfig>>,
+    config_sender: Sender<Reload>,
+    discovery: Sender<DiscoveryEvent>,
+    waker: ServerWaker,
+}
+
+impl Reloader {
+    pub fn new(
+        overrides: Overrides,
+        running: ServerConfig,
+        config_sender: Sender<Reload>,
+        discovery: Sender<DiscoveryEvent>,
+        waker: ServerWaker,
+    ) -> Self {
+        Reloader {
+            overrides,
+            load: ServerConfig::load,
+            running: Arc::new(Mutex::new(running)),
+            config_sender,
+            discovery,
+            waker,
+        }
+    }
+
+    pub fn reload(&self) -> Result<(), Error> {
+        info!("Reloading configuration");
+        match NotifyState::monotonic_usec_now() {
+            Ok(now) => systemd::notify(&[NotifyState::Reloading, now]),
+            Err(e) => warn!("Failed to read the monotonic clock: {}", e),
+        }
+
+        let result = self.apply();
+        systemd::notify(&[NotifyState::Ready]);
+        result
+    }
+
+    fn apply(&self) -> Result<(), Error> {
+        let mut config = (self.load)(&self.overrides).map_err(|e| {
+            error!("Configuration not reloaded: {}", e);
+            Error::from(e)
+        })?;
+        // The server loop loads them again, this only reports mistakes
+        Policies::load(&config).map_err(|e| {
+            error!("Configuration not reloaded: {}", e);
+            e
+        })?;
+
+        let mut running = self
+            .running
+            .lock()
+            .map_err(|_| Error::new(ErrorKind::Server, Some("Config lock poisoned")))?;
+        for key in config.keep_restart_settings(&running) {
+            warn!("Changing {} requires a restart, keeping the old value", key);
+        }
+
+        log::set_max_level(config.log_level());
+        if self
+            .discovery
+            .send(DiscoveryEvent::Reconfigure(Box::new(config.clone())))
+            .is_err()
+        {
+            warn!("Discovery daemon not running, advertisement not updated");
+        }
+        let (applied, applied_receiver) = mpsc::channel();
+        self.config_sender
+            .send(Reload {
+                config: config.clone(),
+                applied,
+            })
+            .map_err(|_| Error::new(ErrorKind::Server, Some("Server loop not running")))?;
+        *running = config;
+
+        self.waker.wake();
+        applied_receiver.recv_timeout(APPLY_TIMEOUT).map_err(|_| {
+            error!("Server loop busy, the reload applies once it is done");
+            Error::new(ErrorKind::Server, Some("Reload not applied yet"))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_reload_waits_for_server_loop() {
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        let waker = ServerWaker::new(&tcp).unwrap();
+        let (config_sender, config_receiver) = mpsc::channel::<Reload>();
+        let load = |overrides: &Overrides| ServerConfig::load_from(&[], |_| None, overrides);
+        let mut reloader = Reloader::new(
+            Overrides::new(),
+            load(&Overrides::new()).unwrap(),
+            config_sender,
+            mpsc::channel().0,
+            waker.clone(),
+        );
+        reloader.load = load;
+
+        let server_loop = thread::spawn(move || {
+            let (_, peer) = tcp.accept().unwrap();
+            assert!(waker.is_wakeup(peer));
+            let reload = config_receiver.recv().unwrap();
+            reload.applied.send(()).unwrap();
+        });
+
+        assert!(reloader.reload().is_ok());
+        server_loop.join().unwrap();
+    }
+}
@@ -1,10 +1,14 @@
 use std::net::TcpStream;
 
 use crate::context::ServerContext;
+use crate::routes::challenge::ChallengeRoute;
 use crate::routes::enroll::EnrollRoute;
+use crate::routes::lock::LockRoute;
 use crate::routes::not_found::NotFound;
 use crate::routes::route::Route;
+use crate::routes::status::StatusRoute;
 use crate::routes::unlock::UnlockRoute;
+use crate::routes::wake::WakeRoute;
 use crate::routes::Routes;
 use remote_unlock_lib::net::request::Request;
 use remote_unlock_lib::net::response::Response;
@@ -32,27 +36,43 @@ impl Router {
                 trace!("Routing to Unlock handler");
                 Routes::Unlock(UnlockRoute::new(context))
             }
+            request if LockRoute::<TcpStream>::match_route(request)? => {
+                trace!("Routing to Lock handler");
+                Routes::Lock(LockRoute::new(context))
+            }
+            request if WakeRoute::<TcpStream>::match_route(request)? => {
+                trace!("Routing to Wake handler");
+                Routes::Wake(WakeRoute::new(context))
+            }
+            request if StatusRoute::<TcpStream>::match_route(request)? => {
+                trace!("Routing to Status handler");
+                Routes::Status(StatusRoute::new(context))
+            }
+            request if ChallengeRoute::<TcpStream>::match_route(request)? => {
+                trace!("Routing to Challenge handler");
+                Routes::Challenge(ChallengeRoute::new(context))
+            }
             _ => {
                 trace!("Unknown route");
                 Routes::NotFound(NotFound::new(context))
             }
         };
 
-        let resp = route
+        let mut resp = route
             .run(request)
             .unwrap_or(Response::new(Status::InternalServerError));
 
+        // The nonce is committed and the action taken before the client is
+        // told it succeeded
+        if let Err(e) = route.post_run(&resp) {
+            error!("Failed to run post route: {}", e);
+            resp = Response::new(Status::InternalServerError);
+        }
+
         trace!("Writing response to stream");
         route.write_response(&resp)?;
 
         trace!("Response sent");
-        match route.post_run(&resp) {
-            Ok(_) => (),
-            Err(e) => {
-                error!("Failed to run post route: {}", e);
-            }
-        }
-
         Ok(())
     }
 }
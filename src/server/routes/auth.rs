@@ -0,0 +1,188 @@
+use std::io::Write;
+
+use base64::prelude::*;
+use remote_unlock_lib::client::{DENIAL_HEADER, SIGNATURE_HEADER};
+use remote_unlock_lib::denial::Denial;
+use remote_unlock_lib::net::request::Request;
+use remote_unlock_lib::net::response::Response;
+use remote_unlock_lib::net::status::Status;
+use remote_unlock_lib::policy::{self, Attempt, PolicyAction, ReplayDefense};
+use remote_unlock_lib::prelude::*;
+use remote_unlock_lib::unlock_request::{UnlockRequestBody, ACTION_LOCK, ACTION_WAKE};
+
+use crate::context::ServerContext;
+use crate::state::{Freshness, REFUSED_ACTION};
+
+pub enum Authorization {
+    // Leaves a pending authorization the route must commit or roll back
+    Granted(uuid::Uuid),
+    Denied(Status),
+    // Authentic, but the device's policy does not allow it right now
+    Refused(Denial),
+}
+
+// Forbidden, with the reason in a header the client maps to an error
+pub fn refusal(denial: Denial) -> Result<Response, Error> {
+    Ok(Response::builder()
+        .status(Status::Forbidden)
+        .add_header(DENIAL_HEADER, denial.code())?
+        .build())
+}
+
+// None for status requests, which policies do not restrict
+fn policy_action(action: Option<&str>) -> Option<PolicyAction> {
+    match action {
+        None => Some(PolicyAction::Unlock),
+        Some(ACTION_LOCK) => Some(PolicyAction::Lock),
+        Some(ACTION_WAKE) => Some(PolicyAction::Wake),
+        Some(_) => None,
+    }
+}
+
+// Applies the device's policy to a request that passed every other check
+fn evaluate<T: Write>(
+    context: &mut ServerContext<'_, T>,
+    id: &uuid::Uuid,
+    action: Option<&str>,
+) -> Result<(), Denial> {
+    let Some(action) = policy_action(action) else {
+        return Ok(());
+    };
+
+    // Locks count from when swaylock started, or from the server's own lock
+    // if that came later. One whose start can't be told counts as just made.
+    let now = context.state().now();
+    let since_lock = if context.locked() {
+        let locked_at = context.state().locked_at();
+        let started = context
+            .lock_started()
+            .map(|started| locked_at.map_or(started, |locked_at| started.max(locked_at)));
+        Some(started.map_or(0, |started| (now - started).max(0)))
+    } else {
+        None
+    };
+    // Without the history only a policy with no limit lets the unlock through
+    let recent_unlocks = context.state().recent_unlocks(id).unwrap_or_else(|e| {
+        error!("Cannot count recent unlocks of {}: {}", id, e);
+        usize::MAX
+    });
+    let attempt = Attempt {
+        action,
+        peer: context.peer(),
+        minute: policy::local_minute(now),
+        since_lock,
+        recent_unlocks,
+    };
+
+    context.policies().device(id).evaluate(&attempt)
+}
+
+// Checks the signature of a signed request for the given action, its
+// timestamp, the nonce or challenge the device's policy asks for, and
+// finally the rest of that policy
+pub fn authorize<T: Write>(
+    context: &mut ServerContext<'_, T>,
+    req: &Request,
+    action: Option<&str>,
+) -> Result<Authorization, Error> {
+    trace!("Parsing signed request");
+    let body_str = std::str::from_utf8(&req.body[..req.body_len])?;
+    let signed_req = match serde_json::from_str::<UnlockRequestBody>(body_str) {
+        Ok(signed_req) => signed_req,
+        Err(e) => {
+            error!("Error parsing signed request: {}", e);
+            return Ok(Authorization::Denied(Status::BadRequest));
+        }
+    };
+    debug!("Signed request: {:?}", &signed_req);
+
+    if signed_req.action() != action {
+        warn!(
+            "Signed request for another action: {:?}",
+            signed_req.action()
+        );
+        return Ok(Authorization::Denied(Status::BadRequest));
+    }
+
+    let signature_header = match req.get_header(SIGNATURE_HEADER) {
+        Some(s) => s,
+        None => {
+            warn!("Unsigned request received");
+            return Ok(Authorization::Denied(Status::BadRequest));
+        }
+    };
+
+    let id = uuid::Uuid::try_parse_ascii(signed_req.id())?;
+    let device = match context.state().store().device(&id)? {
+        Some(device) => device,
+        None => {
+            warn!("Public key not found for user: {}", &id);
+            return Ok(Authorization::Denied(Status::NotFound));
+        }
+    };
+
+    let pubkey = match device.public_key() {
+        Ok(pubkey) => pubkey,
+        Err(_) => {
+            error!("Error parsing stored public key");
+            return Ok(Authorization::Denied(Status::InternalServerError));
+        }
+    };
+
+    debug!("Public key loaded: {:?}", &pubkey.inner());
+
+    trace!("Decoding signature from Base64 Header");
+    let mut signature_bytes = [0u8; 1024];
+    let signature_length =
+        BASE64_STANDARD.decode_slice(signature_header.value.as_bytes(), &mut signature_bytes)?;
+    debug!(
+        "Signature received: {:?}",
+        &signature_bytes[..signature_length]
+    );
+
+    if !signed_req.verify(&signature_bytes[..signature_length], &pubkey)? {
+        warn!("Invalid signature from {}", &id);
+        return Ok(Authorization::Denied(Status::Forbidden));
+    }
+
+    match context
+        .state()
+        .validate_timestamp(&id, signed_req.timestamp())
+    {
+        Freshness::Fresh => (),
+        Freshness::Missing => return Ok(Authorization::Denied(Status::BadRequest)),
+        Freshness::Stale => return Ok(Authorization::Denied(Status::RequestTimeout)),
+    }
+
+    // Devices answer challenges or count nonces, never both
+    let replay = context.policies().device(&id).replay;
+    let valid_request = match (replay, signed_req.nonce(), signed_req.challenge()) {
+        (ReplayDefense::Counter, Some(nonce), None) => context.state().validate_nonce(&id, nonce),
+        (ReplayDefense::Challenge, None, Some(challenge)) => {
+            context.state().validate_challenge(&id, challenge)
+        }
+        _ => {
+            warn!(
+                "Request from {} does not use {:?} replay defense",
+                &id, replay
+            );
+            return Ok(Authorization::Denied(Status::BadRequest));
+        }
+    };
+
+    if !valid_request {
+        warn!("Request authorization failed");
+        return Ok(Authorization::Denied(Status::Forbidden));
+    }
+
+    // Only authentic, fresh requests learn what the policy allows
+    if let Err(denial) = evaluate(context, &id, action) {
+        warn!("Request from {} denied by policy: {}", &id, denial);
+        // Spent like a granted request, or a captured copy could be sent
+        // again once the policy allows it
+        context.state().commit_authorization(id, REFUSED_ACTION)?;
+        return Ok(Authorization::Refused(denial));
+    }
+
+    Ok(Authorization::Granted(id))
+}
@@ -0,0 +1,82 @@
+use remote_unlock_lib::challenge_response::ChallengeResponse;
+use remote_unlock_lib::net::method::Method;
+use remote_unlock_lib::net::status::Status;
+use remote_unlock_lib::policy::ReplayDefense;
+use remote_unlock_lib::{
+    net::{request::Request, response::Response},
+    prelude::*,
+};
+use std::io::Write;
+use std::net::TcpStream;
+
+use crate::context::ServerContext;
+
+use super::route::Route;
+
+// Hands a device in challenge mode the value its next signed request must carry
+pub struct ChallengeRoute<'a, 'c: 'a, T: Write = TcpStream> {
+    context: &'a mut ServerContext<'c, T>,
+}
+
+fn query_id(request: &Request) -> Option<uuid::Uuid> {
+    let (_, query) = request.path()?.split_once('?')?;
+    query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("id="))
+        .and_then(|id| uuid::Uuid::try_parse(id).ok())
+}
+
+impl<'a, 'c: 'a, T: Write> Route<'a, 'c, T> for ChallengeRoute<'a, 'c, T> {
+    const PATH: &'static str = "/challenge";
+    const METHOD: Method = Method::GET;
+
+    fn new(context: &'a mut ServerContext<'c, T>) -> Self {
+        Self { context }
+    }
+
+    fn context(&mut self) -> &mut ServerContext<'c, T> {
+        self.context
+    }
+
+    fn post_run(&mut self, _response: &Response) -> Result<(), Error> {
+        Ok(())
+    }
+
+    // The device id comes in the query string
+    fn match_route(request: &Request) -> Result<bool, Error> {
+        let path = request.path().unwrap_or("");
+        let path = path.split_once('?').map_or(path, |(path, _)| path);
+
+        Ok(path == Self::PATH && request.method == Some(Self::METHOD))
+    }
+
+    fn run(&mut self, req: &Request) -> Result<Response, Error> {
+        let builder = Response::builder();
+
+        let Some(id) = query_id(req) else {
+            warn!("Challenge requested without a device id");
+            return Ok(builder.status(Status::BadRequest).build());
+        };
+        // Unknown devices and those using nonces look the same, so the route
+        // tells nobody which ids are enrolled
+        if self.context.state().store().device(&id)?.is_none() {
+            warn!("Challenge requested for unknown device: {}", &id);
+            return Ok(builder.status(Status::NotFound).build());
+        }
+        if self.context.policies().device(&id).replay != ReplayDefense::Challenge {
+            warn!("Challenge requested by {}, which uses nonces", &id);
+            return Ok(builder.status(Status::NotFound).build());
+        }
+
+        let Some((challenge, expires)) = self.context.state().issue_challenge(id) else {
+            return Ok(builder.status(Status::TooManyRequests).build());
+        };
+        let mut resp = builder
+            .status(Status::Ok)
+            .add_header("Content-Type", "application/json")?
+            .build();
+        serde_json::to_writer(&mut resp, &ChallengeResponse::new(&challenge, expires))?;
+
+        Ok(resp)
+    }
+}
@@ -1,6 +1,7 @@
 use std::{io::Write, net::TcpStream};
 
 use crate::context::ServerContext;
+use remote_unlock_lib::store::{Device, Transaction};
 use remote_unlock_lib::{
     enroll_request::EnrollmentRequest,
     enroll_response,
@@ -49,13 +50,24 @@ impl<'a, 'c: 'a, T: Write> Route<'a, 'c, T> for EnrollRoute<'a, 'c, T> {
                 trace!("Enrollment ID: {}", &id);
 
                 if self.context.state().code_buffer().verify(code) {
+                    self.context.code_consumed(*code);
+
                     let pem = enroll_req.pubkey_pem();
                     let pubkey =
                         remote_unlock_lib::crypto::key::PublicKey::from_pem(pem.as_bytes())?;
-                    let mut path = self.context.config().keys_dir().join(&id);
-
-                    path.set_extension("pub");
-                    pubkey.save_to_pem_file(path.as_path())?;
+                    let now = self.context.state().now();
+                    let device = Device::new(
+                        *enroll_response.id(),
+                        pubkey.pem()?.as_str()?.to_string(),
+                        now,
+                    );
+                    self.context.state().store().commit(
+                        Transaction::new().put_device(device).audit(
+                            *enroll_response.id(),
+                            "enroll",
+                            now,
+                        ),
+                    )?;
 
                     trace!("Public key saved for user: {}", &id);
 
@@ -84,25 +96,31 @@ impl<'a, 'c: 'a, T: Write> Route<'a, 'c, T> for EnrollRoute<'a, 'c, T> {
 mod tests {
     use std::sync::mpsc;
 
-    use crate::{context, state::State};
+    use std::sync::Arc;
+
+    use crate::{code_buffer::CodeEvent, context};
 
     use super::*;
+    use remote_unlock_lib::clock::{ManualClock, SystemClock};
+    use remote_unlock_lib::config::Overrides;
     use remote_unlock_lib::enrollment_code::EnrollmentCode;
-    const PUBKEY_PEM: &'static str = include_str!("../../../test_data/pem_test.pub");
+    use remote_unlock_lib::store::{MemoryStore, Store};
+    const PUBKEY_PEM: &str = include_str!("../../../test_data/pem_test.pub");
 
     #[test]
     fn test_post() {
-        let config = Config::new();
+        let config = ServerConfig::load_from(&[], |_| None, &Overrides::new()).unwrap();
+        let store = Arc::new(MemoryStore::new());
         let mock_server = ByteArray::<{ Config::MAX_PACKET_SIZE * 2 }>::new();
         let mut context: ServerContext<ByteArray<{ Config::MAX_PACKET_SIZE * 2 }>> =
             context::ServerContext::builder()
                 .config(&config)
-                .code_receiver(mpsc::channel::<EnrollmentCode>().1)
-                .state(State::new())
+                .code_receiver(mpsc::channel::<CodeEvent>().1)
+                .store(store.clone())
                 .stream(mock_server)
                 .build()
                 .unwrap();
-        let enrollment_code = EnrollmentCode::new();
+        let enrollment_code = EnrollmentCode::new(&SystemClock);
 
         let code_num = enrollment_code.code();
         context
@@ -127,5 +145,42 @@ mod tests {
         let resp = Response::<{ 64 * 2 }>::from_stream(&mut context.stream().unwrap()).unwrap();
 
         assert!(resp.status == remote_unlock_lib::net::status::Status::Ok);
+        assert_eq!(store.devices().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_expired_code() {
+        let config = ServerConfig::load_from(&[], |_| None, &Overrides::new()).unwrap();
+        let store = Arc::new(MemoryStore::new());
+        let clock = Arc::new(ManualClock::new(1_000_000));
+        let mut context: ServerContext<ByteArray<{ Config::MAX_PACKET_SIZE * 2 }>> =
+            context::ServerContext::builder()
+                .config(&config)
+                .code_receiver(mpsc::channel::<CodeEvent>().1)
+                .store(store.clone())
+                .clock(clock.clone())
+                .stream(ByteArray::new())
+                .build()
+                .unwrap();
+        let enrollment_code = EnrollmentCode::new(clock.as_ref());
+        context
+            .state()
+            .code_buffer()
+            .insert(enrollment_code)
+            .unwrap();
+
+        let pubkey = ByteArray::try_from(PUBKEY_PEM.as_bytes()).unwrap();
+        let mut req = Request::new();
+        serde_json::to_writer(
+            &mut req,
+            &EnrollmentRequest::new(enrollment_code.code(), pubkey),
+        )
+        .unwrap();
+
+        clock.set(enrollment_code.expires() + 1);
+        let resp = EnrollRoute::new(&mut context).run(&req).unwrap();
+
+        assert!(resp.status == remote_unlock_lib::net::status::Status::Forbidden);
+        assert!(store.devices().unwrap().is_empty());
     }
 }
@@ -0,0 +1,128 @@
+use remote_unlock_lib::net::method::Method;
+use remote_unlock_lib::net::status::Status;
+use remote_unlock_lib::unlock_request::ACTION_LOCK;
+use remote_unlock_lib::{
+    net::{request::Request, response::Response},
+    prelude::*,
+};
+use std::io::Write;
+use std::net::TcpStream;
+
+use crate::context::ServerContext;
+
+use super::auth::{self, Authorization};
+use super::route::Route;
+
+pub struct LockRoute<'a, 'c: 'a, T: Write = TcpStream> {
+    context: &'a mut ServerContext<'c, T>,
+    id: Option<uuid::Uuid>,
+}
+
+impl<'a, 'c: 'a, T: Write> Route<'a, 'c, T> for LockRoute<'a, 'c, T> {
+    const PATH: &'static str = "/lock";
+    const METHOD: Method = Method::POST;
+
+    fn new(context: &'a mut ServerContext<'c, T>) -> Self {
+        Self { context, id: None }
+    }
+
+    fn context(&mut self) -> &mut ServerContext<'c, T> {
+        self.context
+    }
+
+    fn post_run(&mut self, response: &Response) -> Result<(), Error> {
+        let Some(id) = self.id else {
+            return Ok(());
+        };
+        if response.status() == Status::Ok {
+            self.context.state().commit_authorization(id, ACTION_LOCK)?;
+            self.context.lock()?;
+        } else {
+            self.context.state().rollback_authorization(id);
+        }
+
+        Ok(())
+    }
+
+    fn run(&mut self, req: &Request) -> Result<Response, Error> {
+        let builder = Response::builder();
+
+        match auth::authorize(self.context, req, Some(ACTION_LOCK))? {
+            Authorization::Granted(id) => {
+                self.id = Some(id);
+                Ok(builder.status(Status::Ok).build())
+            }
+            Authorization::Denied(status) => Ok(builder.status(status).build()),
+            Authorization::Refused(denial) => auth::refusal(denial),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{mpsc, Arc};
+
+    use p256::ecdsa::SigningKey;
+    use p256::pkcs8::{EncodePublicKey, LineEnding};
+    use remote_unlock_lib::client::{self, Action, DENIAL_HEADER};
+    use remote_unlock_lib::config::Overrides;
+    use remote_unlock_lib::denial::Denial;
+    use remote_unlock_lib::policy::Policies;
+    use remote_unlock_lib::store::{Device, MemoryStore, Store, Transaction};
+
+    use super::*;
+    use crate::code_buffer::CodeEvent;
+    use crate::state::REFUSED_ACTION;
+
+    #[test]
+    fn test_refused_by_policy() {
+        let config = ServerConfig::load_from(&[], |_| None, &Overrides::new()).unwrap();
+        let store = Arc::new(MemoryStore::new());
+        let signing_key = SigningKey::from_bytes(&[7; 32].into()).unwrap();
+        let id = uuid::Uuid::new_v4();
+        let pem = signing_key
+            .verifying_key()
+            .to_public_key_pem(LineEnding::LF)
+            .unwrap();
+        store
+            .commit(Transaction::new().put_device(Device::new(id, pem, 1_700_000_000)))
+            .unwrap();
+
+        let policies = Policies::parse(&format!(
+            "[devices.{}]\nactions = [\"unlock\"]",
+            id.as_simple()
+        ))
+        .unwrap();
+        let mut context: ServerContext<ByteArray<{ Config::MAX_PACKET_SIZE * 2 }>> =
+            ServerContext::builder()
+                .config(&config)
+                .code_receiver(mpsc::channel::<CodeEvent>().1)
+                .store(store.clone())
+                .policies(policies)
+                .stream(ByteArray::new())
+                .build()
+                .unwrap();
+
+        let signed = client::signed_request(&signing_key, &id, 1, None, Action::Lock).unwrap();
+        let mut raw = ByteArray::<{ Config::MAX_PACKET_SIZE }>::new();
+        signed.write_into(&mut raw).unwrap();
+        let req = Request::parse(raw.as_bytes()).unwrap();
+
+        let mut route = LockRoute::new(&mut context);
+        let resp = route.run(&req).unwrap();
+        assert_eq!(resp.status(), Status::Forbidden);
+        let denial = resp.get_header(DENIAL_HEADER).unwrap();
+        assert_eq!(
+            Denial::from_code(denial.value.as_str().unwrap()),
+            Some(Denial::ActionNotAllowed)
+        );
+
+        // The nonce was used up and the refusal audited
+        route.post_run(&resp).unwrap();
+        assert_eq!(store.nonce(&id).unwrap(), Some(2));
+        assert!(!context.state().validate_nonce(&id, 1));
+        let audit = store.audit_log().unwrap();
+        assert_eq!(audit.len(), 1);
+        assert_eq!(audit[0].action, REFUSED_ACTION);
+    }
+}
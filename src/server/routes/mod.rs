@@ -3,39 +3,60 @@ use remote_unlock_lib::net::request::Request;
 use remote_unlock_lib::net::response::Response;
 use remote_unlock_lib::prelude::*;
 
+pub mod auth;
+pub mod challenge;
 pub mod enroll;
+pub mod lock;
 pub mod not_found;
 pub mod route;
+pub mod status;
 pub mod unlock;
+pub mod wake;
 
 pub enum Routes<'a, 'c: 'a> {
+    Challenge(challenge::ChallengeRoute<'a, 'c>),
     Enroll(enroll::EnrollRoute<'a, 'c>),
+    Lock(lock::LockRoute<'a, 'c>),
     NotFound(not_found::NotFound<'a, 'c>),
+    Status(status::StatusRoute<'a, 'c>),
     Unlock(unlock::UnlockRoute<'a, 'c>),
+    Wake(wake::WakeRoute<'a, 'c>),
 }
 
 impl<'a, 'c: 'a> Routes<'a, 'c> {
     pub fn run(&mut self, request: &Request) -> Result<Response, Error> {
         match self {
+            Routes::Challenge(route) => route.run(request),
             Routes::Enroll(route) => route.run(request),
+            Routes::Lock(route) => route.run(request),
             Routes::NotFound(route) => route.run(request),
+            Routes::Status(route) => route.run(request),
             Routes::Unlock(route) => route.run(request),
+            Routes::Wake(route) => route.run(request),
         }
     }
 
     pub fn write_response(&mut self, response: &Response) -> Result<(), Error> {
         match self {
+            Routes::Challenge(route) => route.write_response(response),
             Routes::Enroll(route) => route.write_response(response),
+            Routes::Lock(route) => route.write_response(response),
             Routes::NotFound(route) => route.write_response(response),
+            Routes::Status(route) => route.write_response(response),
             Routes::Unlock(route) => route.write_response(response),
+            Routes::Wake(route) => route.write_response(response),
         }
     }
 
     pub fn post_run(&mut self, response: &Response) -> Result<(), Error> {
         match self {
+            Routes::Challenge(route) => route.post_run(response),
             Routes::Enroll(route) => route.post_run(response),
+            Routes::Lock(route) => route.post_run(response),
             Routes::NotFound(route) => route.post_run(response),
+            Routes::Status(route) => route.post_run(response),
             Routes::Unlock(route) => route.post_run(response),
+            Routes::Wake(route) => route.post_run(response),
         }
     }
 }
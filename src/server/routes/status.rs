@@ -0,0 +1,69 @@
+use remote_unlock_lib::net::method::Method;
+use remote_unlock_lib::net::status::Status;
+use remote_unlock_lib::status_response::StatusResponse;
+use remote_unlock_lib::unlock_request::ACTION_STATUS;
+use remote_unlock_lib::{
+    net::{request::Request, response::Response},
+    prelude::*,
+};
+use std::io::Write;
+use std::net::TcpStream;
+
+use crate::context::ServerContext;
+
+use super::auth::{self, Authorization};
+use super::route::Route;
+
+pub struct StatusRoute<'a, 'c: 'a, T: Write = TcpStream> {
+    context: &'a mut ServerContext<'c, T>,
+    id: Option<uuid::Uuid>,
+}
+
+impl<'a, 'c: 'a, T: Write> Route<'a, 'c, T> for StatusRoute<'a, 'c, T> {
+    const PATH: &'static str = "/status";
+    const METHOD: Method = Method::POST;
+
+    fn new(context: &'a mut ServerContext<'c, T>) -> Self {
+        Self { context, id: None }
+    }
+
+    fn context(&mut self) -> &mut ServerContext<'c, T> {
+        self.context
+    }
+
+    fn post_run(&mut self, response: &Response) -> Result<(), Error> {
+        let Some(id) = self.id else {
+            return Ok(());
+        };
+        if response.status() == Status::Ok {
+            self.context
+                .state()
+                .commit_authorization(id, ACTION_STATUS)?;
+        } else {
+            self.context.state().rollback_authorization(id);
+        }
+
+        Ok(())
+    }
+
+    fn run(&mut self, req: &Request) -> Result<Response, Error> {
+        let builder = Response::builder();
+
+        match auth::authorize(self.context, req, Some(ACTION_STATUS))? {
+            Authorization::Granted(id) => {
+                self.id = Some(id);
+
+                let status = StatusResponse::new(self.context.locked());
+                let mut resp = builder
+                    .status(Status::Ok)
+                    .add_header("Content-Type", "application/json")?
+                    .build();
+                serde_json::to_writer(&mut resp, &status)?;
+
+                Ok(resp)
+            }
+            Authorization::Denied(status) => Ok(builder.status(status).build()),
+            Authorization::Refused(denial) => auth::refusal(denial),
+        }
+    }
+}
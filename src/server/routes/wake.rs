@@ -0,0 +1,60 @@
+use remote_unlock_lib::net::method::Method;
+use remote_unlock_lib::net::status::Status;
+use remote_unlock_lib::unlock_request::ACTION_WAKE;
+use remote_unlock_lib::{
+    net::{request::Request, response::Response},
+    prelude::*,
+};
+use std::io::Write;
+use std::net::TcpStream;
+
+use crate::context::ServerContext;
+
+use super::auth::{self, Authorization};
+use super::route::Route;
+
+// Turns the screen on without touching the lock
+pub struct WakeRoute<'a, 'c: 'a, T: Write = TcpStream> {
+    context: &'a mut ServerContext<'c, T>,
+    id: Option<uuid::Uuid>,
+}
+
+impl<'a, 'c: 'a, T: Write> Route<'a, 'c, T> for WakeRoute<'a, 'c, T> {
+    const PATH: &'static str = "/wake";
+    const METHOD: Method = Method::POST;
+
+    fn new(context: &'a mut ServerContext<'c, T>) -> Self {
+        Self { context, id: None }
+    }
+
+    fn context(&mut self) -> &mut ServerContext<'c, T> {
+        self.context
+    }
+
+    fn post_run(&mut self, response: &Response) -> Result<(), Error> {
+        let Some(id) = self.id else {
+            return Ok(());
+        };
+        if response.status() == Status::Ok {
+            self.context.state().commit_authorization(id, ACTION_WAKE)?;
+            self.context.wake()?;
+        } else {
+            self.context.state().rollback_authorization(id);
+        }
+
+        Ok(())
+    }
+
+    fn run(&mut self, req: &Request) -> Result<Response, Error> {
+        let builder = Response::builder();
+
+        match auth::authorize(self.context, req, Some(ACTION_WAKE))? {
+            Authorization::Granted(id) => {
+                self.id = Some(id);
+                Ok(builder.status(Status::Ok).build())
+            }
+            Authorization::Denied(status) => Ok(builder.status(status).build()),
+            Authorization::Refused(denial) => auth::refusal(denial),
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::os::unix::net::{self, UnixListener, UnixStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use remote_unlock_lib::prelude::*;
+use sd_notify::NotifyState;
+
+use crate::systemd;
+
+// Gets the server loop out of accept by connecting to its listener. The
+// loop drops these connections unread, it can tell them apart by address.
+#[derive(Clone)]
+pub struct ServerWaker {
+    addr: SocketAddr,
+    // Local addresses of wake up connections not yet accepted
+    pending: Arc<Mutex<HashSet<SocketAddr>>>,
+}
+
+impl ServerWaker {
+    // The address comes from the listener, which systemd may have bound
+    pub fn new(server: &TcpListener) -> Result<Self, Error> {
+        let mut addr = server.local_addr()?;
+        match addr.ip() {
+            IpAddr::V4(ip) if ip.is_unspecified() => addr.set_ip(Ipv4Addr::LOCALHOST.into()),
+            IpAddr::V6(ip) if ip.is_unspecified() => addr.set_ip(Ipv6Addr::LOCALHOST.into()),
+            _ => {}
+        }
+
+        Ok(ServerWaker {
+            addr,
+            pending: Arc::new(Mutex::new(HashSet::new())),
+        })
+    }
+
+    pub fn wake(&self) {
+        // Held across the connect so the loop can't check the peer first
+        let Ok(mut pending) = self.pending.lock() else {
+            return;
+        };
+        match TcpStream::connect(self.addr).and_then(|stream| stream.local_addr()) {
+            Ok(local) => {
+                pending.insert(local);
+            }
+            Err(e) => debug!("Server listener not woken: {}", e),
+        }
+    }
+
+    pub fn is_wakeup(&self, peer: SocketAddr) -> bool {
+        self.pending
+            .lock()
+            .map(|mut pending| pending.remove(&peer))
+            .unwrap_or(false)
+    }
+}
+
+// Shared by the listeners, signal handler and terminate command. Both
+// listeners wait in accept for up to a tick, triggering connects to each
+// so they stop right away.
+#[derive(Clone)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+    server: ServerWaker,
+    socket_addr: net::SocketAddr,
+}
+
+impl Shutdown {
+    pub fn new(server: ServerWaker, socket: &UnixListener) -> Result<Self, Error> {
+        Ok(Shutdown {
+            requested: Arc::new(AtomicBool::new(false)),
+            server,
+            socket_addr: socket.local_addr()?,
+        })
+    }
+
+    pub fn requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    pub fn trigger(&self) {
+        if self.requested.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        info!("Shutdown requested");
+        systemd::notify(&[NotifyState::Stopping, NotifyState::Status("Shutting down")]);
+        self.server.wake();
+        if let Err(e) = UnixStream::connect_addr(&self.socket_addr) {
+            debug!("Socket listener not woken: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trigger_wakes_listeners() {
+        let tcp = TcpListener::bind("0.0.0.0:0").unwrap();
+        let socket_path =
+            std::env::temp_dir().join(format!("shutdown-{}.sock", rand::random::<u32>()));
+        let unix = UnixListener::bind(&socket_path).unwrap();
+
+        let shutdown = Shutdown::new(ServerWaker::new(&tcp).unwrap(), &unix).unwrap();
+        assert!(!shutdown.requested());
+
+        shutdown.trigger();
+        assert!(shutdown.requested());
+        assert!(tcp.accept().is_ok());
+        assert!(unix.accept().is_ok());
+
+        std::fs::remove_file(socket_path).unwrap();
+    }
+
+    #[test]
+    fn test_wakeups_are_recognized() {
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        let waker = ServerWaker::new(&tcp).unwrap();
+
+        let client = TcpStream::connect(tcp.local_addr().unwrap()).unwrap();
+        waker.wake();
+        let (_, client_peer) = tcp.accept().unwrap();
+        let (_, wake_peer) = tcp.accept().unwrap();
+
+        assert_eq!(client_peer, client.local_addr().unwrap());
+        assert!(!waker.is_wakeup(client_peer));
+        assert!(waker.is_wakeup(wake_peer));
+        assert!(!waker.is_wakeup(wake_peer));
+    }
+}
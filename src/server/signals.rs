@@ -0,0 +1,25 @@
+use std::thread;
+
+use remote_unlock_lib::prelude::*;
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+
+use crate::reload::Reloader;
+use crate::shutdown::Shutdown;
+
+pub fn handle_signals(reloader: Reloader, shutdown: Shutdown) -> Result<(), Error> {
+    let mut signals = Signals::new([SIGHUP, SIGINT, SIGTERM])?;
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            match signal {
+                // Failures are logged, the daemon keeps its current config
+                SIGHUP => {
+                    let _ = reloader.reload();
+                }
+                _ => shutdown.trigger(),
+            }
+        }
+    });
+
+    Ok(())
+}
@@ -1,80 +1,444 @@
 use remote_unlock_lib::{
+    crypto::fingerprint::Fingerprint,
     enrollment_code::EnrollmentCode,
-    net::{request::Request, response::Response, status::Status},
+    messages::{
+        read_message, write_frame, DaemonStatus, Device, MessageData, MessageType, PendingCode,
+        Reply, ReplyData, VersionRange,
+    },
+    pairing_uri::PairingUri,
     prelude::*,
 };
-use std::os::unix::fs::PermissionsExt;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use crate::code_buffer::CodeEvent;
+use crate::discovery::DiscoveryEvent;
+use crate::peer::{Authorizer, Peer};
+use crate::reload::Reloader;
+use crate::shutdown::Shutdown;
+use crate::systemd::{self, Heartbeat};
+use remote_unlock_lib::clock::SharedClock;
+use remote_unlock_lib::store::{SharedStore, Store};
+use std::os::unix::fs::{chown, PermissionsExt};
 use std::{
-    os::unix::net::UnixListener,
-    sync::mpsc::Sender,
+    os::unix::net::{UnixListener, UnixStream},
+    sync::mpsc::{Receiver, Sender},
     thread::{self, JoinHandle},
 };
 
-// Opens a Unix socket and returns its listener.
-fn open_socket(sock_path: &str) -> std::io::Result<UnixListener> {
-    let path = std::path::Path::new(sock_path);
+// Connections are handled one at a time, a client that stops talking is dropped
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Opens a Unix socket with the configured mode and group and returns its listener.
+pub fn open_socket(config: &ServerConfig) -> std::io::Result<UnixListener> {
+    let path = std::path::Path::new(config.socket_path());
     if path.exists() {
         std::fs::remove_file(path)?;
     }
-    UnixListener::bind(sock_path)
+    let listener = UnixListener::bind(path)?;
+
+    // Connecting needs write permission, the peer check below is the real gate
+    if let Some(gid) = config.control_gid() {
+        chown(path, None, Some(gid))?;
+    }
+    let mut perms = std::fs::metadata(path)?.permissions();
+    perms.set_mode(config.socket_mode());
+    std::fs::set_permissions(path, perms)?;
+
+    Ok(listener)
+}
+
+// Host clients should dial, falling back to the mDNS name when bound to all interfaces
+fn pairing_host(config: &ServerConfig) -> String {
+    match config.server_ip().parse::<IpAddr>() {
+        Ok(ip) if !ip.is_unspecified() => ip.to_string(),
+        _ => format!("{}.local", config.server_hostname()),
+    }
+}
+
+fn enrolled_devices(store: &dyn Store) -> Result<Vec<Device>, Error> {
+    let devices = store.devices()?;
+    Ok(devices
+        .into_iter()
+        .map(|device| Device {
+            id: device.id.as_simple().to_string(),
+            enrolled: device.enrolled,
+        })
+        .collect())
+}
+
+// Everything the control socket needs from the rest of the daemon
+pub struct ControlSocket {
+    pub config: ServerConfig,
+    pub fingerprint: Fingerprint,
+    pub code_sender: Sender<CodeEvent>,
+    pub consumed_codes: Receiver<u32>,
+    pub discovery: Sender<DiscoveryEvent>,
+    pub reloader: Reloader,
+    pub shutdown: Shutdown,
+    pub store: SharedStore,
+    pub clock: SharedClock,
+    pub heartbeat: Heartbeat,
+}
+
+struct ControlServer {
+    control: ControlSocket,
+    authorizer: Authorizer,
+    // Codes issued through the socket that are neither used nor expired
+    pending_codes: Vec<EnrollmentCode>,
+    started: Instant,
+}
+
+impl ControlServer {
+    fn notify_discovery(&self, event: DiscoveryEvent) {
+        if self.control.discovery.send(event).is_err() {
+            warn!("Discovery daemon not running, enrollment status not advertised");
+        }
+    }
+
+    fn prune_codes(&mut self) {
+        let consumed: Vec<u32> = self.control.consumed_codes.try_iter().collect();
+        let clock = self.control.clock.as_ref();
+        self.pending_codes
+            .retain(|code| !code.expired(clock) && !consumed.contains(&code.code()));
+    }
+
+    fn begin_enroll(&mut self) -> Result<ReplyData, Error> {
+        let config = &self.control.config;
+        let code = EnrollmentCode::new(self.control.clock.as_ref());
+        let pairing_uri = PairingUri::new(
+            &pairing_host(config),
+            config.server_port(),
+            &code,
+            self.control.fingerprint,
+        )?;
+
+        self.control
+            .code_sender
+            .send(CodeEvent::Issued(code))
+            .map_err(|_| Error::new(ErrorKind::Server, Some("Server loop not running")))?;
+        self.notify_discovery(DiscoveryEvent::CodeIssued(code));
+        self.pending_codes.push(code);
+
+        Ok(ReplyData::BeginEnroll(Box::new(pairing_uri)))
+    }
+
+    fn cancel_code(&mut self, code: u32) -> Result<ReplyData, Error> {
+        let pending = self.pending_codes.iter().any(|c| c.code() == code);
+        if pending {
+            self.pending_codes.retain(|c| c.code() != code);
+            self.control
+                .code_sender
+                .send(CodeEvent::Cancelled(code))
+                .map_err(|_| Error::new(ErrorKind::Server, Some("Server loop not running")))?;
+            self.notify_discovery(DiscoveryEvent::CodeCancelled(code));
+            info!("Cancelled enrollment code {}", code);
+        }
+
+        Ok(ReplyData::CancelCode(pending))
+    }
+
+    fn status(&self) -> Result<ReplyData, Error> {
+        Ok(ReplyData::Status(DaemonStatus {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            control_version: Config::CONTROL_PROTOCOL_VERSION,
+            fingerprint: self.control.fingerprint.to_string(),
+            uptime_secs: self.started.elapsed().as_secs(),
+            pending_codes: self.pending_codes.len(),
+            enrolled_devices: enrolled_devices(self.control.store.as_ref())?.len(),
+        }))
+    }
+
+    fn dispatch(&mut self, data: MessageData) -> Result<ReplyData, Error> {
+        self.prune_codes();
+
+        match data {
+            MessageData::Hello(_) => Err(Error::new(
+                ErrorKind::UnexpectedMessage,
+                Some("Version already negotiated"),
+            )),
+            MessageData::BeginEnroll(()) => self.begin_enroll(),
+            MessageData::ListCodes(()) => Ok(ReplyData::ListCodes(
+                self.pending_codes
+                    .iter()
+                    .map(|code| PendingCode {
+                        code: code.code(),
+                        expires: code.expires(),
+                    })
+                    .collect(),
+            )),
+            MessageData::CancelCode(code) => self.cancel_code(code),
+            MessageData::Status(()) => self.status(),
+            MessageData::Devices(()) => Ok(ReplyData::Devices(enrolled_devices(
+                self.control.store.as_ref(),
+            )?)),
+            MessageData::Reload(()) => self.control.reloader.reload().map(ReplyData::Reload),
+            MessageData::Terminate(()) => Ok(ReplyData::Terminate(())),
+        }
+    }
+
+    // A connection opens with a hello, then carries any number of requests.
+    // Nothing is read from peers that aren't allowed in.
+    fn handle(&mut self, stream: &mut UnixStream, peer: &Peer) -> Result<(), Error> {
+        if !self.authorizer.allows(peer) {
+            warn!(
+                "Control access denied to pid {} uid {} gid {}",
+                peer.pid, peer.uid, peer.gid
+            );
+            return write_frame(
+                stream,
+                &Reply::error(MessageType::Hello, "Permission denied"),
+            );
+        }
+        let hello = match read_message(stream)? {
+            Some(message) => message,
+            None => return Ok(()),
+        };
+        let version = match hello.data() {
+            MessageData::Hello(range) => VersionRange::supported().negotiate(range),
+            _ => {
+                let reply = Reply::error(hello.message_type(), "Expected a hello first");
+                return write_frame(stream, &reply);
+            }
+        };
+        let Some(version) = version else {
+            let reply = Reply::error(
+                MessageType::Hello,
+                ErrorKind::UnsupportedVersion.to_string(),
+            );
+            return write_frame(stream, &reply);
+        };
+        write_frame(
+            stream,
+            &Reply::new(MessageType::Hello, ReplyData::Hello(version)),
+        )?;
+        trace!("Control client speaks version {}", version);
+
+        while let Some(message) = read_message(stream)? {
+            let message_type = message.message_type();
+            let reply = match self.dispatch(message.into_data()) {
+                Ok(data) => {
+                    info!(
+                        "Control {:?} by pid {} uid {}",
+                        message_type, peer.pid, peer.uid
+                    );
+                    Reply::new(message_type, data)
+                }
+                Err(e) => {
+                    warn!(
+                        "Control {:?} by pid {} uid {} failed: {}",
+                        message_type, peer.pid, peer.uid, e
+                    );
+                    Reply::error(message_type, e)
+                }
+            };
+            write_frame(stream, &reply)?;
+
+            if message_type == MessageType::Terminate {
+                self.control.shutdown.trigger();
+                break;
+            }
+        }
+
+        Ok(())
+    }
 }
 
-pub fn run_socket(code_channel_sender: Sender<EnrollmentCode>) -> Result<JoinHandle<()>, Error> {
+pub fn run_socket(sock: UnixListener, control: ControlSocket) -> Result<JoinHandle<()>, Error> {
     let handle = thread::spawn(move || {
-        let config = Config::new();
-        let sock: UnixListener = open_socket(config.socket_path()).unwrap();
-
-        // Change permissions of socket to 777
-        // TODO: Review if this is secure or wanted behaviour
-        let mut perms = std::fs::metadata(config.socket_path())
-            .unwrap()
-            .permissions();
-        perms.set_mode(0o777);
-        std::fs::set_permissions(config.socket_path(), perms).unwrap();
-
-        for stream in sock.incoming() {
-            let mut stream = stream.unwrap();
-            let sock_req = Request::<{ 64 * 2 }>::from_stream(&mut stream).unwrap();
-            stream.shutdown(std::net::Shutdown::Read).unwrap();
-
-            let path_array = sock_req.path.unwrap();
-            let path_str = match path_array.as_str() {
-                Ok(s) => s,
-                Err(_) => {
-                    stream.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut server = ControlServer {
+            authorizer: Authorizer::new(&control.config),
+            control,
+            pending_codes: Vec::new(),
+            started: Instant::now(),
+        };
+        while !server.control.shutdown.requested() {
+            server.control.heartbeat.beat();
+            match systemd::wait_for_connection(&sock, systemd::LOOP_TICK) {
+                Ok(true) => (),
+                Ok(false) => continue,
+                Err(e) => {
+                    error!("Error waiting for control connections: {}", e);
+                    thread::sleep(systemd::LOOP_TICK);
+                    continue;
+                }
+            }
+            let mut stream = match sock.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    error!("Error accepting control connection: {}", e);
                     continue;
                 }
             };
+            if server.control.shutdown.requested() {
+                break;
+            }
+            if let Err(e) = stream
+                .set_read_timeout(Some(CONTROL_TIMEOUT))
+                .and_then(|_| stream.set_write_timeout(Some(CONTROL_TIMEOUT)))
+            {
+                error!("Failed to set control connection timeouts: {}", e);
+                continue;
+            }
 
-            let method_array = sock_req.method;
-            let method_str = match method_array {
-                Some(s) => s.as_str(),
-                None => {
-                    stream.shutdown(std::net::Shutdown::Write).unwrap();
+            let peer = match Peer::of(&stream) {
+                Ok(peer) => peer,
+                Err(e) => {
+                    error!("Failed to read control peer credentials: {}", e);
                     continue;
                 }
             };
-
-            if path_str == "/begin_enroll" && method_str == "POST" {
-                let code: EnrollmentCode = EnrollmentCode::new();
-
-                let mut resp = Response::<{ 64 * 2 }>::new(Status::Ok);
-                serde_json::to_writer(&mut resp, &code).unwrap();
-                match resp.add_header("Content-Type", "application/json") {
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!("Error adding header, {}", e);
-                        stream.shutdown(std::net::Shutdown::Write).unwrap();
-                        continue;
-                    }
-                };
-
-                resp.to_writer(&mut stream).unwrap();
-                code_channel_sender.send(code).unwrap();
+            if let Err(e) = server.handle(&mut stream, &peer) {
+                warn!("Control connection from pid {} failed: {}", peer.pid, e);
+            }
+            if server.control.shutdown.requested() {
+                break;
             }
-            stream.shutdown(std::net::Shutdown::Write).unwrap();
         }
     });
 
     Ok(handle)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shutdown::ServerWaker;
+    use remote_unlock_lib::clock::ManualClock;
+    use remote_unlock_lib::config::Overrides;
+    use remote_unlock_lib::messages::{read_reply, Message};
+    use remote_unlock_lib::store::MemoryStore;
+    use std::sync::mpsc;
+    use std::sync::Arc;
+
+    fn control_server(clock: Arc<ManualClock>) -> (ControlServer, Receiver<CodeEvent>) {
+        let config = ServerConfig::load_from(
+            &[],
+            |_| None,
+            &Overrides::new().set("hostname", "control-test"),
+        )
+        .unwrap();
+        let (code_sender, code_receiver) = mpsc::channel();
+        let (discovery, _) = mpsc::channel();
+        let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let waker = ServerWaker::new(&tcp).unwrap();
+        let reloader = Reloader::new(
+            Overrides::new(),
+            config.clone(),
+            mpsc::channel().0,
+            discovery.clone(),
+            waker.clone(),
+        );
+        let path = std::env::temp_dir().join(format!("control-{}.sock", rand::random::<u32>()));
+        let unix = UnixListener::bind(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        let control = ControlSocket {
+            shutdown: Shutdown::new(waker, &unix).unwrap(),
+            config,
+            fingerprint: Fingerprint::of(b"control test"),
+            code_sender,
+            consumed_codes: mpsc::channel().1,
+            discovery,
+            reloader,
+            store: Arc::new(MemoryStore::new()),
+            clock,
+            heartbeat: Heartbeat::new(),
+        };
+        let server = ControlServer {
+            authorizer: Authorizer::new(&control.config),
+            control,
+            pending_codes: Vec::new(),
+            started: Instant::now(),
+        };
+
+        (server, code_receiver)
+    }
+
+    fn call(stream: &mut UnixStream, data: MessageData) -> Result<ReplyData, Error> {
+        let message_type = data.message_type();
+        write_frame(stream, &Message::new(data)).unwrap();
+        read_reply(stream)?.into_data(message_type)
+    }
+
+    #[test]
+    fn test_control_session() {
+        let (mut server, code_receiver) = control_server(Arc::new(ManualClock::new(1_000_000)));
+        let (mut client, mut stream) = UnixStream::pair().unwrap();
+        let handle = thread::spawn(move || {
+            let peer = Peer::of(&stream).unwrap();
+            server.handle(&mut stream, &peer)
+        });
+
+        let hello = call(&mut client, MessageData::Hello(VersionRange::supported())).unwrap();
+        assert_eq!(hello, ReplyData::Hello(Config::CONTROL_PROTOCOL_VERSION));
+
+        let code = match call(&mut client, MessageData::BeginEnroll(())).unwrap() {
+            ReplyData::BeginEnroll(uri) => uri.code(),
+            reply => panic!("unexpected reply {:?}", reply),
+        };
+        assert!(matches!(code_receiver.try_recv(), Ok(CodeEvent::Issued(c)) if c.code() == code));
+
+        match call(&mut client, MessageData::ListCodes(())).unwrap() {
+            ReplyData::ListCodes(codes) => assert_eq!(codes[0].code, code),
+            reply => panic!("unexpected reply {:?}", reply),
+        }
+
+        let cancelled = call(&mut client, MessageData::CancelCode(code)).unwrap();
+        assert_eq!(cancelled, ReplyData::CancelCode(true));
+        assert!(matches!(code_receiver.try_recv(), Ok(CodeEvent::Cancelled(c)) if c == code));
+        let again = call(&mut client, MessageData::CancelCode(code)).unwrap();
+        assert_eq!(again, ReplyData::CancelCode(false));
+
+        assert!(call(&mut client, MessageData::Hello(VersionRange::supported())).is_err());
+
+        drop(client);
+        assert!(handle.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_prunes_expired_codes() {
+        let clock = Arc::new(ManualClock::new(1_000_000));
+        let (mut server, _code_receiver) = control_server(clock.clone());
+
+        server.begin_enroll().unwrap();
+        let expires = server.pending_codes[0].expires();
+
+        clock.set(expires);
+        server.prune_codes();
+        assert_eq!(server.pending_codes.len(), 1);
+
+        clock.advance(1);
+        server.prune_codes();
+        assert!(server.pending_codes.is_empty());
+    }
+
+    #[test]
+    fn test_rejects_unsupported_version() {
+        let (mut server, _) = control_server(Arc::new(ManualClock::new(1_000_000)));
+        let (mut client, mut stream) = UnixStream::pair().unwrap();
+        let handle = thread::spawn(move || {
+            let peer = Peer::of(&stream).unwrap();
+            server.handle(&mut stream, &peer)
+        });
+
+        let future = VersionRange {
+            min: Config::CONTROL_PROTOCOL_VERSION + 1,
+            max: Config::CONTROL_PROTOCOL_VERSION + 1,
+        };
+        assert!(call(&mut client, MessageData::Hello(future)).is_err());
+        assert!(handle.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_denies_before_reading() {
+        let (mut server, _) = control_server(Arc::new(ManualClock::new(1_000_000)));
+        server.authorizer = Authorizer::deny_all();
+        let (mut client, mut stream) = UnixStream::pair().unwrap();
+
+        // The client never sends its hello
+        let peer = Peer::of(&stream).unwrap();
+        assert!(server.handle(&mut stream, &peer).is_ok());
+        let reply = read_reply(&mut client).unwrap();
+        assert!(reply.into_data(MessageType::Hello).is_err());
+    }
+}
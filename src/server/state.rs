@@ -1,149 +1,652 @@
-use std::{collections::HashMap, thread};
+use std::collections::{HashMap, VecDeque};
+
+use base64::prelude::*;
 
 use crate::code_buffer::CodeBuffer;
+use remote_unlock_lib::clock::SharedClock;
 use remote_unlock_lib::prelude::*;
+use remote_unlock_lib::store::{SharedStore, Transaction};
+
+// Which nonces are accepted besides ones past the newest
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NoncePolicy {
+    // Older nonces within this distance of the newest are accepted once
+    pub window: u32,
+    // Requests further ahead of the next expected nonce are refused
+    pub max_jump: Option<u64>,
+}
+
+impl NoncePolicy {
+    pub fn from_config(config: &ServerConfig) -> NoncePolicy {
+        NoncePolicy {
+            window: config.nonce_window(),
+            max_jump: config.max_nonce_jump(),
+        }
+    }
+}
+
+// How far the signed timestamp of a request may be from the server's time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimestampPolicy {
+    // Requests without a timestamp are refused
+    pub required: bool,
+    // Seconds a request stays valid after it was signed
+    pub window: u32,
+    // Seconds the client's clock may be ahead or behind, on top of the window
+    pub skew: u32,
+}
+
+impl TimestampPolicy {
+    pub fn from_config(config: &ServerConfig) -> TimestampPolicy {
+        TimestampPolicy {
+            required: config.require_timestamp(),
+            window: config.timestamp_window(),
+            skew: config.clock_skew(),
+        }
+    }
+
+    fn check(&self, ts: Option<i64>, now: i64) -> Freshness {
+        let Some(ts) = ts else {
+            return match self.required {
+                true => Freshness::Missing,
+                false => Freshness::Fresh,
+            };
+        };
+
+        let skew = i64::from(self.skew);
+        let oldest = now.saturating_sub(i64::from(self.window) + skew);
+        if ts > now.saturating_add(skew) || ts < oldest {
+            Freshness::Stale
+        } else {
+            Freshness::Fresh
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Freshness {
+    Fresh,
+    Missing,
+    Stale,
+}
+
+// Sliding replay window of one device. `next` is one past the newest nonce
+// accepted and bit i of `seen` is set once `next - 1 - i` has been used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct NonceWindow {
+    next: u128,
+    seen: u128,
+}
+
+impl NonceWindow {
+    // Only `next` is stored, so after a restart the whole window counts as used
+    fn loaded(next: u128) -> NonceWindow {
+        NonceWindow {
+            next,
+            seen: u128::MAX,
+        }
+    }
+
+    fn check(&self, nonce: u128, policy: &NoncePolicy) -> Result<(), &'static str> {
+        if nonce == u128::MAX {
+            return Err("nonce space exhausted");
+        }
+        if nonce >= self.next {
+            return match policy.max_jump {
+                Some(max_jump) if nonce - self.next > u128::from(max_jump) => {
+                    Err("nonce jumps too far ahead")
+                }
+                _ => Ok(()),
+            };
+        }
+
+        let age = self.next - 1 - nonce;
+        if age >= u128::from(policy.window) {
+            Err("nonce outside the replay window")
+        } else if self.seen & (1 << age) != 0 {
+            Err("nonce already used")
+        } else {
+            Ok(())
+        }
+    }
+
+    fn record(self, nonce: u128) -> NonceWindow {
+        if nonce < self.next {
+            return NonceWindow {
+                next: self.next,
+                seen: self.seen | 1 << (self.next - 1 - nonce),
+            };
+        }
+
+        let shift = nonce + 1 - self.next;
+        let seen = match u32::try_from(shift) {
+            Ok(shift) if shift < u128::BITS => self.seen << shift,
+            _ => 0,
+        };
+        NonceWindow {
+            next: nonce + 1,
+            seen: seen | 1,
+        }
+    }
+}
+
+// Seconds a challenge from `/challenge` can be answered in
+const CHALLENGE_LIFETIME: i64 = 30;
+// Unexpired challenges a device may have, `/challenge` is unauthenticated so
+// a full set refuses new ones rather than dropping those already handed out
+const MAX_CHALLENGES: usize = 4;
+
+struct Challenge {
+    value: String,
+    expires: i64,
+}
+
+// Window `max_unlocks_per_hour` counts over
+const UNLOCK_HISTORY: i64 = 60 * 60;
+// Audit action of the unlocks it counts
+pub const UNLOCK_ACTION: &str = "unlock";
+// Audit action of requests a device policy refused
+pub const REFUSED_ACTION: &str = "refused";
+
+// How an accepted request showed it was not a replay
+enum Pending {
+    Nonce(u128),
+    Challenge,
+}
 
 pub struct State {
-    // Map of strictly increasing nonces for each client
-    nonces: HashMap<uuid::Uuid, u128>,
+    // Replay windows of the devices that sent requests since startup
+    nonces: HashMap<uuid::Uuid, NonceWindow>,
+    nonce_policy: NoncePolicy,
+    timestamp_policy: TimestampPolicy,
+    clock: SharedClock,
+    // Outstanding challenges of each device in challenge mode, oldest first
+    challenges: HashMap<uuid::Uuid, Vec<Challenge>>,
     code_buffer: CodeBuffer,
-    pending_nonce_updates: HashMap<uuid::Uuid, u128>,
+    // Requests accepted but still being handled
+    pending: HashMap<uuid::Uuid, Pending>,
+    // When the server last locked the screen, cleared by its next unlock
+    locked_at: Option<i64>,
+    // Recent unlock times of each device, oldest first. Read from the audit
+    // log the first time a device is looked at, so a restart keeps them
+    unlocks: HashMap<uuid::Uuid, VecDeque<i64>>,
+    store: SharedStore,
 }
 
 impl State {
-    pub fn new() -> State {
+    pub fn new(store: SharedStore, clock: SharedClock) -> State {
         State {
             nonces: HashMap::new(),
-            code_buffer: CodeBuffer::new(),
-            pending_nonce_updates: HashMap::new(),
+            nonce_policy: NoncePolicy::default(),
+            timestamp_policy: TimestampPolicy::from_config(&ServerConfig::default()),
+            clock: clock.clone(),
+            challenges: HashMap::new(),
+            code_buffer: CodeBuffer::new(clock),
+            pending: HashMap::new(),
+            locked_at: None,
+            unlocks: HashMap::new(),
+            store,
         }
     }
 
+    pub fn store(&self) -> &SharedStore {
+        &self.store
+    }
+
+    pub fn set_nonce_policy(&mut self, policy: NoncePolicy) {
+        self.nonce_policy = policy;
+    }
+
+    pub fn set_timestamp_policy(&mut self, policy: TimestampPolicy) {
+        self.timestamp_policy = policy;
+    }
+
+    // Checked before the nonce or challenge so a stale request uses neither up
+    pub fn validate_timestamp(&self, id: &uuid::Uuid, ts: Option<i64>) -> Freshness {
+        let now = self.clock.now();
+        let freshness = self.timestamp_policy.check(ts, now);
+        match freshness {
+            Freshness::Fresh => (),
+            Freshness::Missing => warn!("Request from {} has no timestamp", &id),
+            Freshness::Stale => warn!(
+                "Stale request from {}: signed at {:?}, now {}",
+                &id, ts, now
+            ),
+        }
+
+        freshness
+    }
+
     #[allow(dead_code)]
     pub fn get_nonce(&self, id: &uuid::Uuid) -> Option<&u128> {
-        self.nonces.get(id)
+        self.nonces.get(id).map(|window| &window.next)
     }
 
     #[allow(dead_code)]
     pub fn increment_nonce(&mut self, id: uuid::Uuid) {
-        let nonce = self.nonces.entry(id).or_insert(0);
-        *nonce += 1;
+        let window = self.nonces.entry(id).or_insert(NonceWindow::loaded(0));
+        *window = window.record(window.next);
     }
 
-    fn save_nonce_to_file(id: uuid::Uuid, nonce: u128) -> Result<(), Error> {
-        let mut id_buf: [u8; 32] = [0; 32];
-        let path = Config::new()
-            .nonce_dir()
-            .join(id.as_simple().encode_lower(&mut id_buf));
-        debug!("Writing nonce to file: {:?}", &path);
+    fn nonce_window(&self, id: &uuid::Uuid) -> Result<NonceWindow, Error> {
+        if let Some(window) = self.nonces.get(id) {
+            return Ok(*window);
+        }
+
+        debug!("No nonce found for id: {}, fetching from storage", &id);
+        Ok(NonceWindow::loaded(self.store.nonce(id)?.unwrap_or(0)))
+    }
 
-        let mut file = match std::fs::File::create(&path) {
-            Ok(file) => file,
+    pub fn validate_nonce(&mut self, id: &uuid::Uuid, nonce: u128) -> bool {
+        trace!("Checking nonce for id: {}", &id);
+        let window = match self.nonce_window(id) {
+            Ok(window) => window,
             Err(e) => {
-                error!("Error creating nonce file: {}", e);
-                return Err(e.into());
+                error!("Refusing requests from {} until fixed: {}", &id, e);
+                return false;
             }
         };
 
-        let nonce_str = nonce.to_string();
-        let mut bytes = ByteArray::<{ Config::BUFFER_SIZE }>::try_from(nonce_str.as_bytes())?;
-
-        std::io::copy(&mut bytes, &mut file)?;
+        if let Err(reason) = window.check(nonce, &self.nonce_policy) {
+            warn!("Invalid nonce for id: {}: {}", &id, reason);
+            return false;
+        }
 
-        Ok(())
+        trace!("Synchronizing nonce for id: {}", &id);
+        self.pending.insert(*id, Pending::Nonce(nonce));
+        true
     }
 
-    fn save_nonce_to_file_async(id: uuid::Uuid, nonce: u128) {
-        thread::spawn(move || Self::save_nonce_to_file(id, nonce));
-    }
+    // None while the device already has `MAX_CHALLENGES` unexpired ones
+    pub fn issue_challenge(&mut self, id: uuid::Uuid) -> Option<(String, i64)> {
+        let now = self.clock.now();
+        let issued = self.challenges.entry(id).or_default();
+        issued.retain(|challenge| challenge.expires >= now);
+        if issued.len() >= MAX_CHALLENGES {
+            warn!("Too many outstanding challenges for id: {}", &id);
+            return None;
+        }
 
-    pub fn update_nonce(&mut self, id: uuid::Uuid, nonce: u128) {
-        Self::save_nonce_to_file_async(id, nonce);
+        let value = BASE64_STANDARD.encode(rand::random::<[u8; 32]>());
+        let expires = now + CHALLENGE_LIFETIME;
+        debug!("Issuing challenge for id: {}", &id);
+        issued.push(Challenge {
+            value: value.clone(),
+            expires,
+        });
 
-        self.nonces.insert(id, nonce);
+        Some((value, expires))
     }
 
-    pub fn try_load_nonce_from_file(
-        &mut self,
-        config: &Config,
-        id: &uuid::Uuid,
-    ) -> Result<u128, Error> {
-        let mut id_buf: [u8; 32] = [0; 32];
-        let path = config
-            .nonce_dir()
-            .join(id.as_simple().encode_lower(&mut id_buf));
-        debug!("Loading nonce from file: {:?}", &path);
+    // A challenge is used up by the request answering it, others the device
+    // holds stay valid
+    pub fn validate_challenge(&mut self, id: &uuid::Uuid, challenge: &str) -> bool {
+        trace!("Checking challenge for id: {}", &id);
+        let Some(issued) = self.challenges.get_mut(id) else {
+            warn!("No challenge issued for id: {}", &id);
+            return false;
+        };
+        let Some(position) = issued.iter().position(|issued| issued.value == challenge) else {
+            warn!("Wrong challenge for id: {}", &id);
+            return false;
+        };
 
-        let mut file = std::fs::File::open(&path);
-        let mut bytes = ByteArray::<{ Config::BUFFER_SIZE }>::new();
+        let issued = issued.remove(position);
+        if self.clock.now() > issued.expires {
+            warn!("Expired challenge for id: {}", &id);
+            return false;
+        }
 
-        match file {
-            Ok(ref mut file) => {
-                std::io::copy(file, &mut bytes)?;
-                let nonce_str = bytes.as_str().unwrap_or("0");
-                debug!("Loaded nonce: {}", nonce_str);
+        self.pending.insert(*id, Pending::Challenge);
+        true
+    }
+
+    // Must succeed before the request is acted on
+    pub fn commit_authorization(&mut self, id: uuid::Uuid, action: &str) -> Result<(), Error> {
+        trace!("Committing authorization for id: {}", &id);
+        // Loaded before the commit adds this unlock to the audit log
+        if action == UNLOCK_ACTION {
+            self.unlock_history(&id)?;
+        }
 
-                let nonce = nonce_str.parse::<u128>().unwrap_or(0);
-                Ok(nonce)
+        let now = self.clock.now();
+        match self.pending.remove(&id) {
+            Some(Pending::Nonce(nonce)) => {
+                // The window only moves once the store has the new nonce
+                let window = self.nonce_window(&id)?.record(nonce);
+                self.store.commit(
+                    Transaction::new()
+                        .put_nonce(id, window.next)
+                        .audit(id, action, now),
+                )?;
+                self.nonces.insert(id, window);
             }
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-                debug!("Nonce file not found: {:?}", &path);
-                Err(e.into())
+            Some(Pending::Challenge) => {
+                self.store
+                    .commit(Transaction::new().audit(id, action, now))?;
             }
-            Err(e) => Err(e.into()),
+            None => return Ok(()),
         }
+
+        if action == UNLOCK_ACTION {
+            self.unlock_history(&id)?.push_back(now);
+        }
+        Ok(())
     }
 
-    pub fn validate_nonce(&mut self, id: &uuid::Uuid, nonce: u128) -> bool {
-        trace!("Checking nonce for id: {}", &id);
-        let current_nonce = match self.nonces.get(id) {
-            Some(last_nonce) => last_nonce.to_owned(),
-            None => {
-                debug!("No nonce found for id: {}, fetching from file", &id);
-                let loaded_nonce = self.try_load_nonce_from_file(&Config::new(), id);
+    pub fn rollback_authorization(&mut self, id: uuid::Uuid) {
+        trace!("Rolling back authorization for id: {}", &id);
+        self.pending.remove(&id);
+    }
 
-                loaded_nonce.unwrap_or(0)
-            }
-        };
+    pub fn now(&self) -> i64 {
+        self.clock.now()
+    }
+
+    pub fn record_lock(&mut self) {
+        self.locked_at = Some(self.clock.now());
+    }
 
-        let result = nonce >= current_nonce;
+    pub fn record_unlock(&mut self) {
+        self.locked_at = None;
+    }
 
-        if !result {
-            warn!("Invalid nonce for id: {}", &id);
+    // None when the lock was not made by this server
+    pub fn locked_at(&self) -> Option<i64> {
+        self.locked_at
+    }
+
+    fn unlock_history(&mut self, id: &uuid::Uuid) -> Result<&mut VecDeque<i64>, Error> {
+        if !self.unlocks.contains_key(id) {
+            let cutoff = self.clock.now() - UNLOCK_HISTORY;
+            let unlocks = self
+                .store
+                .audit_log()?
+                .into_iter()
+                .filter(|event| event.device == *id && event.action == UNLOCK_ACTION)
+                .map(|event| event.time)
+                .filter(|&time| time > cutoff)
+                .collect();
+            self.unlocks.insert(*id, unlocks);
         }
 
-        trace!("Synchronizing nonce for id: {}", &id);
+        Ok(self.unlocks.entry(*id).or_default())
+    }
 
-        if result {
-            return self
-                .queue_nonce_update(*id, nonce + 1)
-                .map(|_| true)
-                .unwrap_or(false);
+    // Unlocks committed for the device within the last hour
+    pub fn recent_unlocks(&mut self, id: &uuid::Uuid) -> Result<usize, Error> {
+        let cutoff = self.clock.now() - UNLOCK_HISTORY;
+        let unlocks = self.unlock_history(id)?;
+        while unlocks.front().is_some_and(|&unlocked| unlocked <= cutoff) {
+            unlocks.pop_front();
         }
 
-        result
+        Ok(unlocks.len())
+    }
+
+    pub fn code_buffer(&mut self) -> &mut CodeBuffer {
+        &mut self.code_buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use remote_unlock_lib::clock::{ManualClock, SystemClock};
+    use remote_unlock_lib::store::MemoryStore;
+
+    #[test]
+    fn test_nonce_persistence() {
+        let store: SharedStore = Arc::new(MemoryStore::new());
+        let mut state = State::new(store.clone(), SystemClock::shared());
+        let id = uuid::Uuid::new_v4();
+
+        assert!(state.validate_nonce(&id, 5));
+        assert_eq!(store.nonce(&id).unwrap(), None);
+        state.commit_authorization(id, "unlock").unwrap();
+        assert_eq!(store.nonce(&id).unwrap(), Some(6));
+        assert_eq!(store.audit_log().unwrap()[0].action, "unlock");
+
+        // A restarted daemon picks the nonce up from storage
+        let mut restarted = State::new(store, SystemClock::shared());
+        assert!(!restarted.validate_nonce(&id, 5));
+        assert!(restarted.validate_nonce(&id, 6));
     }
 
-    pub fn commit_nonce_update(&mut self, id: uuid::Uuid) {
-        trace!("Committing nonce update for id: {}", &id);
-        if let Some(nonce) = self.pending_nonce_updates.remove(&id) {
-            self.update_nonce(id, nonce);
+    fn accept(state: &mut State, id: uuid::Uuid, nonce: u128) -> bool {
+        let valid = state.validate_nonce(&id, nonce);
+        if valid {
+            state.commit_authorization(id, "unlock").unwrap();
         }
+        valid
     }
 
-    pub fn rollback_nonce_update(&mut self, id: uuid::Uuid) {
-        trace!("Rolling back nonce update for id: {}", &id);
-        self.pending_nonce_updates.remove(&id);
+    #[test]
+    fn test_strict_nonces() {
+        let mut state = State::new(Arc::new(MemoryStore::new()), SystemClock::shared());
+        let id = uuid::Uuid::new_v4();
+
+        assert!(accept(&mut state, id, 3));
+        assert!(!accept(&mut state, id, 3));
+        assert!(!accept(&mut state, id, 2));
+        assert!(accept(&mut state, id, 1_000_000));
+        assert!(!accept(&mut state, id, u128::MAX));
     }
 
-    fn queue_nonce_update(&mut self, id: uuid::Uuid, nonce: u128) -> Result<(), Error> {
-        trace!("Queueing nonce update for id: {}", &id);
-        self.pending_nonce_updates.insert(id, nonce);
+    #[test]
+    fn test_sliding_window() {
+        let store: SharedStore = Arc::new(MemoryStore::new());
+        let mut state = State::new(store.clone(), SystemClock::shared());
+        state.set_nonce_policy(NoncePolicy {
+            window: 4,
+            max_jump: None,
+        });
+        let id = uuid::Uuid::new_v4();
 
-        Ok(())
+        // Two requests in flight arriving out of order
+        assert!(accept(&mut state, id, 1));
+        assert!(accept(&mut state, id, 0));
+        assert!(!accept(&mut state, id, 0));
+        assert!(!accept(&mut state, id, 1));
+
+        assert!(accept(&mut state, id, 5));
+        assert!(accept(&mut state, id, 3));
+        assert!(accept(&mut state, id, 2));
+        assert!(!accept(&mut state, id, 1));
+        assert!(!accept(&mut state, id, 3));
+        assert_eq!(store.nonce(&id).unwrap(), Some(6));
+
+        // A rejected request leaves the window untouched
+        assert!(state.validate_nonce(&id, 4));
+        state.rollback_authorization(id);
+        assert!(accept(&mut state, id, 4));
+
+        // Nonces skipped before a restart can no longer be used
+        assert!(accept(&mut state, id, 8));
+        let mut restarted = State::new(store, SystemClock::shared());
+        restarted.set_nonce_policy(NoncePolicy {
+            window: 4,
+            max_jump: None,
+        });
+        assert!(!accept(&mut restarted, id, 7));
+        assert!(accept(&mut restarted, id, 9));
     }
 
-    pub fn code_buffer(&mut self) -> &mut CodeBuffer {
-        &mut self.code_buffer
+    #[test]
+    fn test_max_jump() {
+        let mut state = State::new(Arc::new(MemoryStore::new()), SystemClock::shared());
+        state.set_nonce_policy(NoncePolicy {
+            window: 0,
+            max_jump: Some(10),
+        });
+        let id = uuid::Uuid::new_v4();
+
+        assert!(!accept(&mut state, id, 11));
+        assert!(accept(&mut state, id, 10));
+        assert!(accept(&mut state, id, 21));
+        assert!(!accept(&mut state, id, 33));
+        assert!(!accept(&mut state, id, u128::MAX - 1));
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let clock = Arc::new(ManualClock::new(1_000_000));
+        let mut state = State::new(Arc::new(MemoryStore::new()), clock.clone());
+        state.set_timestamp_policy(TimestampPolicy {
+            required: false,
+            window: 60,
+            skew: 5,
+        });
+        let id = uuid::Uuid::new_v4();
+
+        assert_eq!(state.validate_timestamp(&id, None), Freshness::Fresh);
+        assert_eq!(
+            state.validate_timestamp(&id, Some(1_000_000)),
+            Freshness::Fresh
+        );
+        // Skew applies on both sides of the window
+        assert_eq!(
+            state.validate_timestamp(&id, Some(1_000_005)),
+            Freshness::Fresh
+        );
+        assert_eq!(
+            state.validate_timestamp(&id, Some(1_000_006)),
+            Freshness::Stale
+        );
+        assert_eq!(
+            state.validate_timestamp(&id, Some(1_000_000 - 65)),
+            Freshness::Fresh
+        );
+        assert_eq!(
+            state.validate_timestamp(&id, Some(1_000_000 - 66)),
+            Freshness::Stale
+        );
+
+        // A captured request goes stale as time passes
+        clock.advance(66);
+        assert_eq!(
+            state.validate_timestamp(&id, Some(1_000_000)),
+            Freshness::Stale
+        );
+
+        state.set_timestamp_policy(TimestampPolicy {
+            required: true,
+            window: 60,
+            skew: 5,
+        });
+        assert_eq!(state.validate_timestamp(&id, None), Freshness::Missing);
+        assert_eq!(
+            state.validate_timestamp(&id, Some(i64::MIN)),
+            Freshness::Stale
+        );
+        assert_eq!(
+            state.validate_timestamp(&id, Some(i64::MAX)),
+            Freshness::Stale
+        );
+    }
+
+    #[test]
+    fn test_challenges() {
+        let store: SharedStore = Arc::new(MemoryStore::new());
+        let mut state = State::new(store.clone(), SystemClock::shared());
+        let id = uuid::Uuid::new_v4();
+
+        let (challenge, _) = state.issue_challenge(id).unwrap();
+        assert_eq!(
+            challenge.len(),
+            remote_unlock_lib::challenge_response::CHALLENGE_LEN
+        );
+        assert!(!state.validate_challenge(&uuid::Uuid::new_v4(), &challenge));
+        assert!(state.validate_challenge(&id, &challenge));
+        state.commit_authorization(id, "unlock").unwrap();
+        assert_eq!(store.audit_log().unwrap()[0].action, "unlock");
+        assert_eq!(store.nonce(&id).unwrap(), None);
+
+        // Used up once answered, a wrong answer spends nothing
+        assert!(!state.validate_challenge(&id, &challenge));
+        let (challenge, _) = state.issue_challenge(id).unwrap();
+        assert!(!state.validate_challenge(&id, "wrong"));
+        assert!(state.validate_challenge(&id, &challenge));
+
+        // Issuing more does not drop those handed out before
+        let (first, _) = state.issue_challenge(id).unwrap();
+        let (second, _) = state.issue_challenge(id).unwrap();
+        assert_ne!(first, second);
+        assert!(state.validate_challenge(&id, &first));
+        assert!(state.validate_challenge(&id, &second));
+    }
+
+    #[test]
+    fn test_challenge_limit() {
+        let clock = Arc::new(ManualClock::new(1_000_000));
+        let mut state = State::new(Arc::new(MemoryStore::new()), clock.clone());
+        let id = uuid::Uuid::new_v4();
+
+        let (first, expires) = state.issue_challenge(id).unwrap();
+        for _ in 1..MAX_CHALLENGES {
+            state.issue_challenge(id).unwrap();
+        }
+        assert_eq!(state.issue_challenge(id), None);
+        assert!(state.issue_challenge(uuid::Uuid::new_v4()).is_some());
+
+        // Answering one or waiting for them to expire makes room
+        assert!(state.validate_challenge(&id, &first));
+        assert!(state.issue_challenge(id).is_some());
+        clock.set(expires + CHALLENGE_LIFETIME + 1);
+        for _ in 0..MAX_CHALLENGES {
+            state.issue_challenge(id).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_expired_challenge() {
+        let clock = Arc::new(ManualClock::new(1_000_000));
+        let store: SharedStore = Arc::new(MemoryStore::new());
+        let mut state = State::new(store.clone(), clock.clone());
+        let id = uuid::Uuid::new_v4();
+
+        let (challenge, expires) = state.issue_challenge(id).unwrap();
+        assert_eq!(expires, 1_000_000 + CHALLENGE_LIFETIME);
+        clock.set(expires);
+        assert!(state.validate_challenge(&id, &challenge));
+        state.commit_authorization(id, "unlock").unwrap();
+        assert_eq!(store.audit_log().unwrap()[0].time, expires);
+
+        let (challenge, expires) = state.issue_challenge(id).unwrap();
+        clock.set(expires + 1);
+        assert!(!state.validate_challenge(&id, &challenge));
+    }
+
+    #[test]
+    fn test_lock_and_unlock_history() {
+        let clock = Arc::new(ManualClock::new(1_000_000));
+        let store: SharedStore = Arc::new(MemoryStore::new());
+        let mut state = State::new(store.clone(), clock.clone());
+        let (id, other) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+
+        assert_eq!(state.locked_at(), None);
+        state.record_lock();
+        assert_eq!(state.locked_at(), Some(1_000_000));
+        state.record_unlock();
+        assert_eq!(state.locked_at(), None);
+
+        assert!(accept(&mut state, id, 1));
+        clock.advance(UNLOCK_HISTORY - 1);
+        assert!(accept(&mut state, id, 2));
+        assert!(state.validate_nonce(&other, 1));
+        state.commit_authorization(other, "wake").unwrap();
+        assert_eq!(state.recent_unlocks(&id).unwrap(), 2);
+        assert_eq!(state.recent_unlocks(&other).unwrap(), 0);
+
+        // A restart counts the unlocks in the audit log
+        let mut restarted = State::new(store, clock.clone());
+        assert_eq!(restarted.recent_unlocks(&id).unwrap(), 2);
+        clock.advance(1);
+        assert_eq!(state.recent_unlocks(&id).unwrap(), 1);
+        assert_eq!(restarted.recent_unlocks(&id).unwrap(), 1);
+        assert!(accept(&mut restarted, id, 3));
+        assert_eq!(restarted.recent_unlocks(&id).unwrap(), 2);
     }
 }
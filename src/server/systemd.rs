@@ -0,0 +1,234 @@
+use std::net::TcpListener;
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use remote_unlock_lib::prelude::*;
+use sd_notify::NotifyState;
+
+use crate::shutdown::Shutdown;
+
+// Sockets handed over by systemd, told apart by address family so units
+// don't need to name them
+#[derive(Default)]
+pub struct Activation {
+    pub tcp: Option<TcpListener>,
+    pub unix: Option<UnixListener>,
+}
+
+fn socket_option(fd: RawFd, option: libc::c_int) -> Result<libc::c_int, Error> {
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: the kernel writes at most `len` bytes into the local int
+    let result = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            option,
+            (&mut value as *mut libc::c_int).cast(),
+            &mut len,
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+
+    Ok(value)
+}
+
+impl Activation {
+    // Takes the inherited sockets, an empty activation when not socket activated
+    pub fn take() -> Result<Activation, Error> {
+        Activation::from_fds(sd_notify::listen_fds()?)
+    }
+
+    fn from_fds(fds: impl Iterator<Item = RawFd>) -> Result<Activation, Error> {
+        let unsupported = |message| Error::new(ErrorKind::InvalidArgument, Some(message));
+        let mut activation = Activation::default();
+
+        for fd in fds {
+            if socket_option(fd, libc::SO_TYPE)? != libc::SOCK_STREAM
+                || socket_option(fd, libc::SO_ACCEPTCONN)? == 0
+            {
+                return Err(unsupported("Inherited socket is not a stream listener"));
+            }
+
+            // SAFETY: systemd passes ownership of the listening fds to us
+            match socket_option(fd, libc::SO_DOMAIN)? {
+                libc::AF_INET | libc::AF_INET6 if activation.tcp.is_none() => {
+                    activation.tcp = Some(unsafe { TcpListener::from_raw_fd(fd) });
+                }
+                libc::AF_UNIX if activation.unix.is_none() => {
+                    activation.unix = Some(unsafe { UnixListener::from_raw_fd(fd) });
+                }
+                _ => return Err(unsupported("Unexpected inherited socket")),
+            }
+        }
+
+        if activation.tcp.is_some() || activation.unix.is_some() {
+            info!("Using sockets passed by systemd");
+        }
+        Ok(activation)
+    }
+}
+
+// A no-op unless started by systemd with a notify socket
+pub fn notify(state: &[NotifyState]) {
+    if let Err(e) = sd_notify::notify(false, state) {
+        warn!("Failed to notify systemd: {}", e);
+    }
+}
+
+// Longest a loop waits in accept before going around again
+pub const LOOP_TICK: Duration = Duration::from_secs(1);
+
+// Last time a loop went around, the watchdog only pings while every loop
+// it watches keeps beating
+#[derive(Clone)]
+pub struct Heartbeat {
+    last: Arc<Mutex<Instant>>,
+}
+
+impl Heartbeat {
+    pub fn new() -> Heartbeat {
+        Heartbeat {
+            last: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    pub fn beat(&self) {
+        if let Ok(mut last) = self.last.lock() {
+            *last = Instant::now();
+        }
+    }
+
+    fn age(&self) -> Duration {
+        self.last
+            .lock()
+            .map(|last| last.elapsed())
+            .unwrap_or(Duration::MAX)
+    }
+}
+
+// Waits up to `timeout` for a connection, so a loop can beat while idle
+pub fn wait_for_connection(listener: &impl AsRawFd, timeout: Duration) -> Result<bool, Error> {
+    let mut pollfd = libc::pollfd {
+        fd: listener.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: poll reads and writes only the one pollfd passed in
+    let result = unsafe { libc::poll(&mut pollfd, 1, timeout.as_millis() as libc::c_int) };
+    if result < 0 {
+        let e = std::io::Error::last_os_error();
+        if e.kind() == std::io::ErrorKind::Interrupted {
+            return Ok(false);
+        }
+        return Err(e.into());
+    }
+
+    Ok(result > 0)
+}
+
+// How long a loop may go without beating before it counts as stuck. Idle
+// loops beat once per tick, so the timeout leaves a tick of slack and never
+// drops below two ticks however short the watchdog is.
+fn stall_threshold(timeout: Duration) -> Duration {
+    timeout.saturating_sub(LOOP_TICK).max(2 * LOOP_TICK)
+}
+
+// Pings at half the configured interval until shutdown, skipping pings
+// while any loop is stuck so systemd restarts us
+pub fn start_watchdog(shutdown: Shutdown, heartbeats: Vec<Heartbeat>) {
+    let mut usec = 0;
+    if !sd_notify::watchdog_enabled(false, &mut usec) {
+        return;
+    }
+
+    let timeout = Duration::from_micros(usec);
+    let interval = timeout / 2;
+    let threshold = stall_threshold(timeout);
+    if threshold >= timeout {
+        warn!(
+            "Watchdog timeout {:?} is too short to notice stuck loops in time",
+            timeout
+        );
+    }
+    debug!("Systemd watchdog enabled, pinging every {:?}", interval);
+    thread::spawn(move || {
+        while !shutdown.requested() {
+            if heartbeats
+                .iter()
+                .all(|heartbeat| heartbeat.age() < threshold)
+            {
+                notify(&[NotifyState::Watchdog]);
+            } else {
+                error!("A server loop stopped making progress, withholding watchdog ping");
+            }
+            thread::sleep(interval);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{TcpStream, UdpSocket};
+    use std::os::fd::IntoRawFd;
+
+    #[test]
+    fn test_inherited_listeners() {
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        let tcp_addr = tcp.local_addr().unwrap();
+        let path = std::env::temp_dir().join(format!("activation-{}.sock", rand::random::<u32>()));
+        let unix = UnixListener::bind(&path).unwrap();
+
+        let fds = [unix.into_raw_fd(), tcp.into_raw_fd()];
+        let activation = Activation::from_fds(fds.into_iter()).unwrap();
+        assert_eq!(activation.tcp.unwrap().local_addr().unwrap(), tcp_addr);
+        let unix_addr = activation.unix.unwrap().local_addr().unwrap();
+        assert_eq!(unix_addr.as_pathname(), Some(path.as_path()));
+        std::fs::remove_file(path).unwrap();
+
+        assert!(Activation::from_fds(std::iter::empty())
+            .unwrap()
+            .tcp
+            .is_none());
+
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap().into_raw_fd();
+        assert!(Activation::from_fds([udp].into_iter()).is_err());
+        // SAFETY: the fd was released above and is closed exactly once here
+        unsafe { libc::close(udp) };
+    }
+
+    #[test]
+    fn test_wait_for_connection() {
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        assert!(!wait_for_connection(&tcp, Duration::from_millis(10)).unwrap());
+
+        let _client = TcpStream::connect(tcp.local_addr().unwrap()).unwrap();
+        assert!(wait_for_connection(&tcp, Duration::from_secs(5)).unwrap());
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        let heartbeat = Heartbeat::new();
+        thread::sleep(Duration::from_millis(20));
+        assert!(heartbeat.age() >= Duration::from_millis(20));
+        heartbeat.clone().beat();
+        assert!(heartbeat.age() < Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_stall_threshold() {
+        assert_eq!(
+            stall_threshold(Duration::from_secs(30)),
+            Duration::from_secs(29)
+        );
+        // A healthy loop is at most a tick old, even with a short timeout
+        assert_eq!(stall_threshold(Duration::from_secs(2)), 2 * LOOP_TICK);
+        assert_eq!(stall_threshold(Duration::from_millis(500)), 2 * LOOP_TICK);
+    }
+}
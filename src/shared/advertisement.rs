@@ -0,0 +1,152 @@
+use core::str::FromStr;
+
+use crate::crypto::fingerprint::Fingerprint;
+use crate::prelude::*;
+
+// TXT record keys, kept short as each record is limited to 255 bytes
+pub const TXT_VERSION: &str = "v";
+pub const TXT_SIGNATURE_ALGORITHMS: &str = "sig";
+pub const TXT_FINGERPRINT: &str = "fp";
+pub const TXT_TLS: &str = "tls";
+pub const TXT_ENROLLMENT_OPEN: &str = "enroll";
+
+// Comma separated list of algorithms accepted for unlock signatures
+pub const SIGNATURE_ALGORITHMS: &str = "ecdsa-p256-sha256";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Advertisement {
+    version: u16,
+    signature_algorithms: ByteArray<{ Advertisement::MAX_ALGORITHMS_LEN }>,
+    fingerprint: Option<Fingerprint>,
+    tls: bool,
+    enrollment_open: bool,
+}
+
+impl Advertisement {
+    pub const MAX_ALGORITHMS_LEN: usize = 128;
+
+    pub fn new(fingerprint: Fingerprint, tls: bool, enrollment_open: bool) -> Advertisement {
+        Advertisement {
+            version: Config::PROTOCOL_VERSION,
+            // Constant is well below the buffer size
+            signature_algorithms: ByteArray::try_from(SIGNATURE_ALGORITHMS.as_bytes())
+                .unwrap_or_default(),
+            fingerprint: Some(fingerprint),
+            tls,
+            enrollment_open,
+        }
+    }
+
+    // Decodes TXT records, tolerating missing optional keys from older servers
+    pub fn from_txt<'a>(lookup: impl Fn(&str) -> Option<&'a str>) -> Result<Advertisement, Error> {
+        let invalid = |message| Error::new(ErrorKind::InvalidAdvertisement, Some(message));
+
+        let version = lookup(TXT_VERSION)
+            .ok_or_else(|| invalid("Missing protocol version"))?
+            .parse::<u16>()
+            .map_err(|_| invalid("Invalid protocol version"))?;
+
+        let signature_algorithms = ByteArray::try_from(
+            lookup(TXT_SIGNATURE_ALGORITHMS)
+                .unwrap_or_default()
+                .as_bytes(),
+        )?;
+
+        let fingerprint = match lookup(TXT_FINGERPRINT) {
+            Some(fp) if !fp.is_empty() => Some(Fingerprint::from_str(fp)?),
+            _ => None,
+        };
+
+        Ok(Advertisement {
+            version,
+            signature_algorithms,
+            fingerprint,
+            tls: lookup(TXT_TLS) == Some("1"),
+            enrollment_open: lookup(TXT_ENROLLMENT_OPEN) == Some("1"),
+        })
+    }
+
+    pub fn txt_properties(&self) -> [(&'static str, String); 5] {
+        let flag = |value: bool| if value { "1" } else { "0" }.to_string();
+
+        [
+            (TXT_VERSION, self.version.to_string()),
+            (
+                TXT_SIGNATURE_ALGORITHMS,
+                self.signature_algorithms.as_str().unwrap_or("").to_string(),
+            ),
+            (
+                TXT_FINGERPRINT,
+                self.fingerprint
+                    .map(|fp| fp.to_string())
+                    .unwrap_or_default(),
+            ),
+            (TXT_TLS, flag(self.tls)),
+            (TXT_ENROLLMENT_OPEN, flag(self.enrollment_open)),
+        ]
+    }
+
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    pub fn signature_algorithms(&self) -> impl Iterator<Item = &str> {
+        self.signature_algorithms
+            .as_str()
+            .unwrap_or("")
+            .split(',')
+            .filter(|alg| !alg.is_empty())
+    }
+
+    pub fn fingerprint(&self) -> Option<&Fingerprint> {
+        self.fingerprint.as_ref()
+    }
+
+    pub fn tls(&self) -> bool {
+        self.tls
+    }
+
+    pub fn enrollment_open(&self) -> bool {
+        self.enrollment_open
+    }
+
+    pub fn set_enrollment_open(&mut self, enrollment_open: bool) {
+        self.enrollment_open = enrollment_open;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_txt_round_trip() {
+        let fingerprint = Fingerprint::of(b"server key");
+        let advertisement = Advertisement::new(fingerprint, false, true);
+        let properties = advertisement.txt_properties();
+
+        let decoded = Advertisement::from_txt(|key| {
+            properties
+                .iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| v.as_str())
+        })
+        .unwrap();
+
+        assert_eq!(decoded, advertisement);
+        assert_eq!(
+            decoded.signature_algorithms().collect::<Vec<_>>(),
+            vec!["ecdsa-p256-sha256"]
+        );
+    }
+
+    #[test]
+    fn test_txt_minimal() {
+        let decoded = Advertisement::from_txt(|key| (key == TXT_VERSION).then_some("1")).unwrap();
+
+        assert_eq!(decoded.version(), 1);
+        assert!(decoded.fingerprint().is_none());
+        assert!(!decoded.enrollment_open());
+        assert!(Advertisement::from_txt(|_| None).is_err());
+    }
+}
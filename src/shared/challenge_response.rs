@@ -0,0 +1,25 @@
+use serde::{Deserialize, Serialize};
+
+// Challenges are 32 random bytes in base64
+pub const CHALLENGE_LEN: usize = 44;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChallengeResponse<'a> {
+    challenge: &'a str,
+    // Unix time after which the server refuses it
+    expires: i64,
+}
+
+impl<'a> ChallengeResponse<'a> {
+    pub fn new(challenge: &'a str, expires: i64) -> ChallengeResponse<'a> {
+        ChallengeResponse { challenge, expires }
+    }
+
+    pub fn challenge(&self) -> &'a str {
+        self.challenge
+    }
+
+    pub fn expires(&self) -> i64 {
+        self.expires
+    }
+}
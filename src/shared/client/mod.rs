@@ -0,0 +1,289 @@
+use base64::prelude::*;
+
+use crate::challenge_response::{ChallengeResponse, CHALLENGE_LEN};
+use crate::denial::Denial;
+#[cfg(feature = "std")]
+use crate::enroll_request::EnrollmentRequest;
+use crate::enroll_response::EnrollmentResponse;
+use crate::net::method::Method;
+use crate::net::request::Request;
+use crate::net::response::Response;
+use crate::net::status::Status;
+use crate::prelude::*;
+use crate::status_response::StatusResponse;
+use crate::unlock_request::{UnlockRequestBody, ACTION_LOCK, ACTION_STATUS, ACTION_WAKE};
+
+pub mod nonce_store;
+pub mod signer;
+#[cfg(feature = "client")]
+mod tcp;
+
+#[cfg(feature = "client")]
+pub use nonce_store::MemoryNonceStore;
+pub use nonce_store::NonceStore;
+pub use signer::Signer;
+#[cfg(feature = "client")]
+pub use tcp::Client;
+
+pub const SIGNATURE_HEADER: &str = "X-RemoteUnlock-Signature";
+pub const DENIAL_HEADER: &str = "X-RemoteUnlock-Denial";
+
+// Header value size used for client requests, fits a base64 signature
+pub(crate) const HEADER_VALUE_SIZE: usize = 64 * 2;
+
+pub type ClientRequest = Request<HEADER_VALUE_SIZE>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Unlock,
+    Lock,
+    Status,
+    Wake,
+}
+
+impl Action {
+    pub fn path(&self) -> &'static str {
+        match self {
+            Action::Unlock => "/unlock",
+            Action::Lock => "/lock",
+            Action::Status => "/status",
+            Action::Wake => "/wake",
+        }
+    }
+
+    // Unlock omits the signed action for compatibility with older servers
+    fn signed_name(&self) -> Option<&'static str> {
+        match self {
+            Action::Unlock => None,
+            Action::Lock => Some(ACTION_LOCK),
+            Action::Status => Some(ACTION_STATUS),
+            Action::Wake => Some(ACTION_WAKE),
+        }
+    }
+}
+
+// Needs the PEM encoder, enroll constrained devices from a host instead
+#[cfg(feature = "std")]
+pub fn enroll_request(signer: &impl Signer, code: u32) -> Result<ClientRequest, Error> {
+    let pubkey = signer.public_key()?;
+    let enroll_req = EnrollmentRequest::new(code, pubkey.pem()?);
+
+    let mut req = ClientRequest::builder()
+        .method(Method::POST)
+        .path("/enroll")
+        .add_header("Content-Type", "application/json")?
+        .build();
+    serde_json::to_writer(&mut req, &enroll_req)?;
+
+    Ok(req)
+}
+
+// Asks for a challenge to sign instead of a nonce, for devices the server
+// has set to challenge mode
+pub fn challenge_request(id: &uuid::Uuid) -> Result<ClientRequest, Error> {
+    let mut id_buf: [u8; 32] = [0; 32];
+    let id_str = id.as_simple().encode_lower(&mut id_buf);
+    let mut path: ByteArray<64> = ByteArray::new();
+    core::fmt::Write::write_fmt(&mut path, format_args!("/challenge?id={}", id_str))
+        .map_err(|_| Error::from(ErrorKind::OversizePacket))?;
+
+    Ok(ClientRequest::builder()
+        .method(Method::GET)
+        .path(path.as_str()?)
+        .build())
+}
+
+// `ts` is the current Unix time, servers requiring timestamps refuse
+// requests without one
+pub fn signed_request(
+    signer: &impl Signer,
+    id: &uuid::Uuid,
+    nonce: u128,
+    ts: Option<i64>,
+    action: Action,
+) -> Result<ClientRequest, Error> {
+    // The server stores keys under the simple (unhyphenated) form of the id
+    let mut id_buf: [u8; 32] = [0; 32];
+    let id_str = id.as_simple().encode_lower(&mut id_buf);
+
+    sign(signer, UnlockRequestBody::new(id_str, nonce), ts, action)
+}
+
+// Answers a challenge from `parse_challenge_response`, which is only valid
+// for one request
+pub fn signed_challenge_request(
+    signer: &impl Signer,
+    id: &uuid::Uuid,
+    challenge: &str,
+    ts: Option<i64>,
+    action: Action,
+) -> Result<ClientRequest, Error> {
+    let mut id_buf: [u8; 32] = [0; 32];
+    let id_str = id.as_simple().encode_lower(&mut id_buf);
+
+    sign(
+        signer,
+        UnlockRequestBody::challenged(id_str, challenge),
+        ts,
+        action,
+    )
+}
+
+fn sign(
+    signer: &impl Signer,
+    mut body: UnlockRequestBody,
+    ts: Option<i64>,
+    action: Action,
+) -> Result<ClientRequest, Error> {
+    if let Some(ts) = ts {
+        body = body.with_timestamp(ts);
+    }
+    if let Some(action) = action.signed_name() {
+        body = body.with_action(action);
+    }
+
+    let serial = body.signing_bytes()?;
+    let signature = signer.sign(serial.as_bytes())?;
+
+    let mut encoded = [0; HEADER_VALUE_SIZE];
+    let encoded_len = BASE64_STANDARD
+        .encode_slice(signature.as_bytes(), &mut encoded)
+        .map_err(|_| Error::from(ErrorKind::OversizePacket))?;
+    let encoded = core::str::from_utf8(&encoded[..encoded_len])?;
+
+    Ok(ClientRequest::builder()
+        .method(Method::POST)
+        .path(action.path())
+        .add_header("Content-Type", "application/json")?
+        .add_header(SIGNATURE_HEADER, encoded)?
+        .body(serial.as_bytes())
+        .build())
+}
+
+// Fails on any status other than 200
+pub fn check_status(resp: Response) -> Result<Response, Error> {
+    match resp.status() {
+        Status::Ok => Ok(resp),
+        Status::RequestTimeout => Err(ErrorKind::StaleRequest.into()),
+        Status::Forbidden => match resp
+            .get_header(DENIAL_HEADER)
+            .and_then(|header| Denial::from_code(header.value.as_str().ok()?))
+        {
+            Some(denial) => Err(ErrorKind::PolicyDenied(denial).into()),
+            None => Err(Error::new(
+                ErrorKind::UnexpectedStatus,
+                Some(Status::Forbidden.to_string()),
+            )),
+        },
+        status => Err(Error::new(
+            ErrorKind::UnexpectedStatus,
+            Some(status.to_string()),
+        )),
+    }
+}
+
+pub fn parse_enrollment_response(resp: &Response) -> Result<uuid::Uuid, Error> {
+    let (enroll_resp, _) =
+        serde_json_core::from_slice::<EnrollmentResponse>(&resp.body[..resp.body_len])?;
+    Ok(*enroll_resp.id())
+}
+
+pub fn parse_status_response(resp: &Response) -> Result<StatusResponse, Error> {
+    let (status, _) = serde_json_core::from_slice::<StatusResponse>(&resp.body[..resp.body_len])?;
+    Ok(status)
+}
+
+pub fn parse_challenge_response(resp: &Response) -> Result<ByteArray<CHALLENGE_LEN>, Error> {
+    let (challenge, _) =
+        serde_json_core::from_slice::<ChallengeResponse>(&resp.body[..resp.body_len])?;
+    Ok(ByteArray::try_from(challenge.challenge().as_bytes())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use p256::ecdsa::SigningKey;
+
+    #[test]
+    fn test_signed_request_round_trip() {
+        let signing_key = SigningKey::from_bytes(&[7; 32].into()).unwrap();
+        let id = uuid::Uuid::from_u128(0xc0ffee);
+
+        let req =
+            signed_request(&signing_key, &id, 42, Some(1_700_000_000), Action::Status).unwrap();
+        let mut raw = ByteArray::<{ Config::MAX_PACKET_SIZE }>::new();
+        req.write_into(&mut raw).unwrap();
+
+        let parsed = ClientRequest::parse(raw.as_bytes()).unwrap();
+        assert_eq!(parsed.path(), Some("/status"));
+        assert_eq!(
+            &parsed.body[..parsed.body_len],
+            br#"{"id":"00000000000000000000000000c0ffee","nonce":42,"ts":1700000000,"action":"status"}"#
+        );
+
+        let mut signature = [0; HEADER_VALUE_SIZE];
+        let header = parsed.get_header(SIGNATURE_HEADER).unwrap();
+        let signature_len = BASE64_STANDARD
+            .decode_slice(header.value.as_bytes(), &mut signature)
+            .unwrap();
+
+        let body = UnlockRequestBody::new("00000000000000000000000000c0ffee", 42)
+            .with_timestamp(1_700_000_000)
+            .with_action(ACTION_STATUS);
+        let pubkey = signing_key.public_key().unwrap();
+        assert!(body.verify(&signature[..signature_len], &pubkey).unwrap());
+    }
+
+    #[test]
+    fn test_challenge_round_trip() {
+        let id = uuid::Uuid::from_u128(0xc0ffee);
+        let req = challenge_request(&id).unwrap();
+        assert_eq!(
+            req.path(),
+            Some("/challenge?id=00000000000000000000000000c0ffee")
+        );
+
+        let challenge = "q83vq83vq83vq83vq83vq83vq83vq83vq83vq83vq80=";
+        let body = format!(r#"{{"challenge":"{}","expires":1}}"#, challenge);
+        let raw = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let resp = Response::<HEADER_VALUE_SIZE>::parse(raw.as_bytes()).unwrap();
+        let parsed = parse_challenge_response(&resp).unwrap();
+        assert_eq!(parsed.as_str().unwrap(), challenge);
+
+        let signing_key = SigningKey::from_bytes(&[7; 32].into()).unwrap();
+        let req =
+            signed_challenge_request(&signing_key, &id, challenge, None, Action::Unlock).unwrap();
+        let expected = format!(
+            r#"{{"id":"00000000000000000000000000c0ffee","challenge":"{}"}}"#,
+            challenge
+        );
+        assert_eq!(&req.body[..req.body_len], expected.as_bytes());
+    }
+
+    #[test]
+    fn test_parse_status_response() {
+        let raw = b"HTTP/1.1 200 OK\r\nContent-Length: 15\r\n\r\n{\"locked\":true}";
+        let resp = Response::<HEADER_VALUE_SIZE>::parse(raw).unwrap();
+
+        assert!(parse_status_response(&resp).unwrap().locked());
+    }
+
+    #[test]
+    fn test_policy_denial() {
+        let raw = b"HTTP/1.1 403 Forbidden\r\nX-RemoteUnlock-Denial: outside_hours\r\n\r\n";
+        let err = check_status(Response::parse(raw).unwrap()).unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            Some(ErrorKind::PolicyDenied(Denial::OutsideHours))
+        ));
+
+        let raw = b"HTTP/1.1 403 Forbidden\r\n\r\n";
+        let err = check_status(Response::parse(raw).unwrap()).unwrap_err();
+        assert!(matches!(err.kind(), Some(ErrorKind::UnexpectedStatus)));
+    }
+}
@@ -0,0 +1,38 @@
+#[cfg(feature = "client")]
+use std::collections::HashMap;
+
+use crate::prelude::*;
+
+// Tracks the next nonce for each enrollment
+pub trait NonceStore {
+    // Returns the nonce to sign and persists its successor before the request is sent,
+    // so a lost response never leads to nonce reuse
+    fn next_nonce(&mut self, id: &uuid::Uuid) -> Result<u128, Error>;
+}
+
+#[cfg(feature = "client")]
+#[derive(Debug, Default)]
+pub struct MemoryNonceStore {
+    nonces: HashMap<uuid::Uuid, u128>,
+}
+
+#[cfg(feature = "client")]
+impl MemoryNonceStore {
+    pub fn new() -> MemoryNonceStore {
+        MemoryNonceStore::default()
+    }
+
+    pub fn set_nonce(&mut self, id: uuid::Uuid, nonce: u128) {
+        self.nonces.insert(id, nonce);
+    }
+}
+
+#[cfg(feature = "client")]
+impl NonceStore for MemoryNonceStore {
+    fn next_nonce(&mut self, id: &uuid::Uuid) -> Result<u128, Error> {
+        let nonce = self.nonces.entry(*id).or_insert(0);
+        let current = *nonce;
+        *nonce += 1;
+        Ok(current)
+    }
+}
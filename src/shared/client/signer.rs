@@ -0,0 +1,41 @@
+use der::asn1::BitStringRef;
+use der::Encode;
+use p256::ecdsa::{signature, Signature, SigningKey};
+use p256::pkcs8::AssociatedOid;
+use spki::{AlgorithmIdentifierRef, SubjectPublicKeyInfoRef};
+
+use crate::crypto::key::PublicKey;
+use crate::prelude::*;
+
+// Upper bound of a DER encoded P-256 ECDSA signature
+pub const MAX_SIGNATURE_LEN: usize = 72;
+
+// Produces request signatures, letting keys live outside the process (agents, hardware tokens)
+pub trait Signer {
+    // DER encoded ECDSA P-256/SHA-256 signature over the message
+    fn sign(&self, message: &[u8]) -> Result<ByteArray<MAX_SIGNATURE_LEN>, Error>;
+
+    fn public_key(&self) -> Result<PublicKey, Error>;
+}
+
+impl Signer for SigningKey {
+    fn sign(&self, message: &[u8]) -> Result<ByteArray<MAX_SIGNATURE_LEN>, Error> {
+        let signature: Signature = signature::Signer::sign(self, message);
+        Ok(ByteArray::try_from(signature.to_der().as_bytes())?)
+    }
+
+    fn public_key(&self) -> Result<PublicKey, Error> {
+        // Encoded by hand as the SPKI document helpers need an allocator
+        let point = self.verifying_key().to_encoded_point(false);
+        let spki = SubjectPublicKeyInfoRef {
+            algorithm: AlgorithmIdentifierRef {
+                oid: p256::elliptic_curve::ALGORITHM_OID,
+                parameters: Some((&p256::NistP256::OID).into()),
+            },
+            subject_public_key: BitStringRef::from_bytes(point.as_bytes())?,
+        };
+
+        let mut buf = [0; Config::BUFFER_SIZE];
+        PublicKey::from_der(spki.encode_to_slice(&mut buf)?)
+    }
+}
@@ -0,0 +1,268 @@
+use std::net::{Shutdown, TcpStream};
+
+use super::{
+    challenge_request, check_status, enroll_request, parse_challenge_response,
+    parse_enrollment_response, parse_status_response, signed_challenge_request, signed_request,
+    Action, ClientRequest, NonceStore, Signer,
+};
+use crate::clock::{Clock, SystemClock};
+use crate::net::response::Response;
+use crate::prelude::*;
+use crate::status_response::StatusResponse;
+
+pub struct Client<S: Signer, N: NonceStore> {
+    host: String,
+    port: u16,
+    signer: S,
+    nonce_store: N,
+    id: Option<uuid::Uuid>,
+    challenges: bool,
+    timestamps: bool,
+}
+
+impl<S: Signer, N: NonceStore> Client<S, N> {
+    pub fn new(host: &str, port: u16, signer: S, nonce_store: N) -> Client<S, N> {
+        Client {
+            host: host.to_string(),
+            port,
+            signer,
+            nonce_store,
+            id: None,
+            challenges: false,
+            timestamps: false,
+        }
+    }
+
+    // Resumes an existing enrollment
+    pub fn with_id(mut self, id: uuid::Uuid) -> Client<S, N> {
+        self.id = Some(id);
+        self
+    }
+
+    // Signs a fresh challenge from the server instead of the next nonce, for
+    // devices the server has set to challenge mode
+    pub fn with_challenges(mut self) -> Client<S, N> {
+        self.challenges = true;
+        self
+    }
+
+    // Adds the current time to signed requests so captured copies go stale
+    pub fn with_timestamps(mut self) -> Client<S, N> {
+        self.timestamps = true;
+        self
+    }
+
+    pub fn id(&self) -> Option<&uuid::Uuid> {
+        self.id.as_ref()
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn signer(&self) -> &S {
+        &self.signer
+    }
+
+    pub fn nonce_store(&self) -> &N {
+        &self.nonce_store
+    }
+
+    pub fn enroll(&mut self, code: u32) -> Result<uuid::Uuid, Error> {
+        let req = enroll_request(&self.signer, code)?;
+        let id = parse_enrollment_response(&self.send(&req)?)?;
+
+        debug!("Enrolled with id: {}", &id);
+        self.id = Some(id);
+
+        Ok(id)
+    }
+
+    pub fn unlock(&mut self) -> Result<(), Error> {
+        self.send_signed(Action::Unlock)?;
+        Ok(())
+    }
+
+    pub fn lock(&mut self) -> Result<(), Error> {
+        self.send_signed(Action::Lock)?;
+        Ok(())
+    }
+
+    pub fn wake(&mut self) -> Result<(), Error> {
+        self.send_signed(Action::Wake)?;
+        Ok(())
+    }
+
+    pub fn status(&mut self) -> Result<StatusResponse, Error> {
+        let resp = self.send_signed(Action::Status)?;
+        parse_status_response(&resp)
+    }
+
+    fn send_signed(&mut self, action: Action) -> Result<Response, Error> {
+        let id = self.id.ok_or(Error::new(ErrorKind::NotEnrolled, None))?;
+
+        let ts = self.timestamps.then(|| SystemClock.now());
+        let req = if self.challenges {
+            let challenge = parse_challenge_response(&self.send(&challenge_request(&id)?)?)?;
+            signed_challenge_request(&self.signer, &id, challenge.as_str()?, ts, action)?
+        } else {
+            let nonce = self.nonce_store.next_nonce(&id)?;
+            signed_request(&self.signer, &id, nonce, ts, action)?
+        };
+        self.send(&req)
+    }
+
+    fn send(&self, req: &ClientRequest) -> Result<Response, Error> {
+        debug!("Connecting to {}:{}", &self.host, self.port);
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port))?;
+
+        req.to_writer(&mut stream)?;
+        // The server reads until EOF
+        stream.shutdown(Shutdown::Write)?;
+
+        check_status(Response::from_stream(&mut stream)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::challenge_response::ChallengeResponse;
+    use crate::client::{MemoryNonceStore, HEADER_VALUE_SIZE, SIGNATURE_HEADER};
+    use crate::net::request::Request;
+    use crate::net::status::Status;
+    use crate::unlock_request::{UnlockRequestBody, ACTION_LOCK};
+    use base64::prelude::*;
+
+    use std::net::TcpListener;
+    use std::thread;
+
+    use p256::ecdsa::SigningKey;
+    use rand::rngs::OsRng;
+
+    // Accepts one request, checks it was signed by the client key and replies 200
+    fn serve_signed_once(listener: TcpListener, signer: SigningKey) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let req = Request::<HEADER_VALUE_SIZE>::from_stream(&mut stream).unwrap();
+
+            let body = std::str::from_utf8(&req.body[..req.body_len]).unwrap();
+            let signed = serde_json::from_str::<UnlockRequestBody>(body).unwrap();
+            assert_eq!(signed.nonce(), Some(7));
+            assert_eq!(signed.action(), Some(ACTION_LOCK));
+
+            let header = req.get_header(SIGNATURE_HEADER).unwrap();
+            let signature = BASE64_STANDARD.decode(header.value.as_bytes()).unwrap();
+            let pubkey = signer.public_key().unwrap();
+            assert!(signed.verify(&signature, &pubkey).unwrap());
+
+            Response::<HEADER_VALUE_SIZE>::new(Status::Ok)
+                .to_writer(&mut stream)
+                .unwrap();
+        })
+    }
+
+    #[test]
+    fn test_signed_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let signing_key = SigningKey::random(&mut OsRng);
+        let server = serve_signed_once(listener, signing_key.clone());
+
+        let id = uuid::Uuid::new_v4();
+        let mut nonces = MemoryNonceStore::new();
+        nonces.set_nonce(id, 7);
+
+        let mut client = Client::new("127.0.0.1", port, signing_key, nonces).with_id(id);
+        client.lock().unwrap();
+        server.join().unwrap();
+
+        assert_eq!(client.nonce_store.next_nonce(&id).unwrap(), 8);
+    }
+
+    #[test]
+    fn test_challenge_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let signing_key = SigningKey::random(&mut OsRng);
+        let id = uuid::Uuid::new_v4();
+        let challenge = "q83vq83vq83vq83vq83vq83vq83vq83vq83vq83vq80=";
+
+        let signer = signing_key.clone();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let req = Request::<HEADER_VALUE_SIZE>::from_stream(&mut stream).unwrap();
+            assert!(req.path().unwrap().starts_with("/challenge?id="));
+            let mut resp = Response::<HEADER_VALUE_SIZE>::new(Status::Ok);
+            serde_json::to_writer(&mut resp, &ChallengeResponse::new(challenge, 0)).unwrap();
+            resp.to_writer(&mut stream).unwrap();
+            // The client reads the response until EOF
+            drop(stream);
+
+            let (mut stream, _) = listener.accept().unwrap();
+            let req = Request::<HEADER_VALUE_SIZE>::from_stream(&mut stream).unwrap();
+            let body = std::str::from_utf8(&req.body[..req.body_len]).unwrap();
+            let signed = serde_json::from_str::<UnlockRequestBody>(body).unwrap();
+            assert_eq!(signed.nonce(), None);
+            assert_eq!(signed.challenge(), Some(challenge));
+
+            let header = req.get_header(SIGNATURE_HEADER).unwrap();
+            let signature = BASE64_STANDARD.decode(header.value.as_bytes()).unwrap();
+            assert!(signed
+                .verify(&signature, &signer.public_key().unwrap())
+                .unwrap());
+            Response::<HEADER_VALUE_SIZE>::new(Status::Ok)
+                .to_writer(&mut stream)
+                .unwrap();
+        });
+
+        let mut client = Client::new("127.0.0.1", port, signing_key, MemoryNonceStore::new())
+            .with_id(id)
+            .with_challenges();
+        client.unlock().unwrap();
+        server.join().unwrap();
+
+        // The nonce counter is left alone
+        assert_eq!(client.nonce_store.next_nonce(&id).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_stale_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let signing_key = SigningKey::random(&mut OsRng);
+
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let req = Request::<HEADER_VALUE_SIZE>::from_stream(&mut stream).unwrap();
+            let body = std::str::from_utf8(&req.body[..req.body_len]).unwrap();
+            let signed = serde_json::from_str::<UnlockRequestBody>(body).unwrap();
+            assert!(signed.timestamp().is_some_and(|ts| ts > 1_700_000_000));
+
+            Response::<HEADER_VALUE_SIZE>::new(Status::RequestTimeout)
+                .to_writer(&mut stream)
+                .unwrap();
+        });
+
+        let id = uuid::Uuid::new_v4();
+        let mut client = Client::new("127.0.0.1", port, signing_key, MemoryNonceStore::new())
+            .with_id(id)
+            .with_timestamps();
+        let err = client.unlock().unwrap_err();
+        server.join().unwrap();
+
+        assert!(matches!(err.kind(), Some(ErrorKind::StaleRequest)));
+    }
+
+    #[test]
+    fn test_requires_enrollment() {
+        let signing_key = SigningKey::random(&mut OsRng);
+        let mut client = Client::new("127.0.0.1", 1, signing_key, MemoryNonceStore::new());
+
+        assert!(client.unlock().is_err());
+    }
+}
@@ -0,0 +1,73 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Source of the current Unix time in seconds, swapped out in tests so
+// expiry can be checked without sleeping
+pub trait Clock: Send + Sync {
+    fn now(&self) -> i64;
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl SystemClock {
+    pub fn shared() -> SharedClock {
+        Arc::new(SystemClock)
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            Err(before) => -(before.duration().as_secs() as i64),
+        }
+    }
+}
+
+// Only moves when told to
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now: AtomicI64,
+}
+
+impl ManualClock {
+    pub fn new(now: i64) -> ManualClock {
+        ManualClock {
+            now: AtomicI64::new(now),
+        }
+    }
+
+    pub fn set(&self, now: i64) {
+        self.now.store(now, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, seconds: i64) {
+        self.now.fetch_add(seconds, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> i64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock() {
+        let clock = ManualClock::new(100);
+        clock.advance(5);
+        assert_eq!(clock.now(), 105);
+        clock.set(-1);
+        assert_eq!(clock.now(), -1);
+
+        assert!(SystemClock.now() > 1_700_000_000);
+    }
+}
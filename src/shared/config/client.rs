@@ -0,0 +1,108 @@
+use std::path::PathBuf;
+use toml::Value;
+
+use super::layer::{self, *};
+
+#[cfg(debug_assertions)]
+const DEFAULT_GENERATED_KEYS_DIR: &str = "/tmp/remote_unlock_keys";
+
+#[derive(Debug, Clone, Default)]
+pub struct ClientConfig {
+    socket_path: Setting<String>,
+    server_port: Setting<u16>,
+    service_type: Setting<String>,
+    client_dir: Setting<String>,
+    generated_keys_dir: Setting<String>,
+}
+
+impl ClientConfig {
+    // Layers the config files, environment and command line overrides
+    pub fn load(overrides: &Overrides) -> Result<ClientConfig, ConfigError> {
+        layer::load(overrides)
+    }
+
+    // Effective settings and where they came from
+    pub fn entries(&self) -> Vec<Entry> {
+        #[cfg_attr(not(debug_assertions), allow(unused_mut))]
+        let mut entries = vec![
+            Entry::new("socket_path", self.socket_path(), &self.socket_path),
+            Entry::new("server_port", self.server_port(), &self.server_port),
+            Entry::new("service_type", self.service_type(), &self.service_type),
+            Entry::new("client_dir", self.client_dir().display(), &self.client_dir),
+        ];
+
+        #[cfg(debug_assertions)]
+        entries.push(Entry::new(
+            "generated_keys_dir",
+            self.generated_keys_dir(),
+            &self.generated_keys_dir,
+        ));
+
+        entries
+    }
+
+    // Control socket of a daemon on this machine
+    pub fn socket_path(&self) -> &str {
+        match self.socket_path.value() {
+            Some(path) => path,
+            None => DEFAULT_SOCKET_PATH,
+        }
+    }
+
+    pub fn service_type(&self) -> &str {
+        match self.service_type.value() {
+            Some(service_type) => service_type,
+            None => DEFAULT_SERVICE_TYPE,
+        }
+    }
+
+    // Port assumed when a pairing doesn't name one
+    pub fn server_port(&self) -> u16 {
+        self.server_port
+            .value()
+            .copied()
+            .unwrap_or(DEFAULT_SERVER_PORT)
+    }
+
+    // Where client profiles and keys are kept
+    pub fn client_dir(&self) -> PathBuf {
+        if let Some(dir) = self.client_dir.value() {
+            return PathBuf::from(dir);
+        }
+
+        config_home()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("remote-unlock")
+    }
+
+    pub fn profiles_dir(&self) -> PathBuf {
+        self.client_dir().join("profiles")
+    }
+
+    #[cfg(debug_assertions)]
+    pub fn generated_keys_dir(&self) -> &str {
+        match self.generated_keys_dir.value() {
+            Some(path) => path,
+            None => DEFAULT_GENERATED_KEYS_DIR,
+        }
+    }
+}
+
+impl Layered for ClientConfig {
+    fn apply(&mut self, key: &str, value: &Value, source: &Source) -> Result<(), ConfigError> {
+        match key {
+            "socket_path" => self.socket_path.set(string(key, value, source)?, source),
+            "server_port" => self.server_port.set(port(key, value, source)?, source),
+            "service_type" => self
+                .service_type
+                .set(service_type(key, value, source)?, source),
+            "client_dir" => self.client_dir.set(string(key, value, source)?, source),
+            "generated_keys_dir" => self
+                .generated_keys_dir
+                .set(string(key, value, source)?, source),
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
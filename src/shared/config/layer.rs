@@ -0,0 +1,636 @@
+// Settings are layered, later layers win:
+// defaults < /etc/remote-unlock/config.toml < $XDG_CONFIG_HOME/remote-unlock/config.toml
+//          < --config file < environment < --set flags
+use core::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+
+use toml::{Table, Value};
+
+pub(super) const DEFAULT_SOCKET_PATH: &str = "/tmp/remote_unlock.sock";
+pub(super) const DEFAULT_SERVICE_TYPE: &str = "_remote-unlock._tcp.local.";
+pub(super) const DEFAULT_SERVER_PORT: u16 = 8142;
+
+const SYSTEM_CONFIG_PATH: &str = "/etc/remote-unlock/config.toml";
+
+// Every setting accepted in files and flags, with the variable overriding it.
+// Shared by both configs so a single file can serve the daemon and the cli.
+const KEYS: &[(&str, &str)] = &[
+    ("socket_path", "REMOTE_UNLOCK_SOCKET_PATH"),
+    ("socket_mode", "REMOTE_UNLOCK_SOCKET_MODE"),
+    ("control_group", "REMOTE_UNLOCK_CONTROL_GROUP"),
+    ("server_ip", "REMOTE_UNLOCK_SERVER_IP"),
+    ("server_port", "REMOTE_UNLOCK_SERVER_PORT"),
+    ("storage_dir", "REMOTE_UNLOCK_STORAGE_DIR"),
+    ("storage_backend", "REMOTE_UNLOCK_STORAGE_BACKEND"),
+    ("nonce_window", "REMOTE_UNLOCK_NONCE_WINDOW"),
+    ("max_nonce_jump", "REMOTE_UNLOCK_MAX_NONCE_JUMP"),
+    ("require_timestamp", "REMOTE_UNLOCK_REQUIRE_TIMESTAMP"),
+    ("timestamp_window", "REMOTE_UNLOCK_TIMESTAMP_WINDOW"),
+    ("clock_skew", "REMOTE_UNLOCK_CLOCK_SKEW"),
+    ("policy_file", "REMOTE_UNLOCK_POLICY_FILE"),
+    ("identity_file", "REMOTE_UNLOCK_IDENTITY_FILE"),
+    ("log_level", "REMOTE_UNLOCK_LOG_LEVEL"),
+    ("hostname", "REMOTE_UNLOCK_HOSTNAME"),
+    ("service_type", "REMOTE_UNLOCK_MDNS_SERVICE_TYPE"),
+    ("instance_name", "REMOTE_UNLOCK_MDNS_INSTANCE_NAME"),
+    ("mdns_interfaces", "REMOTE_UNLOCK_MDNS_INTERFACES"),
+    ("mdns_ipv4", "REMOTE_UNLOCK_MDNS_IPV4"),
+    ("mdns_ipv6", "REMOTE_UNLOCK_MDNS_IPV6"),
+    ("sway_socket_path", "SWAYSOCK"),
+    ("client_dir", "REMOTE_UNLOCK_CLIENT_DIR"),
+    ("generated_keys_dir", "REMOTE_UNLOCK_GENERATED_KEYS_DIR"),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    File(PathBuf),
+    Env(&'static str),
+    Flag,
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(path) => write!(f, "{}", path.display()),
+            Source::Env(name) => write!(f, "env {}", name),
+            Source::Flag => write!(f, "command line"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Setting<T> {
+    value: Option<T>,
+    source: Source,
+}
+
+impl<T> Setting<T> {
+    pub fn value(&self) -> Option<&T> {
+        self.value.as_ref()
+    }
+
+    pub fn source(&self) -> &Source {
+        &self.source
+    }
+
+    pub(super) fn set(&mut self, value: T, source: &Source) {
+        self.value = Some(value);
+        self.source = source.clone();
+    }
+}
+
+impl<T> Default for Setting<T> {
+    fn default() -> Self {
+        Setting {
+            value: None,
+            source: Source::Default,
+        }
+    }
+}
+
+// Effective value of a setting as shown by `cli config show`
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub key: &'static str,
+    pub value: String,
+    pub source: Source,
+}
+
+impl Entry {
+    pub(super) fn new<T>(key: &'static str, value: impl ToString, setting: &Setting<T>) -> Entry {
+        Entry {
+            key,
+            value: value.to_string(),
+            source: setting.source().clone(),
+        }
+    }
+}
+
+// Carries the full context, unlike the fixed size messages of `Error`
+#[derive(Debug)]
+pub struct ConfigError {
+    source: Source,
+    key: Option<String>,
+    message: String,
+}
+
+impl ConfigError {
+    fn new(source: &Source, key: Option<&str>, message: impl Into<String>) -> ConfigError {
+        ConfigError {
+            source: source.clone(),
+            key: key.map(str::to_string),
+            message: message.into(),
+        }
+    }
+
+    pub fn source(&self) -> &Source {
+        &self.source
+    }
+
+    pub fn key(&self) -> Option<&str> {
+        self.key.as_deref()
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.key {
+            Some(key) => write!(f, "{}: {}: {}", self.source, key, self.message),
+            None => write!(f, "{}: {}", self.source, self.message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// Settings given on the command line
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    file: Option<PathBuf>,
+    values: Vec<(String, String)>,
+}
+
+impl Overrides {
+    pub fn new() -> Overrides {
+        Overrides::default()
+    }
+
+    // Extra file layered above the standard ones, it must exist
+    pub fn file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.file = Some(path.into());
+        self
+    }
+
+    pub fn set(mut self, key: &str, value: &str) -> Self {
+        self.values.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    // Parses a `key=value` assignment
+    pub fn assign(self, assignment: &str) -> Result<Self, ConfigError> {
+        match assignment.split_once('=') {
+            Some((key, value)) => Ok(self.set(key.trim(), value.trim())),
+            None => Err(ConfigError::new(
+                &Source::Flag,
+                Some(assignment),
+                "expected key=value",
+            )),
+        }
+    }
+}
+
+pub(super) trait Layered: Default {
+    // Called for every key in `KEYS`, settings of the other config are ignored
+    fn apply(&mut self, key: &str, value: &Value, source: &Source) -> Result<(), ConfigError>;
+}
+
+// Standard config files in the order they are applied
+pub fn config_files() -> Vec<PathBuf> {
+    let mut files = vec![PathBuf::from(SYSTEM_CONFIG_PATH)];
+    if let Some(config_home) = config_home() {
+        files.push(config_home.join("remote-unlock").join("config.toml"));
+    }
+
+    files
+}
+
+pub(super) fn config_home() -> Option<PathBuf> {
+    std::env::var("XDG_CONFIG_HOME")
+        .ok()
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var("HOME")
+                .ok()
+                .map(|home| Path::new(&home).join(".config"))
+        })
+}
+
+pub(super) fn load<C: Layered>(overrides: &Overrides) -> Result<C, ConfigError> {
+    load_from(&config_files(), |name| std::env::var(name).ok(), overrides)
+}
+
+pub(super) fn load_from<C: Layered>(
+    files: &[PathBuf],
+    env: impl Fn(&str) -> Option<String>,
+    overrides: &Overrides,
+) -> Result<C, ConfigError> {
+    let mut config = C::default();
+
+    for path in files {
+        if let Some(table) = read_file(path, false)? {
+            apply_table(&mut config, table, &Source::File(path.clone()))?;
+        }
+    }
+    if let Some(path) = &overrides.file {
+        if let Some(table) = read_file(path, true)? {
+            apply_table(&mut config, table, &Source::File(path.clone()))?;
+        }
+    }
+
+    for (key, name) in KEYS {
+        if let Some(value) = env(name) {
+            config.apply(key, &Value::String(value), &Source::Env(name))?;
+        }
+    }
+
+    for (key, value) in &overrides.values {
+        check_key(key, &Source::Flag)?;
+        config.apply(key, &Value::String(value.clone()), &Source::Flag)?;
+    }
+
+    Ok(config)
+}
+
+fn read_file(path: &Path, required: bool) -> Result<Option<Table>, ConfigError> {
+    let source = Source::File(path.to_path_buf());
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => return Ok(None),
+        Err(e) => return Err(ConfigError::new(&source, None, e.to_string())),
+    };
+
+    contents
+        .parse::<Table>()
+        .map(Some)
+        .map_err(|e| ConfigError::new(&source, None, e.to_string().trim_end()))
+}
+
+fn apply_table<C: Layered>(
+    config: &mut C,
+    table: Table,
+    source: &Source,
+) -> Result<(), ConfigError> {
+    for (key, value) in table.iter() {
+        check_key(key, source)?;
+        config.apply(key, value, source)?;
+    }
+
+    Ok(())
+}
+
+fn check_key(key: &str, source: &Source) -> Result<(), ConfigError> {
+    if KEYS.iter().any(|(known, _)| *known == key) {
+        Ok(())
+    } else {
+        Err(ConfigError::new(source, Some(key), "unknown setting"))
+    }
+}
+
+fn mismatch(key: &str, value: &Value, source: &Source, expected: &str) -> ConfigError {
+    ConfigError::new(
+        source,
+        Some(key),
+        format!("expected {}, found {}", expected, value.type_str()),
+    )
+}
+
+pub(super) fn string(key: &str, value: &Value, source: &Source) -> Result<String, ConfigError> {
+    match value {
+        Value::String(s) if s.trim().is_empty() => {
+            Err(ConfigError::new(source, Some(key), "must not be empty"))
+        }
+        Value::String(s) => Ok(s.clone()),
+        other => Err(mismatch(key, other, source, "a string")),
+    }
+}
+
+pub(super) fn port(key: &str, value: &Value, source: &Source) -> Result<u16, ConfigError> {
+    let port = match value {
+        Value::Integer(port) => *port,
+        Value::String(s) => s.trim().parse::<i64>().map_err(|_| {
+            ConfigError::new(
+                source,
+                Some(key),
+                format!("expected a port number, found \"{}\"", s),
+            )
+        })?,
+        other => return Err(mismatch(key, other, source, "a port number")),
+    };
+
+    u16::try_from(port)
+        .ok()
+        .filter(|port| *port != 0)
+        .ok_or_else(|| {
+            ConfigError::new(
+                source,
+                Some(key),
+                format!("port must be between 1 and 65535, found {}", port),
+            )
+        })
+}
+
+#[cfg(feature = "server")]
+pub(super) fn boolean(key: &str, value: &Value, source: &Source) -> Result<bool, ConfigError> {
+    match value {
+        Value::Boolean(b) => Ok(*b),
+        Value::String(s) => match s.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Ok(true),
+            "0" | "false" | "no" | "off" => Ok(false),
+            _ => Err(ConfigError::new(
+                source,
+                Some(key),
+                format!("expected true or false, found \"{}\"", s),
+            )),
+        },
+        other => Err(mismatch(key, other, source, "a boolean")),
+    }
+}
+
+// Non-negative integers up to `max`
+#[cfg(feature = "server")]
+pub(super) fn count(
+    key: &str,
+    value: &Value,
+    source: &Source,
+    max: u64,
+) -> Result<u64, ConfigError> {
+    let count = match value {
+        Value::Integer(count) => u64::try_from(*count).ok(),
+        Value::String(s) => s.trim().parse::<u64>().ok(),
+        other => return Err(mismatch(key, other, source, "a number")),
+    };
+
+    count.filter(|count| *count <= max).ok_or_else(|| {
+        ConfigError::new(
+            source,
+            Some(key),
+            format!("expected a number from 0 to {}, found {}", max, value),
+        )
+    })
+}
+
+// Arrays in files, comma separated strings everywhere else
+#[cfg(feature = "server")]
+pub(super) fn list(key: &str, value: &Value, source: &Source) -> Result<Vec<String>, ConfigError> {
+    match value {
+        Value::Array(items) => items
+            .iter()
+            .map(|item| match item {
+                Value::String(s) => Ok(s.trim().to_string()),
+                other => Err(mismatch(key, other, source, "a list of strings")),
+            })
+            .filter(|item| !matches!(item, Ok(s) if s.is_empty()))
+            .collect(),
+        Value::String(s) => Ok(s
+            .split(',')
+            .map(|item| item.trim().to_string())
+            .filter(|item| !item.is_empty())
+            .collect()),
+        other => Err(mismatch(key, other, source, "a list of strings")),
+    }
+}
+
+// mDNS service types look like `_name._tcp.local.`
+pub(super) fn service_type(
+    key: &str,
+    value: &Value,
+    source: &Source,
+) -> Result<String, ConfigError> {
+    let service_type = string(key, value, source)?;
+    let valid = service_type.starts_with('_')
+        && (service_type.ends_with("._tcp.local.") || service_type.ends_with("._udp.local."));
+
+    if valid {
+        Ok(service_type)
+    } else {
+        Err(ConfigError::new(
+            source,
+            Some(key),
+            format!(
+                "expected a service type like {}, found \"{}\"",
+                DEFAULT_SERVICE_TYPE, service_type
+            ),
+        ))
+    }
+}
+
+// A single DNS label, advertised as `<hostname>.local`
+#[cfg(feature = "server")]
+pub(super) fn hostname(key: &str, value: &Value, source: &Source) -> Result<String, ConfigError> {
+    let hostname = string(key, value, source)?;
+    let valid = hostname.len() <= 63
+        && !hostname.starts_with('-')
+        && !hostname.ends_with('-')
+        && hostname
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-');
+
+    if valid {
+        Ok(hostname)
+    } else {
+        Err(ConfigError::new(
+            source,
+            Some(key),
+            format!(
+                "expected up to 63 letters, digits or inner hyphens, found \"{}\"",
+                hostname
+            ),
+        ))
+    }
+}
+
+// Instance names are free form but must fit in a DNS label
+#[cfg(feature = "server")]
+pub(super) fn instance_name(
+    key: &str,
+    value: &Value,
+    source: &Source,
+) -> Result<String, ConfigError> {
+    let name = string(key, value, source)?;
+    if name.len() <= 63 && !name.contains('.') {
+        Ok(name)
+    } else {
+        Err(ConfigError::new(
+            source,
+            Some(key),
+            format!("expected up to 63 bytes without dots, found \"{}\"", name),
+        ))
+    }
+}
+
+// Permission bits, octal in strings so "660" reads as expected
+#[cfg(feature = "server")]
+pub(super) fn mode(key: &str, value: &Value, source: &Source) -> Result<u32, ConfigError> {
+    let mode = match value {
+        Value::Integer(mode) => u32::try_from(*mode).ok(),
+        Value::String(s) => {
+            let digits = s.trim().trim_start_matches("0o");
+            u32::from_str_radix(digits, 8).ok()
+        }
+        other => return Err(mismatch(key, other, source, "an octal file mode")),
+    };
+
+    mode.filter(|mode| *mode <= 0o777).ok_or_else(|| {
+        ConfigError::new(
+            source,
+            Some(key),
+            format!("expected an octal file mode like 660, found {}", value),
+        )
+    })
+}
+
+// Group names resolve through NSS, numeric ids are taken as is
+#[cfg(feature = "server")]
+pub(super) fn lookup_group(name: &str) -> Option<u32> {
+    if let Ok(gid) = name.parse::<u32>() {
+        return Some(gid);
+    }
+
+    let name = std::ffi::CString::new(name).ok()?;
+    let mut group: libc::group = unsafe { std::mem::zeroed() };
+    let mut result: *mut libc::group = std::ptr::null_mut();
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    // SAFETY: every pointer refers to a live local and the buffer length is passed along
+    let status = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut group,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+
+    (status == 0 && !result.is_null()).then_some(group.gr_gid)
+}
+
+#[cfg(feature = "server")]
+pub(super) fn group(key: &str, value: &Value, source: &Source) -> Result<String, ConfigError> {
+    let group = string(key, value, source)?;
+    match lookup_group(&group) {
+        Some(_) => Ok(group),
+        None => Err(ConfigError::new(
+            source,
+            Some(key),
+            format!("unknown group \"{}\"", group),
+        )),
+    }
+}
+
+// Any string setting with a `FromStr` representation
+#[cfg(feature = "server")]
+pub(super) fn parsed<T: core::str::FromStr>(
+    key: &str,
+    value: &Value,
+    source: &Source,
+    expected: &str,
+) -> Result<T, ConfigError> {
+    let s = string(key, value, source)?;
+    s.trim().parse::<T>().map_err(|_| {
+        ConfigError::new(
+            source,
+            Some(key),
+            format!("expected {}, found \"{}\"", expected, s),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct TestConfig {
+        port: Setting<u16>,
+        socket_path: Setting<String>,
+    }
+
+    impl Layered for TestConfig {
+        fn apply(&mut self, key: &str, value: &Value, source: &Source) -> Result<(), ConfigError> {
+            match key {
+                "server_port" => self.port.set(port(key, value, source)?, source),
+                "socket_path" => self.socket_path.set(string(key, value, source)?, source),
+                _ => {}
+            }
+
+            Ok(())
+        }
+    }
+
+    fn write_config(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "remote_unlock_config_{}_{}.toml",
+            std::process::id(),
+            name
+        ));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_precedence() {
+        let system = write_config(
+            "system",
+            "server_port = 1000\nsocket_path = \"/run/ru.sock\"",
+        );
+        let user = write_config("user", "server_port = 2000");
+        let files = [system.clone(), user.clone()];
+
+        let config: TestConfig = load_from(&files, |_| None, &Overrides::new()).unwrap();
+        assert_eq!(config.port.value(), Some(&2000));
+        assert_eq!(config.port.source(), &Source::File(user.clone()));
+        assert_eq!(config.socket_path.source(), &Source::File(system.clone()));
+
+        let env = |name: &str| (name == "REMOTE_UNLOCK_SERVER_PORT").then(|| "3000".to_string());
+        let config: TestConfig = load_from(&files, env, &Overrides::new()).unwrap();
+        assert_eq!(config.port.value(), Some(&3000));
+        assert_eq!(
+            config.port.source(),
+            &Source::Env("REMOTE_UNLOCK_SERVER_PORT")
+        );
+
+        let overrides = Overrides::new().assign("server_port=4000").unwrap();
+        let config: TestConfig = load_from(&files, env, &overrides).unwrap();
+        assert_eq!(config.port.value(), Some(&4000));
+        assert_eq!(config.port.source(), &Source::Flag);
+
+        std::fs::remove_file(system).unwrap();
+        std::fs::remove_file(user).unwrap();
+    }
+
+    #[test]
+    fn test_missing_files() {
+        let missing = std::env::temp_dir().join("remote_unlock_config_missing.toml");
+
+        let config: TestConfig =
+            load_from(std::slice::from_ref(&missing), |_| None, &Overrides::new()).unwrap();
+        assert_eq!(config.port.value(), None);
+        assert_eq!(config.port.source(), &Source::Default);
+
+        let overrides = Overrides::new().file(&missing);
+        assert!(load_from::<TestConfig>(&[], |_| None, &overrides).is_err());
+    }
+
+    #[test]
+    fn test_readable_errors() {
+        let env = |name: &str| (name == "REMOTE_UNLOCK_SERVER_PORT").then(|| "http".to_string());
+        let err = load_from::<TestConfig>(&[], env, &Overrides::new())
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "env REMOTE_UNLOCK_SERVER_PORT: server_port: expected a port number, found \"http\""
+        );
+
+        let file = write_config("errors", "server_port = 70000");
+        let err = load_from::<TestConfig>(std::slice::from_ref(&file), |_| None, &Overrides::new())
+            .err()
+            .unwrap();
+        assert_eq!(err.key(), Some("server_port"));
+        assert!(err
+            .to_string()
+            .ends_with("between 1 and 65535, found 70000"));
+
+        std::fs::write(&file, "sever_port = 8142").unwrap();
+        let err = load_from::<TestConfig>(std::slice::from_ref(&file), |_| None, &Overrides::new())
+            .err()
+            .unwrap();
+        assert_eq!(err.key(), Some("sever_port"));
+        assert!(err.to_string().ends_with("unknown setting"));
+
+        std::fs::remove_file(file).unwrap();
+    }
+}
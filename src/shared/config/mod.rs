@@ -0,0 +1,28 @@
+#[cfg(feature = "client")]
+mod client;
+#[cfg(any(feature = "client", feature = "server"))]
+mod layer;
+#[cfg(feature = "server")]
+mod server;
+
+#[cfg(feature = "client")]
+pub use client::ClientConfig;
+#[cfg(any(feature = "client", feature = "server"))]
+pub use layer::{config_files, ConfigError, Entry, Overrides, Setting, Source};
+#[cfg(feature = "server")]
+pub use server::{ServerConfig, StorageBackend};
+
+// Protocol limits shared by every build, runtime settings live in the
+// client and server configs
+pub struct Config;
+
+impl Config {
+    pub const MAX_PACKET_SIZE: usize = 1024 * 4;
+    pub const BUFFER_SIZE: usize = 1024;
+    pub const ERROR_STRING_SIZE: usize = 64;
+    pub const STREAM_RETRY_DELAY_MS: u64 = 100;
+    pub const PROTOCOL_VERSION: u16 = 1;
+    // Versions of the local control socket protocol the daemon speaks
+    pub const CONTROL_PROTOCOL_VERSION: u16 = 1;
+    pub const MIN_CONTROL_PROTOCOL_VERSION: u16 = 1;
+}
@@ -0,0 +1,666 @@
+use crate::types::{Error, ErrorKind};
+#[cfg(feature = "evdev")]
+use evdev::Device;
+use log::warn;
+use std::{
+    net::IpAddr,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+use toml::Value;
+
+use super::layer::{self, *};
+
+const DEFAULT_STORAGE_DIR: &str = "./var/lib/remote_unlock";
+const DEFAULT_SERVER_IP: &str = "0.0.0.0";
+const DEFAULT_LOG_LEVEL: log::LevelFilter = log::LevelFilter::Info;
+const FALLBACK_HOSTNAME: &str = "localhost";
+// Only the daemon's own user may connect unless a control group is set
+const DEFAULT_SOCKET_MODE: u32 = 0o600;
+const DEFAULT_GROUP_SOCKET_MODE: u32 = 0o660;
+const DATABASE_FILE: &str = "remote_unlock.redb";
+// Nonces behind the newest one a client may still use, one bit each
+const MAX_NONCE_WINDOW: u32 = 128;
+// Seconds a timestamped request stays valid, and how far clocks may drift
+const DEFAULT_TIMESTAMP_WINDOW: u32 = 300;
+const MAX_TIMESTAMP_WINDOW: u32 = 24 * 60 * 60;
+const DEFAULT_CLOCK_SKEW: u32 = 30;
+const MAX_CLOCK_SKEW: u32 = 60 * 60;
+
+// Where devices, nonces and the audit log are kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StorageBackend {
+    // Key, nonce and metadata files under the storage directory
+    File,
+    // A single transactional database file
+    #[default]
+    Database,
+}
+
+impl core::str::FromStr for StorageBackend {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "file" => Ok(StorageBackend::File),
+            "database" => Ok(StorageBackend::Database),
+            _ => Err(()),
+        }
+    }
+}
+
+impl core::fmt::Display for StorageBackend {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            StorageBackend::File => write!(f, "file"),
+            StorageBackend::Database => write!(f, "database"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ServerConfig {
+    socket_path: Setting<String>,
+    socket_mode: Setting<u32>,
+    control_group: Setting<String>,
+    storage_dir: Setting<String>,
+    storage_backend: Setting<StorageBackend>,
+    nonce_window: Setting<u32>,
+    max_nonce_jump: Setting<u64>,
+    require_timestamp: Setting<bool>,
+    timestamp_window: Setting<u32>,
+    clock_skew: Setting<u32>,
+    policy_file: Setting<String>,
+    identity_file: Setting<String>,
+    server_ip: Setting<String>,
+    server_port: Setting<u16>,
+    log_level: Setting<log::LevelFilter>,
+    sway_socket_path: Setting<String>,
+    hostname: Setting<String>,
+    service_type: Setting<String>,
+    instance_name: Setting<String>,
+    mdns_interfaces: Setting<Vec<String>>,
+    mdns_ipv4: Setting<bool>,
+    mdns_ipv6: Setting<bool>,
+}
+
+impl ServerConfig {
+    // Layers the config files, environment and command line overrides
+    pub fn load(overrides: &Overrides) -> Result<ServerConfig, ConfigError> {
+        layer::load(overrides)
+    }
+
+    // Same as `load` with the given files and environment in place of the
+    // standard ones
+    pub fn load_from(
+        files: &[PathBuf],
+        env: impl Fn(&str) -> Option<String>,
+        overrides: &Overrides,
+    ) -> Result<ServerConfig, ConfigError> {
+        layer::load_from(files, env, overrides)
+    }
+
+    // Effective settings and where they came from
+    pub fn entries(&self) -> Vec<Entry> {
+        let interfaces = match self.mdns_interfaces() {
+            [] => "all".to_string(),
+            interfaces => interfaces.join(","),
+        };
+        let sway_socket_path = self
+            .sway_socket_path
+            .value()
+            .map(String::as_str)
+            .unwrap_or("detected at runtime");
+
+        vec![
+            Entry::new("socket_path", self.socket_path(), &self.socket_path),
+            Entry::new(
+                "socket_mode",
+                format!("{:04o}", self.socket_mode()),
+                &self.socket_mode,
+            ),
+            Entry::new(
+                "control_group",
+                self.control_group.value().map_or("none", String::as_str),
+                &self.control_group,
+            ),
+            Entry::new("server_ip", self.server_ip(), &self.server_ip),
+            Entry::new("server_port", self.server_port(), &self.server_port),
+            Entry::new("storage_dir", self.storage_dir(), &self.storage_dir),
+            Entry::new(
+                "storage_backend",
+                self.storage_backend(),
+                &self.storage_backend,
+            ),
+            Entry::new("nonce_window", self.nonce_window(), &self.nonce_window),
+            Entry::new(
+                "max_nonce_jump",
+                self.max_nonce_jump()
+                    .map_or("unlimited".to_string(), |jump| jump.to_string()),
+                &self.max_nonce_jump,
+            ),
+            Entry::new(
+                "require_timestamp",
+                self.require_timestamp(),
+                &self.require_timestamp,
+            ),
+            Entry::new(
+                "timestamp_window",
+                self.timestamp_window(),
+                &self.timestamp_window,
+            ),
+            Entry::new("clock_skew", self.clock_skew(), &self.clock_skew),
+            Entry::new(
+                "policy_file",
+                self.policy_file().unwrap_or("none"),
+                &self.policy_file,
+            ),
+            Entry::new(
+                "identity_file",
+                self.identity_key_path().display(),
+                &self.identity_file,
+            ),
+            Entry::new(
+                "log_level",
+                self.log_level().as_str().to_lowercase(),
+                &self.log_level,
+            ),
+            Entry::new("hostname", self.server_hostname(), &self.hostname),
+            Entry::new("service_type", self.service_type(), &self.service_type),
+            Entry::new(
+                "instance_name",
+                self.mdns_instance_name(),
+                &self.instance_name,
+            ),
+            Entry::new("mdns_interfaces", interfaces, &self.mdns_interfaces),
+            Entry::new("mdns_ipv4", self.mdns_ipv4(), &self.mdns_ipv4),
+            Entry::new("mdns_ipv6", self.mdns_ipv6(), &self.mdns_ipv6),
+            Entry::new("sway_socket_path", sway_socket_path, &self.sway_socket_path),
+        ]
+    }
+
+    // Copies settings that only apply on restart from the running config,
+    // returning the keys whose new values were ignored
+    pub fn keep_restart_settings(&mut self, running: &ServerConfig) -> Vec<&'static str> {
+        let mut ignored = Vec::new();
+        if self.socket_path() != running.socket_path() {
+            ignored.push("socket_path");
+        }
+        if self.socket_mode() != running.socket_mode() {
+            ignored.push("socket_mode");
+        }
+        if self.control_group.value() != running.control_group.value() {
+            ignored.push("control_group");
+        }
+        if self.storage_dir() != running.storage_dir() {
+            ignored.push("storage_dir");
+        }
+        if self.storage_backend() != running.storage_backend() {
+            ignored.push("storage_backend");
+        }
+        if self.identity_key_path() != running.identity_key_path() {
+            ignored.push("identity_file");
+        }
+        if self.server_ip() != running.server_ip() {
+            ignored.push("server_ip");
+        }
+        if self.server_port() != running.server_port() {
+            ignored.push("server_port");
+        }
+
+        self.socket_path = running.socket_path.clone();
+        self.socket_mode = running.socket_mode.clone();
+        self.control_group = running.control_group.clone();
+        self.storage_dir = running.storage_dir.clone();
+        self.storage_backend = running.storage_backend.clone();
+        self.identity_file = running.identity_file.clone();
+        self.server_ip = running.server_ip.clone();
+        self.server_port = running.server_port.clone();
+        ignored
+    }
+
+    // Whether the unlock backend has to be set up again for this config
+    pub fn backend_changed(&self, running: &ServerConfig) -> bool {
+        self.sway_socket_path.value() != running.sway_socket_path.value()
+    }
+
+    fn gethostname() -> Option<String> {
+        let mut buf = [0u8; 256];
+        // SAFETY: the buffer outlives the call and its length is passed along
+        let result = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) };
+        if result != 0 {
+            return None;
+        }
+
+        let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+        std::str::from_utf8(&buf[..len]).ok().map(str::to_string)
+    }
+
+    // Only the first label is used, mDNS supplies the .local domain
+    fn try_detect_hostname() -> Option<String> {
+        let detected = Self::gethostname().or_else(|| {
+            warn!("gethostname failed, reading /etc/hostname");
+            std::fs::read_to_string("/etc/hostname").ok()
+        })?;
+
+        detected
+            .trim()
+            .split('.')
+            .next()
+            .filter(|name| !name.is_empty())
+            .map(str::to_string)
+    }
+
+    #[cfg(feature = "evdev")]
+    fn try_detect_wake_device_path() -> Option<PathBuf> {
+        let mut devices = evdev::enumerate();
+        let lid_device = devices.find(|(_, device)| {
+            device
+                .supported_keys()
+                .is_some_and(|keys| keys.contains(evdev::Key::KEY_WAKEUP))
+        });
+
+        match lid_device {
+            Some((pb, _)) => Some(pb),
+            None => {
+                warn!("Failed to detect lid device");
+                None
+            }
+        }
+    }
+    fn try_detect_sway_socket_path() -> Option<PathBuf> {
+        let uid = match std::fs::metadata("/proc/self").map(|m| m.uid()) {
+            Ok(uid) => uid,
+            Err(_) => return None,
+        };
+        std::fs::read_dir("/run/user")
+            .map(|mut entries| {
+                let first_sock = entries.find(|entry| {
+                    entry
+                        .as_ref()
+                        .map(|entry| {
+                            entry
+                                .file_name()
+                                .to_str()
+                                .map(|name| name.starts_with(format!("sway-ipc.{}.", uid).as_str()))
+                                .unwrap_or(false)
+                        })
+                        .unwrap_or(false)
+                });
+
+                match first_sock {
+                    Some(entry) => entry.ok().map(|entry| entry.path()),
+                    None => None,
+                }
+            })
+            .unwrap_or(None)
+    }
+
+    pub fn sway_socket_path(&self) -> Result<PathBuf, Error> {
+        match self.sway_socket_path.value() {
+            Some(path) => Ok(path.into()),
+            None => {
+                warn!("SWAYSOCK environment variable not set, attempting to construct path");
+                match Self::try_detect_sway_socket_path() {
+                    Some(path) => Ok(path),
+                    None => {
+                        warn!("Failed to detect sway socket path");
+                        Err(Error::new(
+                            ErrorKind::SwaylockBackend,
+                            Some("Failed to detect sway socket path"),
+                        ))
+                    }
+                }
+            }
+        }
+    }
+
+    // Enumerates input devices, only the daemon should call this
+    #[cfg(feature = "evdev")]
+    pub fn wake_device(&self) -> Option<Device> {
+        match Self::try_detect_wake_device_path() {
+            Some(path) => Device::open(path).ok(),
+            None => None,
+        }
+    }
+
+    pub fn socket_path(&self) -> &str {
+        match self.socket_path.value() {
+            Some(path) => path,
+            None => DEFAULT_SOCKET_PATH,
+        }
+    }
+
+    pub fn socket_mode(&self) -> u32 {
+        match (self.socket_mode.value(), self.control_group.value()) {
+            (Some(mode), _) => *mode,
+            (None, Some(_)) => DEFAULT_GROUP_SOCKET_MODE,
+            (None, None) => DEFAULT_SOCKET_MODE,
+        }
+    }
+
+    // Group allowed on the control socket besides the daemon's user
+    pub fn control_gid(&self) -> Option<u32> {
+        let group = self.control_group.value()?;
+        let gid = layer::lookup_group(group);
+        if gid.is_none() {
+            warn!("Control group {} no longer exists", group);
+        }
+        gid
+    }
+
+    pub fn storage_dir(&self) -> &str {
+        match self.storage_dir.value() {
+            Some(path) => path,
+            None => DEFAULT_STORAGE_DIR,
+        }
+    }
+
+    pub fn keys_dir(&self) -> PathBuf {
+        Path::new(self.storage_dir()).join("keys")
+    }
+
+    pub fn nonce_dir(&self) -> PathBuf {
+        Path::new(self.storage_dir()).join("nonces")
+    }
+
+    // Device metadata and the audit log of the file backend
+    pub fn devices_dir(&self) -> PathBuf {
+        Path::new(self.storage_dir()).join("devices")
+    }
+
+    pub fn storage_backend(&self) -> StorageBackend {
+        self.storage_backend.value().copied().unwrap_or_default()
+    }
+
+    pub fn database_path(&self) -> PathBuf {
+        Path::new(self.storage_dir()).join(DATABASE_FILE)
+    }
+
+    // How far behind the newest nonce a request may be, 0 only accepts
+    // nonces past the newest
+    pub fn nonce_window(&self) -> u32 {
+        self.nonce_window.value().copied().unwrap_or(0)
+    }
+
+    // How far past the next expected nonce a request may jump
+    pub fn max_nonce_jump(&self) -> Option<u64> {
+        self.max_nonce_jump.value().copied()
+    }
+
+    // Refuses signed requests without a timestamp, which stay valid until
+    // a newer nonce is used
+    pub fn require_timestamp(&self) -> bool {
+        self.require_timestamp.value().copied().unwrap_or(false)
+    }
+
+    // How many seconds old a timestamped request may be
+    pub fn timestamp_window(&self) -> u32 {
+        self.timestamp_window
+            .value()
+            .copied()
+            .unwrap_or(DEFAULT_TIMESTAMP_WINDOW)
+    }
+
+    // How many seconds a client's clock may be ahead or behind
+    pub fn clock_skew(&self) -> u32 {
+        self.clock_skew
+            .value()
+            .copied()
+            .unwrap_or(DEFAULT_CLOCK_SKEW)
+    }
+
+    // Per device policies, every device gets the defaults without one
+    pub fn policy_file(&self) -> Option<&str> {
+        self.policy_file.value().map(String::as_str)
+    }
+
+    // The registry key derives from the identity, keeping it outside the
+    // storage dir means writing there is not enough to forge seals
+    pub fn identity_key_path(&self) -> PathBuf {
+        match self.identity_file.value() {
+            Some(path) => PathBuf::from(path),
+            None => Path::new(self.storage_dir()).join("identity.pem"),
+        }
+    }
+
+    pub fn service_type(&self) -> &str {
+        match self.service_type.value() {
+            Some(service_type) => service_type,
+            None => DEFAULT_SERVICE_TYPE,
+        }
+    }
+
+    // Interfaces to advertise on, empty means all
+    pub fn mdns_interfaces(&self) -> &[String] {
+        match self.mdns_interfaces.value() {
+            Some(interfaces) => interfaces,
+            None => &[],
+        }
+    }
+
+    pub fn mdns_ipv4(&self) -> bool {
+        self.mdns_ipv4.value().copied().unwrap_or(true)
+    }
+
+    pub fn mdns_ipv6(&self) -> bool {
+        self.mdns_ipv6.value().copied().unwrap_or(true)
+    }
+
+    pub fn server_hostname(&self) -> String {
+        if let Some(hostname) = self.hostname.value() {
+            return hostname.clone();
+        }
+
+        Self::try_detect_hostname().unwrap_or_else(|| {
+            warn!("Failed to detect hostname, using {}", FALLBACK_HOSTNAME);
+            FALLBACK_HOSTNAME.to_string()
+        })
+    }
+
+    // Name shown when browsing, may be changed at runtime on collisions
+    pub fn mdns_instance_name(&self) -> String {
+        match self.instance_name.value() {
+            Some(name) => name.clone(),
+            None => self.server_hostname(),
+        }
+    }
+
+    pub fn server_ip(&self) -> &str {
+        match self.server_ip.value() {
+            Some(server_ip) => server_ip,
+            None => DEFAULT_SERVER_IP,
+        }
+    }
+
+    pub fn server_port(&self) -> u16 {
+        self.server_port
+            .value()
+            .copied()
+            .unwrap_or(DEFAULT_SERVER_PORT)
+    }
+
+    pub fn log_level(&self) -> log::LevelFilter {
+        self.log_level.value().copied().unwrap_or(DEFAULT_LOG_LEVEL)
+    }
+}
+
+impl Layered for ServerConfig {
+    fn apply(&mut self, key: &str, value: &Value, source: &Source) -> Result<(), ConfigError> {
+        match key {
+            "socket_path" => self.socket_path.set(string(key, value, source)?, source),
+            "socket_mode" => self.socket_mode.set(mode(key, value, source)?, source),
+            "control_group" => self.control_group.set(group(key, value, source)?, source),
+            "storage_dir" => self.storage_dir.set(string(key, value, source)?, source),
+            "storage_backend" => self
+                .storage_backend
+                .set(parsed(key, value, source, "file or database")?, source),
+            "nonce_window" => self.nonce_window.set(
+                count(key, value, source, MAX_NONCE_WINDOW.into())? as u32,
+                source,
+            ),
+            "max_nonce_jump" => self
+                .max_nonce_jump
+                .set(count(key, value, source, u64::MAX)?, source),
+            "require_timestamp" => self
+                .require_timestamp
+                .set(boolean(key, value, source)?, source),
+            "timestamp_window" => self.timestamp_window.set(
+                count(key, value, source, MAX_TIMESTAMP_WINDOW.into())? as u32,
+                source,
+            ),
+            "clock_skew" => self.clock_skew.set(
+                count(key, value, source, MAX_CLOCK_SKEW.into())? as u32,
+                source,
+            ),
+            "policy_file" => self.policy_file.set(string(key, value, source)?, source),
+            "identity_file" => self.identity_file.set(string(key, value, source)?, source),
+            "server_ip" => {
+                let ip = parsed::<IpAddr>(key, value, source, "an IP address")?;
+                self.server_ip.set(ip.to_string(), source)
+            }
+            "server_port" => self.server_port.set(port(key, value, source)?, source),
+            "log_level" => self.log_level.set(
+                parsed(key, value, source, "off, error, warn, info, debug or trace")?,
+                source,
+            ),
+            "sway_socket_path" => self
+                .sway_socket_path
+                .set(string(key, value, source)?, source),
+            "hostname" => self.hostname.set(hostname(key, value, source)?, source),
+            "service_type" => self
+                .service_type
+                .set(service_type(key, value, source)?, source),
+            "instance_name" => self
+                .instance_name
+                .set(instance_name(key, value, source)?, source),
+            "mdns_interfaces" => self.mdns_interfaces.set(list(key, value, source)?, source),
+            "mdns_ipv4" => self.mdns_ipv4.set(boolean(key, value, source)?, source),
+            "mdns_ipv6" => self.mdns_ipv6.set(boolean(key, value, source)?, source),
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Defaults and overrides only, whatever the machine has configured
+    fn load(overrides: Overrides) -> Result<ServerConfig, ConfigError> {
+        ServerConfig::load_from(&[], |_| None, &overrides)
+    }
+
+    #[test]
+    fn test_keep_restart_settings() {
+        let running = load(Overrides::new().set("server_port", "9000")).unwrap();
+        let mut reloaded = load(
+            Overrides::new()
+                .set("server_port", "9001")
+                .set("log_level", "debug"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            reloaded.keep_restart_settings(&running),
+            vec!["server_port"]
+        );
+        assert_eq!(reloaded.server_port(), 9000);
+        assert_eq!(reloaded.log_level(), log::LevelFilter::Debug);
+        assert!(!reloaded.backend_changed(&running));
+
+        let moved = load(Overrides::new().set("sway_socket_path", "/tmp/sway.sock")).unwrap();
+        assert!(moved.backend_changed(&running));
+    }
+
+    #[test]
+    fn test_socket_mode() {
+        assert_eq!(load(Overrides::new()).unwrap().socket_mode(), 0o600);
+        let with_group = load(Overrides::new().set("control_group", "0")).unwrap();
+        assert_eq!(with_group.socket_mode(), 0o660);
+        assert_eq!(with_group.control_gid(), Some(0));
+
+        let explicit = load(Overrides::new().set("socket_mode", "0640")).unwrap();
+        assert_eq!(explicit.socket_mode(), 0o640);
+        assert!(load(Overrides::new().set("socket_mode", "999")).is_err());
+        assert!(load(Overrides::new().set("control_group", "no-such-group-here")).is_err());
+    }
+
+    #[test]
+    fn test_storage_backend() {
+        let default = load(Overrides::new()).unwrap();
+        assert_eq!(default.storage_backend(), StorageBackend::Database);
+        let file = load(Overrides::new().set("storage_backend", "file")).unwrap();
+        assert_eq!(file.storage_backend(), StorageBackend::File);
+        assert!(load(Overrides::new().set("storage_backend", "sqlite")).is_err());
+    }
+
+    #[test]
+    fn test_nonce_policy() {
+        let default = load(Overrides::new()).unwrap();
+        assert_eq!(default.nonce_window(), 0);
+        assert_eq!(default.max_nonce_jump(), None);
+
+        let set = load(
+            Overrides::new()
+                .set("nonce_window", "64")
+                .set("max_nonce_jump", "1000"),
+        )
+        .unwrap();
+        assert_eq!(set.nonce_window(), 64);
+        assert_eq!(set.max_nonce_jump(), Some(1000));
+        assert!(load(Overrides::new().set("nonce_window", "129")).is_err());
+        assert!(load(Overrides::new().set("max_nonce_jump", "-1")).is_err());
+    }
+
+    #[test]
+    fn test_timestamp_policy() {
+        let default = load(Overrides::new()).unwrap();
+        assert!(!default.require_timestamp());
+        assert_eq!(default.timestamp_window(), 300);
+        assert_eq!(default.clock_skew(), 30);
+
+        let set = load(
+            Overrides::new()
+                .set("require_timestamp", "yes")
+                .set("timestamp_window", "60")
+                .set("clock_skew", "0"),
+        )
+        .unwrap();
+        assert!(set.require_timestamp());
+        assert_eq!(set.timestamp_window(), 60);
+        assert_eq!(set.clock_skew(), 0);
+        assert!(load(Overrides::new().set("clock_skew", "3601")).is_err());
+        assert!(load(Overrides::new().set("timestamp_window", "86401")).is_err());
+    }
+
+    #[test]
+    fn test_policy_file() {
+        let default = load(Overrides::new()).unwrap();
+        assert_eq!(default.policy_file(), None);
+
+        let set =
+            load(Overrides::new().set("policy_file", "/etc/remote-unlock/policy.toml")).unwrap();
+        assert_eq!(set.policy_file(), Some("/etc/remote-unlock/policy.toml"));
+    }
+
+    #[test]
+    fn test_identity_file() {
+        let default = load(Overrides::new()).unwrap();
+        assert_eq!(
+            default.identity_key_path(),
+            Path::new(default.storage_dir()).join("identity.pem")
+        );
+
+        let mut set =
+            load(Overrides::new().set("identity_file", "/etc/remote-unlock/identity.pem")).unwrap();
+        assert_eq!(
+            set.identity_key_path(),
+            PathBuf::from("/etc/remote-unlock/identity.pem")
+        );
+        assert_eq!(set.keep_restart_settings(&default), vec!["identity_file"]);
+        assert_eq!(set.identity_key_path(), default.identity_key_path());
+    }
+}
@@ -0,0 +1,101 @@
+use core::fmt::{self, Display};
+use core::str::FromStr;
+
+use serde::de::{Deserialize, Deserializer, Visitor};
+use serde::ser::{Serialize, Serializer};
+use sha2::{Digest, Sha256};
+
+use crate::prelude::*;
+
+// SHA-256 digest of a DER encoded SubjectPublicKeyInfo
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fingerprint([u8; Fingerprint::LEN]);
+
+impl Fingerprint {
+    pub const LEN: usize = 32;
+
+    pub fn of(der: &[u8]) -> Fingerprint {
+        let mut bytes = [0; Self::LEN];
+        bytes.copy_from_slice(&Sha256::digest(der));
+        Fingerprint(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; Self::LEN] {
+        &self.0
+    }
+}
+
+impl From<[u8; Fingerprint::LEN]> for Fingerprint {
+    fn from(bytes: [u8; Fingerprint::LEN]) -> Self {
+        Fingerprint(bytes)
+    }
+}
+
+impl Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.0.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Fingerprint {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            Error::new(
+                ErrorKind::InvalidFingerprint,
+                Some("Expected 64 hex characters"),
+            )
+        };
+
+        if s.len() != Self::LEN * 2 || !s.is_ascii() {
+            return Err(invalid());
+        }
+
+        let mut bytes = [0; Self::LEN];
+        for (idx, byte) in bytes.iter_mut().enumerate() {
+            let hex = &s[idx * 2..idx * 2 + 2];
+            *byte = u8::from_str_radix(hex, 16).map_err(|_| invalid())?;
+        }
+
+        Ok(Fingerprint(bytes))
+    }
+}
+
+impl Serialize for Fingerprint {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+struct FingerprintVisitor;
+
+impl<'de> Visitor<'de> for FingerprintVisitor {
+    type Value = Fingerprint;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a hex encoded SHA-256 fingerprint")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Fingerprint, E>
+    where
+        E: serde::de::Error,
+    {
+        Fingerprint::from_str(v).map_err(E::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Fingerprint {
+    fn deserialize<D>(deserializer: D) -> Result<Fingerprint, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(FingerprintVisitor)
+    }
+}
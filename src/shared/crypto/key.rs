@@ -1,20 +1,29 @@
+#[cfg(feature = "std")]
 use std::{io, path::Path};
 
 use crate::prelude::*;
 
 use super::der::SubjectPublicKeyInfoOwned;
-use der::{pem::PemLabel, Decode, DecodePem, Encode, PemWriter, SecretDocument};
+use super::fingerprint::Fingerprint;
+#[cfg(feature = "std")]
+use der::{pem::PemLabel, DecodePem, PemWriter, SecretDocument};
+use der::{Decode, Encode};
+#[cfg(feature = "std")]
 use pkcs8::{DecodePrivateKey, PrivateKeyInfo};
 
+#[cfg(feature = "std")]
 use spki::EncodePublicKey;
 
 pub struct PublicKey(SubjectPublicKeyInfoOwned);
+// Secret documents are heap allocated
+#[cfg(feature = "std")]
 pub struct PrivateKey(SecretDocument);
 
 impl PublicKey {
     pub fn inner(&self) -> &SubjectPublicKeyInfoOwned {
         &self.0
     }
+    #[cfg(feature = "std")]
     pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
         let bytes = ByteArray::<{ Config::BUFFER_SIZE }>::try_from(bytes)?;
         let spki = bytes
@@ -23,11 +32,13 @@ impl PublicKey {
         Ok(Self(spki))
     }
 
+    #[cfg(feature = "std")]
     pub fn from_pem(bytes: &[u8]) -> Result<Self, Error> {
         let spki = SubjectPublicKeyInfoOwned::from_pem(bytes)?;
         Ok(Self(spki))
     }
 
+    #[cfg(feature = "std")]
     pub fn read_pem_file(path: &Path) -> Result<Self, Error> {
         let mut file = std::fs::File::open(path)?;
         let mut bytes = ByteArray::<{ Config::BUFFER_SIZE }>::new();
@@ -43,6 +54,7 @@ impl PublicKey {
         Ok(Self(spki))
     }
 
+    #[cfg(feature = "std")]
     pub fn read_der_file(path: &Path) -> Result<Self, Error> {
         let mut file = std::fs::File::open(path)?;
         let mut bytes = ByteArray::<{ Config::BUFFER_SIZE }>::new();
@@ -53,6 +65,7 @@ impl PublicKey {
             .map_err(|err| err.into())
     }
 
+    #[cfg(feature = "std")]
     pub fn save_to_pem_file(&self, path: &Path) -> Result<(), Error> {
         debug!("Saving public key to file: {:?}", path);
         let mut pem = self.pem()?;
@@ -69,6 +82,7 @@ impl PublicKey {
         Ok(())
     }
 
+    #[cfg(feature = "std")]
     pub fn save_to_der_file(&self, path: &Path) -> Result<(), Error> {
         let mut options = std::fs::OpenOptions::new();
         options.write(true).create(true);
@@ -82,11 +96,12 @@ impl PublicKey {
     }
 
     pub fn der(&self) -> Result<ByteArray<{ Config::BUFFER_SIZE }>, Error> {
-        let mut buf = ByteArray::<{ Config::BUFFER_SIZE }>::new();
-        self.0.encode(&mut buf)?;
-        Ok(buf)
+        let mut buf = [0; Config::BUFFER_SIZE];
+        let der = self.0.encode_to_slice(&mut buf)?;
+        Ok(ByteArray::try_from(der)?)
     }
 
+    #[cfg(feature = "std")]
     pub fn pem(&self) -> Result<ByteArray<{ Config::BUFFER_SIZE }>, Error> {
         let mut buf = [0; { Config::BUFFER_SIZE }];
         let mut writer = PemWriter::new(
@@ -102,8 +117,13 @@ impl PublicKey {
 
         Ok(buf)
     }
+
+    pub fn fingerprint(&self) -> Result<Fingerprint, Error> {
+        Ok(Fingerprint::of(self.der()?.as_bytes()))
+    }
 }
 
+#[cfg(feature = "std")]
 impl PrivateKey {
     pub fn inner(&self) -> &SecretDocument {
         &self.0
@@ -1,3 +1,4 @@
 pub mod der;
+pub mod fingerprint;
 pub mod key;
 pub mod pem;
@@ -0,0 +1,66 @@
+use core::fmt::Display;
+
+// Why a device's policy refused an authenticated request. Sent back in the
+// `X-RemoteUnlock-Denial` header of a 403 so clients can tell them apart
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Denial {
+    ActionNotAllowed,
+    OutsideHours,
+    SubnetNotAllowed,
+    TooSoonAfterLock,
+    RateLimited,
+}
+
+impl Denial {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Denial::ActionNotAllowed => "action_not_allowed",
+            Denial::OutsideHours => "outside_hours",
+            Denial::SubnetNotAllowed => "subnet_not_allowed",
+            Denial::TooSoonAfterLock => "too_soon_after_lock",
+            Denial::RateLimited => "rate_limited",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Denial> {
+        match code {
+            "action_not_allowed" => Some(Denial::ActionNotAllowed),
+            "outside_hours" => Some(Denial::OutsideHours),
+            "subnet_not_allowed" => Some(Denial::SubnetNotAllowed),
+            "too_soon_after_lock" => Some(Denial::TooSoonAfterLock),
+            "rate_limited" => Some(Denial::RateLimited),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Denial {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Denial::ActionNotAllowed => write!(f, "action not allowed for this device"),
+            Denial::OutsideHours => write!(f, "outside the allowed hours"),
+            Denial::SubnetNotAllowed => write!(f, "source address not allowed"),
+            Denial::TooSoonAfterLock => write!(f, "too soon after locking"),
+            Denial::RateLimited => write!(f, "too many unlocks in the last hour"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_round_trip() {
+        for denial in [
+            Denial::ActionNotAllowed,
+            Denial::OutsideHours,
+            Denial::SubnetNotAllowed,
+            Denial::TooSoonAfterLock,
+            Denial::RateLimited,
+        ] {
+            assert_eq!(Denial::from_code(denial.code()), Some(denial));
+        }
+        assert_eq!(Denial::from_code("nope"), None);
+    }
+}
@@ -0,0 +1,166 @@
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+
+use crate::advertisement::Advertisement;
+use crate::prelude::*;
+
+#[derive(Debug, Clone)]
+pub struct DiscoveredService {
+    pub instance_name: String,
+    pub host_name: String,
+    pub addresses: Vec<IpAddr>,
+    pub port: u16,
+    // None when the TXT records could not be decoded
+    pub advertisement: Option<Advertisement>,
+}
+
+// Instance names are a single DNS label
+const MAX_INSTANCE_NAME_LEN: usize = 63;
+
+pub fn instance_name<'a>(service_type: &str, fullname: &'a str) -> &'a str {
+    fullname
+        .strip_suffix(service_type)
+        .map(|name| name.trim_end_matches('.'))
+        .unwrap_or(fullname)
+}
+
+impl DiscoveredService {
+    pub fn from_info(service_type: &str, info: &ServiceInfo) -> DiscoveredService {
+        let fullname = info.get_fullname();
+
+        let advertisement = match Advertisement::from_txt(|key| info.get_property_val_str(key)) {
+            Ok(advertisement) => Some(advertisement),
+            Err(e) => {
+                warn!("Failed to decode TXT records for {}: {}", fullname, e);
+                None
+            }
+        };
+
+        let mut addresses: Vec<IpAddr> = info.get_addresses().iter().copied().collect();
+        addresses.sort();
+
+        DiscoveredService {
+            instance_name: instance_name(service_type, fullname).to_string(),
+            host_name: info.get_hostname().to_string(),
+            addresses,
+            port: info.get_port(),
+            advertisement,
+        }
+    }
+}
+
+// First free name of `base`, `base (2)`, `base (3)`, ... as suggested by RFC 6762
+pub fn unique_instance_name(base: &str, taken: impl Fn(&str) -> bool) -> String {
+    if !taken(base) {
+        return base.to_string();
+    }
+
+    (2..)
+        .map(|n| {
+            let suffix = format!(" ({})", n);
+            let mut end = base.len().min(MAX_INSTANCE_NAME_LEN - suffix.len());
+            while !base.is_char_boundary(end) {
+                end -= 1;
+            }
+            format!("{}{}", &base[..end], suffix)
+        })
+        .find(|name| !taken(name))
+        .unwrap()
+}
+
+// Browses the LAN for daemons until the timeout elapses
+pub fn browse(service_type: &str, timeout: Duration) -> Result<Vec<DiscoveredService>, Error> {
+    let daemon = ServiceDaemon::new()?;
+    let receiver = daemon.browse(service_type)?;
+    let deadline = Instant::now() + timeout;
+    let mut services: Vec<DiscoveredService> = Vec::new();
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+
+        match receiver.recv_timeout(remaining) {
+            Ok(ServiceEvent::ServiceResolved(info)) => {
+                debug!("Resolved service: {}", info.get_fullname());
+                let service = DiscoveredService::from_info(service_type, &info);
+                services.retain(|s| s.instance_name != service.instance_name);
+                services.push(service);
+            }
+            Ok(ServiceEvent::ServiceRemoved(_, fullname)) => {
+                debug!("Service removed: {}", fullname);
+                let removed = instance_name(service_type, &fullname);
+                services.retain(|s| s.instance_name != removed);
+            }
+            Ok(event) => trace!("Ignoring mDNS event: {:?}", event),
+            Err(_) => break,
+        }
+    }
+
+    if let Err(e) = daemon.stop_browse(service_type) {
+        warn!("Failed to stop browsing: {}", e);
+    }
+    if let Err(e) = daemon.shutdown() {
+        warn!("Failed to shut down mDNS daemon: {}", e);
+    }
+
+    services.sort_by(|a, b| a.instance_name.cmp(&b.instance_name));
+    Ok(services)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::fingerprint::Fingerprint;
+
+    #[test]
+    fn test_unique_instance_name() {
+        assert_eq!(unique_instance_name("desk", |_| false), "desk");
+
+        let taken = ["desk", "desk (2)"];
+        assert_eq!(
+            unique_instance_name("desk", |name| taken.contains(&name)),
+            "desk (3)"
+        );
+
+        let long = "é".repeat(31);
+        let renamed = unique_instance_name(&long, |name| name == long);
+        assert!(renamed.len() <= MAX_INSTANCE_NAME_LEN);
+        assert!(renamed.ends_with(" (2)"));
+    }
+
+    #[test]
+    fn test_browse_local_service() {
+        let service_type = "_unlock-test._tcp.local.";
+        let fingerprint = Fingerprint::of(b"test server key");
+        let advertisement = Advertisement::new(fingerprint, false, true);
+        let properties = advertisement.txt_properties();
+
+        let info = ServiceInfo::new(
+            service_type,
+            "browse-test",
+            "browse-test.local.",
+            "",
+            9142,
+            &properties[..],
+        )
+        .unwrap()
+        .enable_addr_auto();
+
+        let server = ServiceDaemon::new().unwrap();
+        server.register(info).unwrap();
+
+        let services = browse(service_type, Duration::from_secs(3)).unwrap();
+        server.shutdown().unwrap();
+
+        let service = services
+            .iter()
+            .find(|s| s.instance_name == "browse-test")
+            .expect("registered service not discovered");
+        assert_eq!(service.port, 9142);
+        assert_eq!(service.advertisement, Some(advertisement));
+    }
+}
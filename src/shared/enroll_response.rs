@@ -6,6 +6,7 @@ pub struct EnrollmentResponse {
 }
 
 impl EnrollmentResponse {
+    #[cfg(feature = "std")]
     pub fn new() -> EnrollmentResponse {
         EnrollmentResponse {
             id: uuid::Uuid::new_v4(),
@@ -17,6 +18,7 @@ impl EnrollmentResponse {
     }
 }
 
+#[cfg(feature = "std")]
 impl Default for EnrollmentResponse {
     fn default() -> Self {
         Self::new()
@@ -1,37 +1,43 @@
-use chrono::{Duration, TimeZone, Utc};
+use chrono::{TimeZone, Utc};
 use core::fmt::Display;
 use rand::prelude::*;
 
-// 30 minutes
-#[allow(deprecated)]
-const CODE_LIFETIME: Duration = Duration::milliseconds(30 * 60 * 1000);
+use crate::clock::{Clock, SystemClock};
 
-#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+// 30 minutes, in seconds
+const CODE_LIFETIME: i64 = 30 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct EnrollmentCode {
     code: u32,
     expires: i64,
 }
 
 impl EnrollmentCode {
-    pub fn new() -> EnrollmentCode {
+    pub fn new(clock: &dyn Clock) -> EnrollmentCode {
         let mut rng = rand::thread_rng();
         let code = rng.gen_range(100_000..1_000_000);
-        let expires = (Utc::now() + CODE_LIFETIME).timestamp();
+        let expires = clock.now() + CODE_LIFETIME;
 
         EnrollmentCode { code, expires }
     }
 
-    pub fn expired(&self) -> bool {
-        Utc::now().timestamp() > self.expires
+    // Still valid during the second it expires at
+    pub fn expired(&self, clock: &dyn Clock) -> bool {
+        clock.now() > self.expires
     }
 
-    pub fn verify(&self, code: &u32) -> bool {
-        !self.expired() && self.code == *code
+    pub fn verify(&self, code: &u32, clock: &dyn Clock) -> bool {
+        !self.expired(clock) && self.code == *code
     }
 
     pub fn code(&self) -> u32 {
         self.code
     }
+
+    pub fn expires(&self) -> i64 {
+        self.expires
+    }
 }
 
 impl Display for EnrollmentCode {
@@ -44,6 +50,28 @@ impl Display for EnrollmentCode {
 
 impl Default for EnrollmentCode {
     fn default() -> Self {
-        Self::new()
+        Self::new(&SystemClock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    #[test]
+    fn test_expiry_edge() {
+        let clock = ManualClock::new(1_000_000);
+        let code = EnrollmentCode::new(&clock);
+        assert_eq!(code.expires(), 1_000_000 + CODE_LIFETIME);
+
+        clock.advance(CODE_LIFETIME);
+        assert!(!code.expired(&clock));
+        assert!(code.verify(&code.code(), &clock));
+        assert!(!code.verify(&(code.code() + 1), &clock));
+
+        clock.advance(1);
+        assert!(code.expired(&clock));
+        assert!(!code.verify(&code.code(), &clock));
     }
 }
@@ -1,15 +1,39 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+#[cfg(feature = "discovery")]
+pub mod advertisement;
+pub mod challenge_response;
+pub mod client;
+#[cfg(feature = "std")]
+pub mod clock;
 pub mod config;
 pub mod crypto;
+pub mod denial;
+#[cfg(feature = "discovery")]
+pub mod discovery;
 pub mod enroll_request;
 pub mod enroll_response;
+#[cfg(feature = "server")]
 pub mod enrollment_code;
+#[cfg(feature = "std")]
 pub mod messages;
 pub mod net;
+#[cfg(feature = "std")]
+pub mod pairing_uri;
+#[cfg(feature = "server")]
+pub mod policy;
+pub mod status_response;
+#[cfg(feature = "server")]
+pub mod store;
 pub mod types;
 pub mod unlock_request;
 
 pub mod prelude {
+    #[cfg(feature = "client")]
+    pub use crate::config::ClientConfig;
     pub use crate::config::Config;
+    #[cfg(feature = "server")]
+    pub use crate::config::ServerConfig;
     pub use crate::types::*;
     pub use log::{debug, error, info, trace, warn};
 }
@@ -1,20 +1,363 @@
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+use crate::pairing_uri::PairingUri;
+use crate::prelude::*;
+
+// Frames are a big endian u32 length followed by that many bytes of JSON
+const MAX_FRAME_SIZE: usize = 64 * 1024;
 
 #[repr(i8)]
-#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum MessageType {
+    Hello = 1,
     BeginEnroll = 10,
+    ListCodes = 11,
+    CancelCode = 12,
+    Status = 20,
+    Devices = 21,
+    Reload = 30,
     Terminate = 99,
 }
 
-#[derive(Serialize, Deserialize)]
+// Control protocol versions a peer speaks, sent in the opening hello
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionRange {
+    pub min: u16,
+    pub max: u16,
+}
+
+impl VersionRange {
+    pub fn supported() -> VersionRange {
+        VersionRange {
+            min: Config::MIN_CONTROL_PROTOCOL_VERSION,
+            max: Config::CONTROL_PROTOCOL_VERSION,
+        }
+    }
+
+    // Highest version both sides speak
+    pub fn negotiate(&self, other: &VersionRange) -> Option<u16> {
+        let version = self.max.min(other.max);
+        (version >= self.min.max(other.min)).then_some(version)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum MessageData {
+    Hello(VersionRange),
     BeginEnroll(()),
+    ListCodes(()),
+    CancelCode(u32),
+    Status(()),
+    Devices(()),
+    Reload(()),
     Terminate(()),
 }
 
-#[derive(Serialize, Deserialize)]
+impl MessageData {
+    pub fn message_type(&self) -> MessageType {
+        match self {
+            MessageData::Hello(_) => MessageType::Hello,
+            MessageData::BeginEnroll(_) => MessageType::BeginEnroll,
+            MessageData::ListCodes(_) => MessageType::ListCodes,
+            MessageData::CancelCode(_) => MessageType::CancelCode,
+            MessageData::Status(_) => MessageType::Status,
+            MessageData::Devices(_) => MessageType::Devices,
+            MessageData::Reload(_) => MessageType::Reload,
+            MessageData::Terminate(_) => MessageType::Terminate,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Message {
     message_type: MessageType,
     data: MessageData,
 }
+
+impl Message {
+    pub fn new(data: MessageData) -> Message {
+        Message {
+            message_type: data.message_type(),
+            data,
+        }
+    }
+
+    pub fn message_type(&self) -> MessageType {
+        self.message_type
+    }
+
+    pub fn data(&self) -> &MessageData {
+        &self.data
+    }
+
+    pub fn into_data(self) -> MessageData {
+        self.data
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingCode {
+    pub code: u32,
+    pub expires: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DaemonStatus {
+    pub version: String,
+    pub control_version: u16,
+    pub fingerprint: String,
+    pub uptime_secs: u64,
+    pub pending_codes: usize,
+    pub enrolled_devices: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Device {
+    pub id: String,
+    // Unix time the key was written
+    pub enrolled: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReplyData {
+    Hello(u16),
+    BeginEnroll(Box<PairingUri>),
+    ListCodes(Vec<PendingCode>),
+    // Whether the code was still pending
+    CancelCode(bool),
+    Status(DaemonStatus),
+    Devices(Vec<Device>),
+    Reload(()),
+    Terminate(()),
+    Error(String),
+}
+
+impl ReplyData {
+    // Errors answer any message type
+    pub fn message_type(&self) -> Option<MessageType> {
+        match self {
+            ReplyData::Hello(_) => Some(MessageType::Hello),
+            ReplyData::BeginEnroll(_) => Some(MessageType::BeginEnroll),
+            ReplyData::ListCodes(_) => Some(MessageType::ListCodes),
+            ReplyData::CancelCode(_) => Some(MessageType::CancelCode),
+            ReplyData::Status(_) => Some(MessageType::Status),
+            ReplyData::Devices(_) => Some(MessageType::Devices),
+            ReplyData::Reload(_) => Some(MessageType::Reload),
+            ReplyData::Terminate(_) => Some(MessageType::Terminate),
+            ReplyData::Error(_) => None,
+        }
+    }
+}
+
+// Replies carry the type of the message they answer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reply {
+    message_type: MessageType,
+    data: ReplyData,
+}
+
+impl Reply {
+    pub fn new(message_type: MessageType, data: ReplyData) -> Reply {
+        Reply { message_type, data }
+    }
+
+    pub fn error(message_type: MessageType, reason: impl ToString) -> Reply {
+        Reply::new(message_type, ReplyData::Error(reason.to_string()))
+    }
+
+    pub fn message_type(&self) -> MessageType {
+        self.message_type
+    }
+
+    // Checks the reply answers `message_type`, error replies become errors
+    pub fn into_data(self, message_type: MessageType) -> Result<ReplyData, Error> {
+        if let ReplyData::Error(reason) = self.data {
+            return Err(Error::new(ErrorKind::Server, Some(truncate(&reason))));
+        }
+        if self.message_type != message_type || self.data.message_type() != Some(message_type) {
+            return Err(Error::new(
+                ErrorKind::UnexpectedMessage,
+                Some("Reply does not match the request"),
+            ));
+        }
+
+        Ok(self.data)
+    }
+}
+
+// Error messages are capped, cut long reasons on a char boundary
+fn truncate(reason: &str) -> &str {
+    let mut end = reason.len().min(Config::ERROR_STRING_SIZE);
+    while !reason.is_char_boundary(end) {
+        end -= 1;
+    }
+    &reason[..end]
+}
+
+pub fn write_frame<T: Serialize>(writer: &mut impl Write, value: &T) -> Result<(), Error> {
+    let body = serde_json::to_vec(value)?;
+    if body.len() > MAX_FRAME_SIZE {
+        return Err(ErrorKind::OversizePacket.into());
+    }
+
+    writer.write_all(&(body.len() as u32).to_be_bytes())?;
+    writer.write_all(&body)?;
+    writer.flush()?;
+    Ok(())
+}
+
+// None when the peer closed the connection between frames
+pub fn read_frame<T: DeserializeOwned>(reader: &mut impl Read) -> Result<Option<T>, Error> {
+    let mut len = [0u8; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(ErrorKind::OversizePacket.into());
+    }
+
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+pub fn read_message(reader: &mut impl Read) -> Result<Option<Message>, Error> {
+    let message: Option<Message> = read_frame(reader)?;
+    match message {
+        Some(message) if message.message_type != message.data.message_type() => Err(Error::new(
+            ErrorKind::MalformedRequest,
+            Some("Message type does not match its data"),
+        )),
+        message => Ok(message),
+    }
+}
+
+pub fn read_reply(reader: &mut impl Read) -> Result<Reply, Error> {
+    read_frame(reader)?.ok_or(Error::new(
+        ErrorKind::UnexpectedMessage,
+        Some("Connection closed before the reply"),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::fingerprint::Fingerprint;
+
+    fn round_trip<T: Serialize + DeserializeOwned>(value: &T) -> T {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, value).unwrap();
+        let mut reader = &buf[..];
+        let decoded = read_frame(&mut reader).unwrap().unwrap();
+        assert!(reader.is_empty());
+        decoded
+    }
+
+    #[test]
+    fn test_message_round_trip() {
+        let messages = [
+            MessageData::Hello(VersionRange::supported()),
+            MessageData::BeginEnroll(()),
+            MessageData::ListCodes(()),
+            MessageData::CancelCode(123456),
+            MessageData::Status(()),
+            MessageData::Devices(()),
+            MessageData::Reload(()),
+            MessageData::Terminate(()),
+        ];
+
+        for data in messages {
+            let message = Message::new(data);
+            assert_eq!(round_trip(&message), message);
+        }
+    }
+
+    #[test]
+    fn test_reply_round_trip() {
+        let uri = PairingUri::parse(&format!(
+            "remote-unlock://host.local:8142/enroll?code=123456&expires=1700000000&fp={}",
+            Fingerprint::of(b"key")
+        ))
+        .unwrap();
+        let replies = [
+            Reply::new(MessageType::Hello, ReplyData::Hello(1)),
+            Reply::new(
+                MessageType::BeginEnroll,
+                ReplyData::BeginEnroll(Box::new(uri)),
+            ),
+            Reply::new(
+                MessageType::ListCodes,
+                ReplyData::ListCodes(vec![PendingCode {
+                    code: 123456,
+                    expires: 1700000000,
+                }]),
+            ),
+            Reply::new(MessageType::CancelCode, ReplyData::CancelCode(true)),
+            Reply::new(
+                MessageType::Status,
+                ReplyData::Status(DaemonStatus {
+                    version: "0.1.0".to_string(),
+                    control_version: 1,
+                    fingerprint: Fingerprint::of(b"key").to_string(),
+                    uptime_secs: 42,
+                    pending_codes: 1,
+                    enrolled_devices: 2,
+                }),
+            ),
+            Reply::new(
+                MessageType::Devices,
+                ReplyData::Devices(vec![Device {
+                    id: "0123456789abcdef0123456789abcdef".to_string(),
+                    enrolled: 1700000000,
+                }]),
+            ),
+            Reply::new(MessageType::Reload, ReplyData::Reload(())),
+            Reply::new(MessageType::Terminate, ReplyData::Terminate(())),
+            Reply::error(MessageType::Reload, "invalid setting"),
+        ];
+
+        for reply in replies {
+            assert_eq!(round_trip(&reply), reply);
+        }
+    }
+
+    #[test]
+    fn test_negotiate_version() {
+        let ours = VersionRange { min: 1, max: 3 };
+        assert_eq!(ours.negotiate(&VersionRange { min: 1, max: 2 }), Some(2));
+        assert_eq!(ours.negotiate(&VersionRange { min: 2, max: 5 }), Some(3));
+        assert_eq!(ours.negotiate(&VersionRange { min: 4, max: 5 }), None);
+    }
+
+    #[test]
+    fn test_reply_must_match_request() {
+        let reply = Reply::new(MessageType::Reload, ReplyData::Reload(()));
+        assert!(reply.clone().into_data(MessageType::Reload).is_ok());
+        assert!(reply.into_data(MessageType::Terminate).is_err());
+
+        let mismatched = Reply::new(MessageType::Reload, ReplyData::Terminate(()));
+        assert!(mismatched.into_data(MessageType::Reload).is_err());
+    }
+
+    #[test]
+    fn test_rejects_bad_frames() {
+        let mut oversize = &(MAX_FRAME_SIZE as u32 + 1).to_be_bytes()[..];
+        assert!(read_frame::<Message>(&mut oversize).is_err());
+
+        let mut mismatched = Vec::new();
+        let message = Message {
+            message_type: MessageType::Terminate,
+            data: MessageData::Reload(()),
+        };
+        write_frame(&mut mismatched, &message).unwrap();
+        assert!(read_message(&mut &mismatched[..]).is_err());
+
+        assert!(read_message(&mut &[][..]).unwrap().is_none());
+    }
+}
@@ -14,6 +14,7 @@ impl<const N: usize, const V: usize> Header<N, V> {
         }
     }
 
+    #[cfg(feature = "std")]
     pub fn from_stream(stream: &mut impl std::io::Read) -> std::io::Result<Header<N, V>> {
         let mut header = Header::new();
         let mut buf = [0; 1];
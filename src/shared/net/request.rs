@@ -1,5 +1,7 @@
 use crate::prelude::*;
 
+use core::fmt;
+#[cfg(feature = "std")]
 use std::{io::Write, thread};
 
 use super::{headers::Header, method::Method};
@@ -16,6 +18,7 @@ pub struct Request<const HV: usize = { 64 * 2 }> {
     num_headers: usize,
 }
 
+#[cfg(feature = "std")]
 impl<const HV: usize> Write for Request<HV> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         let remaining = self.body.len() - self.body_written;
@@ -87,8 +90,9 @@ impl<const HV: usize> Request<HV> {
         None
     }
 
-    pub fn to_writer(&self, writer: &mut impl Write) -> Result<(), Error> {
-        trace!("Writing request to writer");
+    pub fn write_into<const N: usize>(&self, out: &mut ByteArray<N>) -> Result<(), Error> {
+        trace!("Serializing request");
+        let oversize = |_| Error::from(ErrorKind::OversizePacket);
         let path = match self.path.as_ref() {
             Some(path) => path.as_str()?,
             None => "/",
@@ -99,17 +103,18 @@ impl<const HV: usize> Request<HV> {
         };
 
         trace!("Writing HTTP request line");
-        writer.write_fmt(format_args!("{} {} HTTP/1.1\r\n", method, path))?;
+        fmt::Write::write_fmt(out, format_args!("{} {} HTTP/1.1\r\n", method, path))
+            .map_err(oversize)?;
 
         trace!("Writing request headers");
         for header in self.headers.iter().take(self.num_headers) {
             match header {
                 Some(header) => {
-                    writer.write_fmt(format_args!(
-                        "{}: {}\r\n",
-                        header.name.as_str()?,
-                        header.value.as_str()?
-                    ))?;
+                    fmt::Write::write_fmt(
+                        out,
+                        format_args!("{}: {}\r\n", header.name.as_str()?, header.value.as_str()?),
+                    )
+                    .map_err(oversize)?;
                 }
                 None => break,
             }
@@ -117,19 +122,34 @@ impl<const HV: usize> Request<HV> {
         // Write content length
 
         trace!("Writing content length header");
-        writer.write_fmt(format_args!("Content-Length: {}\r\n", self.body_written))?;
-        writer.write_all(b"\r\n")?;
+        fmt::Write::write_fmt(
+            out,
+            format_args!("Content-Length: {}\r\n\r\n", self.body_written),
+        )
+        .map_err(oversize)?;
 
         trace!("Writing request body");
-        writer.write_all(&self.body[..self.body_written])?;
+        out.append_slice(&self.body[..self.body_written])
+            .map_err(|_| Error::from(ErrorKind::OversizePacket))?;
+
+        trace!("Finished serializing request");
+        Ok(())
+    }
+
+    #[cfg(feature = "std")]
+    pub fn to_writer(&self, writer: &mut impl Write) -> Result<(), Error> {
+        trace!("Writing request to writer");
+        let mut buf = ByteArray::<{ Config::MAX_PACKET_SIZE }>::new();
+        self.write_into(&mut buf)?;
+        writer.write_all(buf.as_bytes())?;
 
         trace!("Finished writing request");
         Ok(())
     }
 
+    #[cfg(feature = "std")]
     pub fn from_stream(stream: &mut impl std::io::Read) -> Result<Self, Error> {
         trace!("Parsing request from stream");
-        let mut builder = Self::builder();
         let mut buf = [0; Config::MAX_PACKET_SIZE];
         let mut buf_ptr = 0;
 
@@ -170,11 +190,17 @@ impl<const HV: usize> Request<HV> {
             }
         }
 
+        Self::parse(&buf[..buf_ptr])
+    }
+
+    pub fn parse(buf: &[u8]) -> Result<Self, Error> {
+        let mut builder = Self::builder();
+
         trace!("Parsing httparse request");
         // Process the buffer into a request
         let mut headers = [httparse::EMPTY_HEADER; 16];
         let mut req = httparse::Request::new(&mut headers);
-        let status = match req.parse(&buf) {
+        let status = match req.parse(buf) {
             Ok(httparse::Status::Complete(i)) => i,
             Ok(httparse::Status::Partial) => return Err(ErrorKind::IncompleteRequest.into()),
             Err(e) => return Err(e.into()),
@@ -206,7 +232,7 @@ impl<const HV: usize> Request<HV> {
             None => b"0",
         };
 
-        let content_length = std::str::from_utf8(content_length)?
+        let content_length = core::str::from_utf8(content_length)?
             .parse::<usize>()
             .unwrap_or(0);
         trace!("Content-Length: {}", content_length);
@@ -214,7 +240,7 @@ impl<const HV: usize> Request<HV> {
         trace!("Adding headers to request");
         for header in req.headers {
             if !header.name.is_empty() {
-                let hv = std::str::from_utf8(header.value)?;
+                let hv = core::str::from_utf8(header.value)?;
 
                 trace!("Header: {}: {}", header.name, hv);
                 builder = builder.add_header(header.name, hv)?;
@@ -222,7 +248,7 @@ impl<const HV: usize> Request<HV> {
         }
 
         let body_start_ptr = status;
-        let body_end_ptr = status + content_length;
+        let body_end_ptr = core::cmp::min(status + content_length, buf.len());
 
         let body = &buf[body_start_ptr..body_end_ptr];
         trace!("Adding body to request: {} bytes", body.len());
@@ -259,10 +285,7 @@ impl<const HV: usize> Request<HV> {
 
     pub fn path(&self) -> Option<&str> {
         match self.path.as_ref() {
-            Some(path) => match path.as_str() {
-                Ok(path) => Some(path),
-                Err(_) => None,
-            },
+            Some(path) => path.as_str().ok(),
             None => None,
         }
     }
@@ -338,7 +361,15 @@ impl<const HV: usize> RequestBuilder<HV> {
     }
 
     pub fn append_body(mut self, body: &[u8]) -> Result<Self, Error> {
-        self.write_all(body)?;
+        let end = self.body_written + body.len();
+        if end > self.body.len() {
+            return Err(ErrorKind::OversizePacket.into());
+        }
+
+        self.body[self.body_written..end].copy_from_slice(body);
+        self.body_written = end;
+        self.body_len = self.body_written;
+
         Ok(self)
     }
 
@@ -362,6 +393,7 @@ impl<const HV: usize> RequestBuilder<HV> {
     }
 }
 
+#[cfg(feature = "std")]
 impl<const HV: usize> Write for RequestBuilder<HV> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         trace!("Writing to request builder");
@@ -1,6 +1,8 @@
 use crate::prelude::*;
 
 use super::{headers::Header, status::Status};
+use core::fmt;
+#[cfg(feature = "std")]
 use std::{io::Write, thread};
 
 #[derive(Debug)]
@@ -15,6 +17,7 @@ pub struct Response<const HV: usize = { 64 * 2 }> {
     num_headers: usize,
 }
 
+#[cfg(feature = "std")]
 impl<const HV: usize> Write for Response<HV> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         let remaining = self.body.len() - self.body_written;
@@ -55,6 +58,13 @@ impl<const HV: usize> Response<HV> {
         self.status
     }
 
+    pub fn get_header(&self, name: &str) -> Option<&Header<32, HV>> {
+        self.headers
+            .iter()
+            .map_while(|header| header.as_ref())
+            .find(|header| header.name.as_str().unwrap_or("") == name)
+    }
+
     pub fn add_header(&mut self, name: &'static str, value: &'static str) -> Result<(), Error> {
         for header in self.headers.iter_mut() {
             match header {
@@ -79,45 +89,64 @@ impl<const HV: usize> Response<HV> {
         Err(Error::new(ErrorKind::Server, Some("Too many headers")))
     }
 
-    pub fn to_writer(&self, writer: &mut impl Write) -> Result<(), Error> {
-        trace!("Writing response to writer");
+    pub fn write_into<const N: usize>(&self, out: &mut ByteArray<N>) -> Result<(), Error> {
+        trace!("Serializing response");
+        let oversize = |_| Error::from(ErrorKind::OversizePacket);
 
         trace!("Writing status line");
-        writer.write_fmt(format_args!(
-            "HTTP/1.1 {} {}\r\n",
-            self.status.to_u16(),
-            self.status.to_string()
-        ))?;
+        fmt::Write::write_fmt(
+            out,
+            format_args!(
+                "HTTP/1.1 {} {}\r\n",
+                self.status.to_u16(),
+                self.status.to_string()
+            ),
+        )
+        .map_err(oversize)?;
 
         trace!("Writing headers");
         for header in self.headers.iter().take(self.num_headers) {
             match header {
                 Some(header) => {
-                    writer.write_fmt(format_args!(
-                        "{}: {}\r\n",
-                        header.name.as_str()?,
-                        header.value.as_str()?
-                    ))?;
+                    fmt::Write::write_fmt(
+                        out,
+                        format_args!("{}: {}\r\n", header.name.as_str()?, header.value.as_str()?),
+                    )
+                    .map_err(oversize)?;
                 }
                 None => break,
             }
         }
 
         trace!("Writing content length header");
-        writer.write_fmt(format_args!("Content-Length: {}\r\n", self.body_written))?;
-
-        writer.write_all(b"\r\n")?;
+        fmt::Write::write_fmt(
+            out,
+            format_args!("Content-Length: {}\r\n\r\n", self.body_written),
+        )
+        .map_err(oversize)?;
 
         trace!("Writing response body");
-        writer.write_all(&self.body[..self.body_written])?;
+        out.append_slice(&self.body[..self.body_written])
+            .map_err(|_| Error::from(ErrorKind::OversizePacket))?;
+
+        trace!("Finished serializing response");
+        Ok(())
+    }
+
+    #[cfg(feature = "std")]
+    pub fn to_writer(&self, writer: &mut impl Write) -> Result<(), Error> {
+        trace!("Writing response to writer");
+        let mut buf = ByteArray::<{ Config::MAX_PACKET_SIZE }>::new();
+        self.write_into(&mut buf)?;
+        writer.write_all(buf.as_bytes())?;
 
         trace!("Finished writing response to writer");
         Ok(())
     }
 
+    #[cfg(feature = "std")]
     pub fn from_stream(stream: &mut impl std::io::Read) -> Result<Self, Error> {
         trace!("Parsing response from stream");
-        let mut builder = Self::builder();
         let mut buf = [0; Config::MAX_PACKET_SIZE];
         let mut buf_ptr = 0;
 
@@ -158,10 +187,16 @@ impl<const HV: usize> Response<HV> {
             }
         }
 
+        Self::parse(&buf[..buf_ptr])
+    }
+
+    pub fn parse(buf: &[u8]) -> Result<Self, Error> {
+        let mut builder = Self::builder();
+
         trace!("Parsing httparse response");
         let mut headers = [httparse::EMPTY_HEADER; 16];
         let mut response = httparse::Response::new(&mut headers);
-        let status = match response.parse(&buf) {
+        let status = match response.parse(buf) {
             Ok(httparse::Status::Complete(i)) => i,
             Ok(httparse::Status::Partial) => return Err(ErrorKind::IncompleteRequest.into()),
             Err(e) => return Err(e.into()),
@@ -174,21 +209,8 @@ impl<const HV: usize> Response<HV> {
             Some("No status code in response"),
         ))?;
 
-        if code != 200 {
-            let code_str: ByteArray<5> = ByteArrayString::try_from(code)?.into();
-            let mut message: ByteArray<{ Config::ERROR_STRING_SIZE }> =
-                ByteArrayString::<{ Config::ERROR_STRING_SIZE }>::try_from(
-                    "Server returned status code",
-                )?
-                .into();
-
-            warn!("Received non-200 status code: {}", code_str.as_str()?);
-            message.append_slice(code_str.as_bytes())?;
-
-            return Err(Error::new(ErrorKind::Server, Some(message.as_str()?)));
-        }
-
-        builder = builder.status(Status::Ok);
+        // Callers decide which statuses are errors, see `client::check_status`
+        builder = builder.status(Status::from_u16(code)?);
 
         trace!("Looking for content-length header");
         let content_length = match response
@@ -200,7 +222,7 @@ impl<const HV: usize> Response<HV> {
             None => b"0",
         };
 
-        let content_length = std::str::from_utf8(content_length)?
+        let content_length = core::str::from_utf8(content_length)?
             .parse::<usize>()
             .unwrap_or(0);
 
@@ -209,7 +231,7 @@ impl<const HV: usize> Response<HV> {
         trace!("Adding headers to request");
         for header in response.headers.iter() {
             if !header.name.is_empty() {
-                let hv = std::str::from_utf8(header.value)?;
+                let hv = core::str::from_utf8(header.value)?;
 
                 trace!("Header: {}: {}", &header.name, hv);
                 builder = builder.add_header(header.name, hv)?;
@@ -217,7 +239,7 @@ impl<const HV: usize> Response<HV> {
         }
 
         let body_start_ptr = status;
-        let body_end_ptr = status + content_length;
+        let body_end_ptr = core::cmp::min(status + content_length, buf.len());
 
         let body = &buf[body_start_ptr..body_end_ptr];
 
@@ -287,7 +309,7 @@ impl<const HV: usize> ResponseBuilder<HV> {
 
     pub fn body(mut self, body: &[u8]) -> Self {
         let remaining = self.body.len() - self.body_written;
-        let write_amt = std::cmp::min(remaining, body.len());
+        let write_amt = core::cmp::min(remaining, body.len());
 
         self.body[self.body_written..self.body_written + write_amt]
             .copy_from_slice(&body[..write_amt]);
@@ -297,7 +319,15 @@ impl<const HV: usize> ResponseBuilder<HV> {
     }
 
     pub fn append_body(mut self, body: &[u8]) -> Result<Self, Error> {
-        self.write_all(body)?;
+        let end = self.body_written + body.len();
+        if end > self.body.len() {
+            return Err(ErrorKind::OversizePacket.into());
+        }
+
+        self.body[self.body_written..end].copy_from_slice(body);
+        self.body_written = end;
+        self.body_len = self.body_written;
+
         Ok(self)
     }
 
@@ -312,6 +342,7 @@ impl<const HV: usize> ResponseBuilder<HV> {
         }
     }
 }
+#[cfg(feature = "std")]
 impl<const HV: usize> Write for ResponseBuilder<HV> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         trace!("Writing to response builder");
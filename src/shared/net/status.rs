@@ -6,6 +6,8 @@ pub enum Status {
     BadRequest = 400,
     Forbidden = 403,
     NotFound = 404,
+    RequestTimeout = 408,
+    TooManyRequests = 429,
     InternalServerError = 500,
 }
 
@@ -16,6 +18,8 @@ impl Status {
             Status::BadRequest => "Bad Request",
             Status::Forbidden => "Forbidden",
             Status::NotFound => "Not Found",
+            Status::RequestTimeout => "Request Timeout",
+            Status::TooManyRequests => "Too Many Requests",
             Status::InternalServerError => "Internal Server Error",
         }
     }
@@ -30,6 +34,8 @@ impl Status {
             400 => Ok(Status::BadRequest),
             403 => Ok(Status::Forbidden),
             404 => Ok(Status::NotFound),
+            408 => Ok(Status::RequestTimeout),
+            429 => Ok(Status::TooManyRequests),
             500 => Ok(Status::InternalServerError),
             _ => {
                 let code_str = ByteArray::<5>::from(ByteArrayString::try_from(code)?);
@@ -0,0 +1,258 @@
+use core::fmt::{self, Display};
+use core::str::FromStr;
+
+use serde::de::{Deserialize, Deserializer, Visitor};
+use serde::ser::{Serialize, Serializer};
+
+use crate::crypto::fingerprint::Fingerprint;
+#[cfg(feature = "server")]
+use crate::enrollment_code::EnrollmentCode;
+use crate::prelude::*;
+
+// Format: remote-unlock://<host>:<port>/enroll?code=<code>&expires=<unix>&fp=<sha256 hex>
+const SCHEME: &str = "remote-unlock://";
+const ENROLL_PATH: &str = "enroll";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PairingUri {
+    host: ByteArray<{ PairingUri::MAX_HOST_LEN }>,
+    port: u16,
+    code: u32,
+    expires: i64,
+    fingerprint: Fingerprint,
+}
+
+impl PairingUri {
+    pub const MAX_HOST_LEN: usize = 253;
+
+    #[cfg(feature = "server")]
+    pub fn new(
+        host: &str,
+        port: u16,
+        code: &EnrollmentCode,
+        fingerprint: Fingerprint,
+    ) -> Result<PairingUri, Error> {
+        Ok(PairingUri {
+            host: Self::parse_host(host)?,
+            port,
+            code: code.code(),
+            expires: code.expires(),
+            fingerprint,
+        })
+    }
+
+    pub fn parse(uri: &str) -> Result<PairingUri, Error> {
+        let invalid = |message| Error::new(ErrorKind::InvalidPairingUri, Some(message));
+
+        let rest = uri
+            .strip_prefix(SCHEME)
+            .ok_or_else(|| invalid("Unknown scheme"))?;
+        let (authority, rest) = rest
+            .split_once('/')
+            .ok_or_else(|| invalid("Missing path"))?;
+        let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+
+        if path != ENROLL_PATH {
+            return Err(invalid("Unknown path"));
+        }
+
+        let (host, port) = match authority.strip_prefix('[') {
+            // IPv6 literal
+            Some(bracketed) => bracketed
+                .split_once("]:")
+                .ok_or_else(|| invalid("Malformed IPv6 host"))?,
+            None => authority
+                .rsplit_once(':')
+                .ok_or_else(|| invalid("Missing port"))?,
+        };
+        let port = port.parse::<u16>().map_err(|_| invalid("Invalid port"))?;
+
+        let mut code = None;
+        let mut expires = None;
+        let mut fingerprint = None;
+
+        for param in query.split('&').filter(|param| !param.is_empty()) {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            match key {
+                "code" => code = Some(value.parse::<u32>().map_err(|_| invalid("Invalid code"))?),
+                "expires" => {
+                    expires = Some(
+                        value
+                            .parse::<i64>()
+                            .map_err(|_| invalid("Invalid expiry"))?,
+                    )
+                }
+                "fp" => fingerprint = Some(Fingerprint::from_str(value)?),
+                // Ignore unknown parameters so newer servers can extend the format
+                _ => trace!("Ignoring unknown pairing URI parameter: {}", key),
+            }
+        }
+
+        Ok(PairingUri {
+            host: Self::parse_host(host)?,
+            port,
+            code: code.ok_or_else(|| invalid("Missing code"))?,
+            expires: expires.ok_or_else(|| invalid("Missing expiry"))?,
+            fingerprint: fingerprint.ok_or_else(|| invalid("Missing fingerprint"))?,
+        })
+    }
+
+    fn parse_host(host: &str) -> Result<ByteArray<{ PairingUri::MAX_HOST_LEN }>, Error> {
+        let valid = !host.is_empty()
+            && host
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | ':'));
+
+        if !valid {
+            return Err(Error::new(
+                ErrorKind::InvalidPairingUri,
+                Some("Invalid host"),
+            ));
+        }
+
+        Ok(ByteArray::try_from(host.as_bytes())?)
+    }
+
+    pub fn host(&self) -> &str {
+        // Validated to be ASCII on construction
+        self.host.as_str().unwrap_or("")
+    }
+
+    pub fn set_host(&mut self, host: &str) -> Result<(), Error> {
+        self.host = Self::parse_host(host)?;
+        Ok(())
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn code(&self) -> u32 {
+        self.code
+    }
+
+    pub fn expires(&self) -> i64 {
+        self.expires
+    }
+
+    pub fn fingerprint(&self) -> &Fingerprint {
+        &self.fingerprint
+    }
+}
+
+impl Display for PairingUri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let host = self.host();
+        if host.contains(':') {
+            write!(f, "{}[{}]:{}", SCHEME, host, self.port)?;
+        } else {
+            write!(f, "{}{}:{}", SCHEME, host, self.port)?;
+        }
+
+        write!(
+            f,
+            "/{}?code={}&expires={}&fp={}",
+            ENROLL_PATH, self.code, self.expires, self.fingerprint
+        )
+    }
+}
+
+impl FromStr for PairingUri {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl Serialize for PairingUri {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+struct PairingUriVisitor;
+
+impl<'de> Visitor<'de> for PairingUriVisitor {
+    type Value = PairingUri;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a remote-unlock pairing URI")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<PairingUri, E>
+    where
+        E: serde::de::Error,
+    {
+        PairingUri::parse(v).map_err(E::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for PairingUri {
+    fn deserialize<D>(deserializer: D) -> Result<PairingUri, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(PairingUriVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FINGERPRINT: &str = "3b2c1e9a6f0d4e8b7a5c2d1f0e9b8a7c6d5e4f3a2b1c0d9e8f7a6b5c4d3e2f1a";
+
+    #[test]
+    fn test_round_trip() {
+        let uri = format!(
+            "remote-unlock://desktop.local:8142/enroll?code=123456&expires=1700000000&fp={}",
+            FINGERPRINT
+        );
+        let parsed = PairingUri::parse(&uri).unwrap();
+
+        assert_eq!(parsed.host(), "desktop.local");
+        assert_eq!(parsed.port(), 8142);
+        assert_eq!(parsed.code(), 123456);
+        assert_eq!(parsed.expires(), 1700000000);
+        assert_eq!(parsed.fingerprint().to_string(), FINGERPRINT);
+        assert_eq!(parsed.to_string(), uri);
+    }
+
+    #[test]
+    fn test_ipv6_round_trip() {
+        let uri = format!(
+            "remote-unlock://[fe80::1]:8142/enroll?code=654321&expires=1700000000&fp={}",
+            FINGERPRINT
+        );
+        let parsed = PairingUri::parse(&uri).unwrap();
+
+        assert_eq!(parsed.host(), "fe80::1");
+        assert_eq!(parsed.to_string(), uri);
+    }
+
+    #[test]
+    fn test_rejects_malformed() {
+        let fp = format!("&fp={}", FINGERPRINT);
+        let invalid = [
+            format!("https://desktop.local:8142/enroll?code=1&expires=1{}", fp),
+            format!(
+                "remote-unlock://desktop.local/enroll?code=1&expires=1{}",
+                fp
+            ),
+            format!(
+                "remote-unlock://desktop.local:8142/unlock?code=1&expires=1{}",
+                fp
+            ),
+            format!("remote-unlock://desktop.local:8142/enroll?expires=1{}", fp),
+            "remote-unlock://desktop.local:8142/enroll?code=1&expires=1&fp=abc".to_string(),
+        ];
+
+        for uri in invalid.iter() {
+            assert!(PairingUri::parse(uri).is_err(), "accepted {}", uri);
+        }
+    }
+}
@@ -0,0 +1,500 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use chrono::{Local, TimeZone, Timelike};
+use serde::Deserialize;
+use toml::{Table, Value};
+
+use crate::denial::Denial;
+use crate::prelude::*;
+
+// How a device shows a signed request is not a replay
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplayDefense {
+    // An increasing nonce kept by the client
+    #[default]
+    Counter,
+    // A single use challenge fetched from `/challenge` first
+    Challenge,
+}
+
+// Actions a policy can withhold, status requests are always answered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PolicyAction {
+    Wake,
+    Unlock,
+    Lock,
+}
+
+// Local time of day window written as "HH:MM-HH:MM", wrapping past
+// midnight when it ends before it starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Hours {
+    // Minutes since midnight, the end is exclusive
+    start: u16,
+    end: u16,
+}
+
+fn minute_of_day(time: &str) -> Option<u16> {
+    let (hours, minutes) = time.trim().split_once(':')?;
+    let (hours, minutes) = (hours.parse::<u16>().ok()?, minutes.parse::<u16>().ok()?);
+    if minutes >= 60 || hours > 24 || (hours == 24 && minutes > 0) {
+        return None;
+    }
+
+    Some(hours * 60 + minutes)
+}
+
+impl TryFrom<String> for Hours {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Hours, String> {
+        let malformed = || format!("\"{}\" is not a HH:MM-HH:MM window", value);
+        let (start, end) = value.split_once('-').ok_or_else(malformed)?;
+        let (start, end) = (
+            minute_of_day(start).ok_or_else(malformed)?,
+            minute_of_day(end).ok_or_else(malformed)?,
+        );
+        if start == end {
+            return Err(format!("\"{}\" is an empty window", value));
+        }
+
+        Ok(Hours { start, end })
+    }
+}
+
+impl Hours {
+    pub fn contains(&self, minute: u16) -> bool {
+        if self.start < self.end {
+            self.start <= minute && minute < self.end
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+// Network in CIDR notation, a bare address matches only itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Subnet {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl TryFrom<String> for Subnet {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Subnet, String> {
+        let malformed = || format!("\"{}\" is not a subnet", value);
+        let (network, prefix) = match value.split_once('/') {
+            Some((network, prefix)) => (network, Some(prefix)),
+            None => (value.as_str(), None),
+        };
+        let network = network.parse::<IpAddr>().map_err(|_| malformed())?;
+        let bits = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().map_err(|_| malformed())?,
+            None => bits,
+        };
+        if prefix > bits {
+            return Err(malformed());
+        }
+
+        let subnet = Subnet { network, prefix };
+        if subnet.bits(network).unwrap_or(0) & subnet.host_mask() != 0 {
+            return Err(format!("\"{}\" has host bits set", value));
+        }
+
+        Ok(subnet)
+    }
+}
+
+impl Subnet {
+    // Addresses as numbers in this subnet's family, None for the other family
+    // unless it is a mapped address
+    fn bits(&self, addr: IpAddr) -> Option<u128> {
+        match (self.network, addr) {
+            (IpAddr::V4(_), IpAddr::V4(addr)) => Some(u32::from(addr) as u128),
+            (IpAddr::V4(_), IpAddr::V6(addr)) => {
+                addr.to_ipv4_mapped().map(|addr| u32::from(addr) as u128)
+            }
+            (IpAddr::V6(_), IpAddr::V6(addr)) => Some(u128::from(addr)),
+            (IpAddr::V6(_), IpAddr::V4(addr)) => Some(u128::from(addr.to_ipv6_mapped())),
+        }
+    }
+
+    fn host_mask(&self) -> u128 {
+        let width = if self.network.is_ipv4() { 32 } else { 128 };
+        u128::MAX
+            .checked_shr(128 - (width - self.prefix as u32))
+            .unwrap_or(0)
+    }
+
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.bits(self.network), self.bits(addr)) {
+            (Some(network), Some(addr)) => addr & !self.host_mask() == network,
+            _ => false,
+        }
+    }
+}
+
+// What an authenticated request looks like to the policy engine
+#[derive(Debug, Clone, Copy)]
+pub struct Attempt {
+    pub action: PolicyAction,
+    pub peer: Option<IpAddr>,
+    // Local minutes since midnight
+    pub minute: u16,
+    // Seconds since the screen was locked, None when it is not locked
+    pub since_lock: Option<i64>,
+    // Unlocks by this device in the last hour
+    pub recent_unlocks: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DevicePolicy {
+    pub replay: ReplayDefense,
+    pub actions: Vec<PolicyAction>,
+    // Any time of day when empty
+    pub hours: Vec<Hours>,
+    // Any source address when empty
+    pub subnets: Vec<Subnet>,
+    // Seconds an unlock must wait after the screen was locked
+    pub min_since_lock: u64,
+    pub max_unlocks_per_hour: Option<u32>,
+}
+
+impl Default for DevicePolicy {
+    fn default() -> DevicePolicy {
+        DevicePolicy {
+            replay: ReplayDefense::default(),
+            actions: vec![PolicyAction::Wake, PolicyAction::Unlock, PolicyAction::Lock],
+            hours: Vec::new(),
+            subnets: Vec::new(),
+            min_since_lock: 0,
+            max_unlocks_per_hour: None,
+        }
+    }
+}
+
+impl DevicePolicy {
+    // The lock time and unlock count only limit unlocks
+    pub fn evaluate(&self, attempt: &Attempt) -> Result<(), Denial> {
+        if !self.actions.contains(&attempt.action) {
+            return Err(Denial::ActionNotAllowed);
+        }
+
+        if !self.subnets.is_empty()
+            && !attempt
+                .peer
+                .is_some_and(|peer| self.subnets.iter().any(|subnet| subnet.contains(peer)))
+        {
+            return Err(Denial::SubnetNotAllowed);
+        }
+
+        if !self.hours.is_empty()
+            && !self
+                .hours
+                .iter()
+                .any(|hours| hours.contains(attempt.minute))
+        {
+            return Err(Denial::OutsideHours);
+        }
+
+        if attempt.action != PolicyAction::Unlock {
+            return Ok(());
+        }
+
+        if attempt
+            .since_lock
+            .is_some_and(|since_lock| since_lock < self.min_since_lock as i64)
+        {
+            return Err(Denial::TooSoonAfterLock);
+        }
+
+        if self
+            .max_unlocks_per_hour
+            .is_some_and(|max| attempt.recent_unlocks >= max as usize)
+        {
+            return Err(Denial::RateLimited);
+        }
+
+        Ok(())
+    }
+}
+
+// Local minutes since midnight at a Unix time, windows in `hours` are
+// written in the server's time zone
+pub fn local_minute(now: i64) -> u16 {
+    Local
+        .timestamp_opt(now, 0)
+        .single()
+        .map(|time| (time.hour() * 60 + time.minute()) as u16)
+        .unwrap_or(0)
+}
+
+// Policies keyed by device id. Settings missing from a device's table are
+// taken from `[default]`, and from the built in defaults after that:
+//
+//     [default]
+//     replay = "counter"
+//     actions = ["wake", "unlock", "lock"]
+//     hours = ["07:00-23:30"]
+//     subnets = ["192.168.1.0/24", "fd00::/8"]
+//     min_since_lock = 60
+//     max_unlocks_per_hour = 10
+//
+//     [devices.0123456789abcdef0123456789abcdef]
+//     replay = "challenge"
+//     actions = ["lock"]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Policies {
+    default: DevicePolicy,
+    devices: HashMap<uuid::Uuid, DevicePolicy>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyFile {
+    #[serde(default)]
+    default: Table,
+    #[serde(default)]
+    devices: HashMap<String, Table>,
+}
+
+fn invalid(message: impl core::fmt::Display) -> Error {
+    error!("Invalid policy: {}", message);
+    Error::new(ErrorKind::InvalidPolicy, None)
+}
+
+fn device_policy(table: Table) -> Result<DevicePolicy, toml::de::Error> {
+    DevicePolicy::deserialize(Value::Table(table))
+}
+
+impl Policies {
+    // Every device gets the built in defaults without a policy file
+    pub fn load(config: &ServerConfig) -> Result<Policies, Error> {
+        let Some(path) = config.policy_file() else {
+            return Ok(Policies::default());
+        };
+
+        debug!("Loading policies from {}", path);
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            error!("Cannot read policy file {}: {}", path, e);
+            Error::from(e)
+        })?;
+        Policies::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<Policies, Error> {
+        let file = toml::from_str::<PolicyFile>(contents).map_err(invalid)?;
+        let default = device_policy(file.default.clone()).map_err(invalid)?;
+
+        let mut devices = HashMap::new();
+        for (id, table) in file.devices {
+            let id = uuid::Uuid::try_parse(&id)
+                .map_err(|_| invalid(format!("\"{}\" is not a device id", id)))?;
+            let mut merged = file.default.clone();
+            merged.extend(table);
+            let policy = device_policy(merged).map_err(|e| invalid(format!("{}: {}", id, e)))?;
+            devices.insert(id, policy);
+        }
+
+        Ok(Policies { default, devices })
+    }
+
+    pub fn device(&self, id: &uuid::Uuid) -> &DevicePolicy {
+        self.devices.get(id).unwrap_or(&self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults() {
+        let id = uuid::Uuid::new_v4();
+        assert_eq!(
+            Policies::default().device(&id).replay,
+            ReplayDefense::Counter
+        );
+        assert_eq!(Policies::parse("").unwrap(), Policies::default());
+    }
+
+    #[test]
+    fn test_device_overrides_default() {
+        let policies = Policies::parse(
+            r#"
+            [default]
+            replay = "challenge"
+
+            [devices.0123456789abcdef0123456789abcdef]
+            replay = "counter"
+
+            [devices.fedcba98-7654-3210-fedc-ba9876543210]
+            "#,
+        )
+        .unwrap();
+
+        let counter = uuid::Uuid::from_u128(0x0123456789abcdef0123456789abcdef);
+        let inherited = uuid::Uuid::from_u128(0xfedcba9876543210fedcba9876543210);
+        assert_eq!(policies.device(&counter).replay, ReplayDefense::Counter);
+        assert_eq!(policies.device(&inherited).replay, ReplayDefense::Challenge);
+        assert_eq!(
+            policies.device(&uuid::Uuid::new_v4()).replay,
+            ReplayDefense::Challenge
+        );
+    }
+
+    #[test]
+    fn test_rejects_invalid() {
+        assert!(Policies::parse("[default]\nreplay = \"sometimes\"").is_err());
+        assert!(Policies::parse("[default]\nreply = \"counter\"").is_err());
+        assert!(Policies::parse("[devices.laptop]\nreplay = \"counter\"").is_err());
+        assert!(Policies::parse("[groups]").is_err());
+    }
+
+    fn attempt(action: PolicyAction) -> Attempt {
+        Attempt {
+            action,
+            peer: Some("192.168.1.20".parse().unwrap()),
+            minute: 12 * 60,
+            since_lock: None,
+            recent_unlocks: 0,
+        }
+    }
+
+    #[test]
+    fn test_default_allows_everything() {
+        let policy = DevicePolicy::default();
+        for action in [PolicyAction::Wake, PolicyAction::Unlock, PolicyAction::Lock] {
+            let attempt = Attempt {
+                peer: None,
+                since_lock: Some(0),
+                recent_unlocks: 1000,
+                ..attempt(action)
+            };
+            assert_eq!(policy.evaluate(&attempt), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let policies = Policies::parse(
+            r#"
+            [default]
+            actions = ["unlock", "lock"]
+            hours = ["22:00-02:00", "08:00-18:00"]
+            subnets = ["192.168.1.0/24", "fd00::/8"]
+            min_since_lock = 60
+            max_unlocks_per_hour = 3
+            "#,
+        )
+        .unwrap();
+        let policy = policies.device(&uuid::Uuid::new_v4());
+
+        assert_eq!(policy.evaluate(&attempt(PolicyAction::Unlock)), Ok(()));
+        assert_eq!(
+            policy.evaluate(&attempt(PolicyAction::Wake)),
+            Err(Denial::ActionNotAllowed)
+        );
+
+        for (peer, allowed) in [
+            ("192.168.2.20", false),
+            ("::ffff:192.168.1.7", true),
+            ("fd12::1", true),
+            ("fe80::1", false),
+        ] {
+            let attempt = Attempt {
+                peer: Some(peer.parse().unwrap()),
+                ..attempt(PolicyAction::Unlock)
+            };
+            let expected = if allowed {
+                Ok(())
+            } else {
+                Err(Denial::SubnetNotAllowed)
+            };
+            assert_eq!(policy.evaluate(&attempt), expected, "{}", peer);
+        }
+        let unknown_peer = Attempt {
+            peer: None,
+            ..attempt(PolicyAction::Lock)
+        };
+        assert_eq!(
+            policy.evaluate(&unknown_peer),
+            Err(Denial::SubnetNotAllowed)
+        );
+
+        for (minute, allowed) in [
+            (23 * 60, true),
+            (60, true),
+            (2 * 60, false),
+            (18 * 60, false),
+        ] {
+            let attempt = Attempt {
+                minute,
+                ..attempt(PolicyAction::Lock)
+            };
+            let expected = if allowed {
+                Ok(())
+            } else {
+                Err(Denial::OutsideHours)
+            };
+            assert_eq!(policy.evaluate(&attempt), expected, "{}", minute);
+        }
+
+        let just_locked = Attempt {
+            since_lock: Some(59),
+            ..attempt(PolicyAction::Unlock)
+        };
+        assert_eq!(policy.evaluate(&just_locked), Err(Denial::TooSoonAfterLock));
+        let waited = Attempt {
+            since_lock: Some(60),
+            ..attempt(PolicyAction::Unlock)
+        };
+        assert_eq!(policy.evaluate(&waited), Ok(()));
+
+        let busy = Attempt {
+            recent_unlocks: 3,
+            ..attempt(PolicyAction::Unlock)
+        };
+        assert_eq!(policy.evaluate(&busy), Err(Denial::RateLimited));
+        let busy_lock = Attempt {
+            action: PolicyAction::Lock,
+            since_lock: Some(0),
+            ..busy
+        };
+        assert_eq!(policy.evaluate(&busy_lock), Ok(()));
+    }
+
+    #[test]
+    fn test_rejects_invalid_rules() {
+        for rules in [
+            "actions = [\"status\"]",
+            "hours = [\"8:00\"]",
+            "hours = [\"08:00-24:01\"]",
+            "hours = [\"09:00-09:00\"]",
+            "subnets = [\"192.168.1.0/33\"]",
+            "subnets = [\"192.168.1.1/24\"]",
+            "subnets = [\"lan\"]",
+            "min_since_lock = -1",
+        ] {
+            assert!(
+                Policies::parse(&format!("[default]\n{}", rules)).is_err(),
+                "{}",
+                rules
+            );
+        }
+
+        let policies = Policies::parse(
+            "[default]\nhours = [\"00:00-24:00\"]\nsubnets = [\"10.0.0.1\", \"::/0\"]",
+        )
+        .unwrap();
+        assert_eq!(policies.device(&uuid::Uuid::new_v4()).subnets.len(), 2);
+    }
+}
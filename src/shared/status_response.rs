@@ -0,0 +1,16 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatusResponse {
+    locked: bool,
+}
+
+impl StatusResponse {
+    pub fn new(locked: bool) -> StatusResponse {
+        StatusResponse { locked }
+    }
+
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+}
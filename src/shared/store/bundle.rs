@@ -0,0 +1,362 @@
+use argon2::Argon2;
+use base64::prelude::*;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::prelude::*;
+
+use super::{Device, Store, Transaction};
+
+// Format of the exported file, bumped when older builds could not read it
+pub const BUNDLE_VERSION: u32 = 1;
+
+const SALT_LEN: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedDevice {
+    pub device: Device,
+    // None for devices that never sent a request
+    pub nonce: Option<u128>,
+}
+
+// Enrolled devices as one server saw them, for restoring on another
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bundle {
+    pub exported: i64,
+    pub devices: Vec<ExportedDevice>,
+}
+
+// Argon2id stretches the passphrase into the ChaCha20-Poly1305 key
+#[derive(Debug, Serialize, Deserialize)]
+struct Encrypted {
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+// Holds either the bundle or its encryption. Plain fields rather than a
+// tagged enum, serde buffers those and cannot buffer a u128 nonce.
+#[derive(Debug, Serialize, Deserialize)]
+struct Envelope {
+    version: u32,
+    #[serde(default)]
+    bundle: Option<Bundle>,
+    #[serde(default)]
+    encrypted: Option<Encrypted>,
+}
+
+// What happens to a device id that is already enrolled with another key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Conflict {
+    Fail,
+    Skip,
+    Replace,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Imported {
+    Added,
+    // Already enrolled with the same key, only the nonce may move forward
+    Merged,
+    Replaced,
+    Skipped,
+}
+
+fn bundle_key(passphrase: &str, salt: &[u8]) -> Result<Zeroizing<[u8; 32]>, Error> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut *key)
+        .map_err(|e| {
+            error!("Cannot derive the bundle key: {}", e);
+            Error::new(ErrorKind::Decryption, Some("Cannot derive the bundle key"))
+        })?;
+    Ok(key)
+}
+
+fn decode(field: &str) -> Result<Vec<u8>, Error> {
+    BASE64_STANDARD
+        .decode(field)
+        .map_err(|_| Error::new(ErrorKind::Decryption, Some("Malformed bundle")))
+}
+
+impl Bundle {
+    // Only devices that pass verification are exported, `exported` is the
+    // Unix time recorded in the bundle
+    pub fn export(store: &dyn Store, exported: i64) -> Result<Bundle, Error> {
+        let mut devices = Vec::new();
+        for device in store.devices()? {
+            let nonce = store.nonce(&device.id)?;
+            devices.push(ExportedDevice { device, nonce });
+        }
+
+        Ok(Bundle { exported, devices })
+    }
+
+    pub fn to_bytes(&self, passphrase: Option<&str>) -> Result<Vec<u8>, Error> {
+        let envelope = match passphrase {
+            None => Envelope {
+                version: BUNDLE_VERSION,
+                bundle: Some(self.clone()),
+                encrypted: None,
+            },
+            Some(passphrase) => {
+                let salt = rand::random::<[u8; SALT_LEN]>();
+                let key = bundle_key(passphrase, &salt)?;
+                let cipher = ChaCha20Poly1305::new(Key::from_slice(&*key));
+                let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+                let plaintext = Zeroizing::new(serde_json::to_vec(self)?);
+                let payload = Payload {
+                    msg: &plaintext,
+                    aad: &BUNDLE_VERSION.to_be_bytes(),
+                };
+                let ciphertext = cipher.encrypt(&nonce, payload).map_err(|_| {
+                    Error::new(ErrorKind::Decryption, Some("Cannot encrypt bundle"))
+                })?;
+
+                Envelope {
+                    version: BUNDLE_VERSION,
+                    bundle: None,
+                    encrypted: Some(Encrypted {
+                        salt: BASE64_STANDARD.encode(salt),
+                        nonce: BASE64_STANDARD.encode(nonce),
+                        ciphertext: BASE64_STANDARD.encode(ciphertext),
+                    }),
+                }
+            }
+        };
+
+        Ok(serde_json::to_vec_pretty(&envelope)?)
+    }
+
+    pub fn is_encrypted(bytes: &[u8]) -> Result<bool, Error> {
+        let envelope = serde_json::from_slice::<Envelope>(bytes)?;
+        Ok(envelope.encrypted.is_some())
+    }
+
+    pub fn from_bytes(bytes: &[u8], passphrase: Option<&str>) -> Result<Bundle, Error> {
+        let envelope = serde_json::from_slice::<Envelope>(bytes)?;
+        if envelope.version > BUNDLE_VERSION {
+            error!(
+                "Bundle is at version {}, this build only reads up to {}",
+                envelope.version, BUNDLE_VERSION
+            );
+            return Err(Error::new(
+                ErrorKind::UnsupportedVersion,
+                Some("Bundle is newer than this build"),
+            ));
+        }
+
+        match (envelope.bundle, envelope.encrypted) {
+            (Some(bundle), None) => Ok(bundle),
+            (None, Some(encrypted)) => {
+                let Some(passphrase) = passphrase else {
+                    return Err(Error::new(
+                        ErrorKind::Decryption,
+                        Some("Bundle needs a passphrase"),
+                    ));
+                };
+                let key = bundle_key(passphrase, &decode(&encrypted.salt)?)?;
+                let nonce = decode(&encrypted.nonce)?;
+                if nonce.len() != 12 {
+                    return Err(Error::new(ErrorKind::Decryption, Some("Malformed bundle")));
+                }
+                let cipher = ChaCha20Poly1305::new(Key::from_slice(&*key));
+                let payload = Payload {
+                    msg: &decode(&encrypted.ciphertext)?,
+                    aad: &envelope.version.to_be_bytes(),
+                };
+                let plaintext = cipher
+                    .decrypt(Nonce::from_slice(&nonce), payload)
+                    .map(Zeroizing::new)
+                    .map_err(|_| {
+                        Error::new(
+                            ErrorKind::Decryption,
+                            Some("Wrong passphrase or corrupt bundle"),
+                        )
+                    })?;
+                Ok(serde_json::from_slice(&plaintext)?)
+            }
+            _ => Err(Error::new(ErrorKind::Decryption, Some("Malformed bundle"))),
+        }
+    }
+
+    // Applies every device in one transaction, or none if any conflict fails.
+    // A nonce only ever moves forward, so signatures the bundle's server
+    // already accepted cannot be replayed against this one.
+    pub fn import(
+        &self,
+        store: &dyn Store,
+        conflict: Conflict,
+        now: i64,
+    ) -> Result<Vec<(uuid::Uuid, Imported)>, Error> {
+        let mut transaction = Transaction::new();
+        let mut outcomes = Vec::new();
+
+        for exported in &self.devices {
+            let id = exported.device.id;
+            exported.device.public_key()?;
+
+            let outcome = match store.device(&id)? {
+                None => Imported::Added,
+                Some(existing) if existing.public_key == exported.device.public_key => {
+                    Imported::Merged
+                }
+                Some(_) => match conflict {
+                    Conflict::Fail => {
+                        error!("Device {} is already enrolled with another key", id);
+                        return Err(Error::new(
+                            ErrorKind::KeyExists,
+                            Some("Device enrolled with another key"),
+                        ));
+                    }
+                    Conflict::Skip => Imported::Skipped,
+                    Conflict::Replace => Imported::Replaced,
+                },
+            };
+
+            if outcome == Imported::Skipped {
+                outcomes.push((id, outcome));
+                continue;
+            }
+            if outcome != Imported::Merged {
+                transaction = transaction.put_device(exported.device.clone());
+            }
+
+            let current = store.nonce(&id)?;
+            let nonce = match (current, exported.nonce) {
+                (Some(current), Some(nonce)) => Some(current.max(nonce)),
+                (current, nonce) => current.or(nonce),
+            };
+            if let Some(nonce) = nonce.filter(|nonce| Some(*nonce) != current) {
+                transaction = transaction.put_nonce(id, nonce);
+            }
+            if outcome != Imported::Merged || nonce != current {
+                transaction = transaction.audit(id, "import", now);
+            }
+            outcomes.push((id, outcome));
+        }
+
+        store.commit(transaction)?;
+        Ok(outcomes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    const PUBKEY_PEM: &str = include_str!("../../../test_data/pem_test.pub");
+    const OTHER_PUBKEY_PEM: &str = include_str!("../../../test_data/bak_pem_test.pub");
+    const NOW: i64 = 1_700_000_000;
+
+    fn enrolled(store: &dyn Store, pem: &str, nonce: u128) -> Device {
+        let device = Device::new(uuid::Uuid::new_v4(), pem.to_string(), NOW);
+        store
+            .commit(
+                Transaction::new()
+                    .put_device(device.clone())
+                    .put_nonce(device.id, nonce),
+            )
+            .unwrap();
+        device
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let store = MemoryStore::new();
+        let device = enrolled(&store, PUBKEY_PEM, 7);
+        let bundle = Bundle::export(&store, NOW).unwrap();
+        assert_eq!(bundle.devices.len(), 1);
+        assert_eq!(bundle.devices[0].nonce, Some(7));
+
+        let plain = bundle.to_bytes(None).unwrap();
+        assert!(!Bundle::is_encrypted(&plain).unwrap());
+        assert_eq!(Bundle::from_bytes(&plain, None).unwrap(), bundle);
+
+        let encrypted = bundle.to_bytes(Some("hunter2")).unwrap();
+        assert!(Bundle::is_encrypted(&encrypted).unwrap());
+        assert!(!String::from_utf8_lossy(&encrypted).contains(&device.id.to_string()));
+        assert_eq!(
+            Bundle::from_bytes(&encrypted, Some("hunter2")).unwrap(),
+            bundle
+        );
+        assert!(Bundle::from_bytes(&encrypted, Some("hunter3")).is_err());
+        assert!(Bundle::from_bytes(&encrypted, None).is_err());
+
+        let target = MemoryStore::new();
+        let outcomes = bundle.import(&target, Conflict::Fail, NOW).unwrap();
+        assert_eq!(outcomes, vec![(device.id, Imported::Added)]);
+        assert_eq!(target.device(&device.id).unwrap(), Some(device.clone()));
+        assert_eq!(target.nonce(&device.id).unwrap(), Some(7));
+    }
+
+    #[test]
+    fn test_nonce_never_goes_backwards() {
+        let source = MemoryStore::new();
+        let device = enrolled(&source, PUBKEY_PEM, 7);
+        let bundle = Bundle::export(&source, NOW).unwrap();
+
+        let target = MemoryStore::new();
+        target
+            .commit(
+                Transaction::new()
+                    .put_device(device.clone())
+                    .put_nonce(device.id, 20),
+            )
+            .unwrap();
+        let outcomes = bundle.import(&target, Conflict::Fail, NOW).unwrap();
+        assert_eq!(outcomes, vec![(device.id, Imported::Merged)]);
+        assert_eq!(target.nonce(&device.id).unwrap(), Some(20));
+        assert!(target.audit_log().unwrap().is_empty());
+
+        source
+            .commit(Transaction::new().put_nonce(device.id, 30))
+            .unwrap();
+        Bundle::export(&source, NOW)
+            .unwrap()
+            .import(&target, Conflict::Fail, NOW)
+            .unwrap();
+        assert_eq!(target.nonce(&device.id).unwrap(), Some(30));
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let source = MemoryStore::new();
+        let device = enrolled(&source, PUBKEY_PEM, 7);
+        let bundle = Bundle::export(&source, NOW).unwrap();
+
+        let target = MemoryStore::new();
+        let other = Device::new(device.id, OTHER_PUBKEY_PEM.to_string(), NOW);
+        target
+            .commit(
+                Transaction::new()
+                    .put_device(other.clone())
+                    .put_nonce(device.id, 10),
+            )
+            .unwrap();
+
+        assert!(bundle.import(&target, Conflict::Fail, NOW).is_err());
+        assert_eq!(target.device(&device.id).unwrap(), Some(other.clone()));
+
+        let outcomes = bundle.import(&target, Conflict::Skip, NOW).unwrap();
+        assert_eq!(outcomes, vec![(device.id, Imported::Skipped)]);
+        assert_eq!(target.device(&device.id).unwrap(), Some(other));
+
+        let outcomes = bundle.import(&target, Conflict::Replace, NOW).unwrap();
+        assert_eq!(outcomes, vec![(device.id, Imported::Replaced)]);
+        assert_eq!(target.device(&device.id).unwrap(), Some(device.clone()));
+        assert_eq!(target.nonce(&device.id).unwrap(), Some(10));
+    }
+
+    #[test]
+    fn test_refuses_newer_bundle() {
+        let bytes = format!(
+            r#"{{"version":{},"bundle":{{"exported":0,"devices":[]}}}}"#,
+            BUNDLE_VERSION + 1
+        );
+        assert!(Bundle::from_bytes(bytes.as_bytes(), None).is_err());
+    }
+}
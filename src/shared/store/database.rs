@@ -0,0 +1,303 @@
+use std::path::Path;
+
+use crate::prelude::*;
+use redb::{Database, ReadableTable, ReadableTableMetadata, TableDefinition};
+use serde::{Deserialize, Serialize};
+
+use super::{AuditEvent, Checked, Device, RegistryKey, Store, Transaction, Write};
+
+// Devices are stored as JSON so fields can be added without a new table
+const DEVICES: TableDefinition<u128, &str> = TableDefinition::new("devices");
+const NONCES: TableDefinition<u128, u128> = TableDefinition::new("nonces");
+const NONCE_SEALS: TableDefinition<u128, &str> = TableDefinition::new("nonce_seals");
+// Keyed by a sequence number to keep events in commit order
+const AUDIT_LOG: TableDefinition<u64, &str> = TableDefinition::new("audit_log");
+
+// redb's errors carry more than fits in an `Error`, log them in full
+fn storage_error(e: impl Into<redb::Error>) -> Error {
+    error!("Database error: {}", e.into());
+    Error::new(ErrorKind::Storage, Some("Database error"))
+}
+
+// A device and the seal that covers it
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredDevice {
+    #[serde(flatten)]
+    device: Device,
+    #[serde(default)]
+    seal: Option<String>,
+}
+
+// Every table in one redb file, each commit is a single write transaction
+pub struct DatabaseStore {
+    db: Database,
+    key: RegistryKey,
+}
+
+impl DatabaseStore {
+    pub fn open(path: &Path, key: RegistryKey) -> Result<DatabaseStore, Error> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let db = Database::create(path).map_err(storage_error)?;
+
+        // Create the tables up front so readers never find them missing
+        let txn = db.begin_write().map_err(storage_error)?;
+        txn.open_table(DEVICES).map_err(storage_error)?;
+        txn.open_table(NONCES).map_err(storage_error)?;
+        txn.open_table(NONCE_SEALS).map_err(storage_error)?;
+        txn.open_table(AUDIT_LOG).map_err(storage_error)?;
+        txn.commit().map_err(storage_error)?;
+
+        Ok(DatabaseStore { db, key })
+    }
+
+    // Opens a database the daemon created without writing to it
+    pub(super) fn open_existing(path: &Path, key: RegistryKey) -> Result<DatabaseStore, Error> {
+        let db = Database::open(path).map_err(storage_error)?;
+        Ok(DatabaseStore { db, key })
+    }
+
+    // Whether any device or nonce carries a seal, valid or not
+    pub(super) fn has_seals(&self) -> Result<bool, Error> {
+        let txn = self.db.begin_read().map_err(storage_error)?;
+        let seals = txn.open_table(NONCE_SEALS).map_err(storage_error)?;
+        if !seals.is_empty().map_err(storage_error)? {
+            return Ok(true);
+        }
+        for entry in txn
+            .open_table(DEVICES)
+            .map_err(storage_error)?
+            .iter()
+            .map_err(storage_error)?
+        {
+            let (_, stored) = entry.map_err(storage_error)?;
+            if serde_json::from_str::<StoredDevice>(stored.value())?
+                .seal
+                .is_some()
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    // The device and its seal as stored
+    fn read_device(&self, id: &uuid::Uuid) -> Result<Option<(Device, Option<String>)>, Error> {
+        let txn = self.db.begin_read().map_err(storage_error)?;
+        let table = txn.open_table(DEVICES).map_err(storage_error)?;
+        match table.get(id.as_u128()).map_err(storage_error)? {
+            Some(stored) => {
+                let stored = serde_json::from_str::<StoredDevice>(stored.value())?;
+                Ok(Some((stored.device, stored.seal)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn read_nonce(&self, id: &uuid::Uuid) -> Result<Option<(u128, Option<String>)>, Error> {
+        let txn = self.db.begin_read().map_err(storage_error)?;
+        let nonces = txn.open_table(NONCES).map_err(storage_error)?;
+        let Some(nonce) = nonces.get(id.as_u128()).map_err(storage_error)? else {
+            return Ok(None);
+        };
+        let seals = txn.open_table(NONCE_SEALS).map_err(storage_error)?;
+        let seal = seals.get(id.as_u128()).map_err(storage_error)?;
+        Ok(Some((
+            nonce.value(),
+            seal.map(|seal| seal.value().to_string()),
+        )))
+    }
+
+    fn check_device(&self, id: &uuid::Uuid) -> Result<Checked<Device>, Error> {
+        Ok(match self.read_device(id)? {
+            Some((device, seal)) => self.key.check_device(device, seal.as_deref()),
+            None => Checked::Missing,
+        })
+    }
+
+    fn check_nonce(&self, id: &uuid::Uuid) -> Result<Checked<u128>, Error> {
+        Ok(match self.read_nonce(id)? {
+            Some((nonce, seal)) => self.key.check_nonce(id, nonce, seal.as_deref()),
+            None => Checked::Missing,
+        })
+    }
+
+    // Devices with a key or a nonce, verified or not
+    fn ids(&self) -> Result<Vec<uuid::Uuid>, Error> {
+        let txn = self.db.begin_read().map_err(storage_error)?;
+        let mut ids = Vec::new();
+        for entry in txn
+            .open_table(DEVICES)
+            .map_err(storage_error)?
+            .iter()
+            .map_err(storage_error)?
+        {
+            ids.push(uuid::Uuid::from_u128(
+                entry.map_err(storage_error)?.0.value(),
+            ));
+        }
+        for entry in txn
+            .open_table(NONCES)
+            .map_err(storage_error)?
+            .iter()
+            .map_err(storage_error)?
+        {
+            ids.push(uuid::Uuid::from_u128(
+                entry.map_err(storage_error)?.0.value(),
+            ));
+        }
+        ids.sort();
+        ids.dedup();
+        Ok(ids)
+    }
+}
+
+impl Store for DatabaseStore {
+    fn device(&self, id: &uuid::Uuid) -> Result<Option<Device>, Error> {
+        self.check_device(id)?.into_result()
+    }
+
+    fn devices(&self) -> Result<Vec<Device>, Error> {
+        let txn = self.db.begin_read().map_err(storage_error)?;
+        let table = txn.open_table(DEVICES).map_err(storage_error)?;
+        let mut devices = Vec::new();
+        for entry in table.iter().map_err(storage_error)? {
+            let (_, stored) = entry.map_err(storage_error)?;
+            let stored = serde_json::from_str::<StoredDevice>(stored.value())?;
+            if let Checked::Valid(device) =
+                self.key.check_device(stored.device, stored.seal.as_deref())
+            {
+                devices.push(device);
+            }
+        }
+        Ok(devices)
+    }
+
+    fn nonce(&self, id: &uuid::Uuid) -> Result<Option<u128>, Error> {
+        self.check_nonce(id)?.into_result()
+    }
+
+    fn rejected(&self) -> Result<Vec<uuid::Uuid>, Error> {
+        let mut rejected = Vec::new();
+        for id in self.ids()? {
+            if self.check_device(&id)? == Checked::Rejected
+                || self.check_nonce(&id)? == Checked::Rejected
+            {
+                rejected.push(id);
+            }
+        }
+        Ok(rejected)
+    }
+
+    fn reseal(&self, id: &uuid::Uuid) -> Result<(), Error> {
+        let mut transaction = Transaction::new();
+        if let Some((device, _)) = self.read_device(id)? {
+            transaction = transaction.put_device(device);
+        }
+        if let Some((nonce, _)) = self.read_nonce(id)? {
+            transaction = transaction.put_nonce(*id, nonce);
+        }
+        self.commit(transaction)
+    }
+
+    fn audit_log(&self) -> Result<Vec<AuditEvent>, Error> {
+        let txn = self.db.begin_read().map_err(storage_error)?;
+        let table = txn.open_table(AUDIT_LOG).map_err(storage_error)?;
+        let mut events = Vec::new();
+        for entry in table.iter().map_err(storage_error)? {
+            let (_, event) = entry.map_err(storage_error)?;
+            events.push(serde_json::from_str(event.value())?);
+        }
+        Ok(events)
+    }
+
+    fn commit(&self, transaction: Transaction) -> Result<(), Error> {
+        let txn = self.db.begin_write().map_err(storage_error)?;
+        {
+            let mut devices = txn.open_table(DEVICES).map_err(storage_error)?;
+            let mut nonces = txn.open_table(NONCES).map_err(storage_error)?;
+            let mut nonce_seals = txn.open_table(NONCE_SEALS).map_err(storage_error)?;
+            let mut audit_log = txn.open_table(AUDIT_LOG).map_err(storage_error)?;
+            let mut sequence = match audit_log.last().map_err(storage_error)? {
+                Some((last, _)) => last.value() + 1,
+                None => 0,
+            };
+
+            for write in transaction.writes() {
+                match write {
+                    Write::PutDevice(device) => {
+                        let json = serde_json::to_string(&StoredDevice {
+                            device: device.clone(),
+                            seal: Some(self.key.seal_device(device)),
+                        })?;
+                        devices
+                            .insert(device.id.as_u128(), json.as_str())
+                            .map_err(storage_error)?;
+                    }
+                    Write::RemoveDevice(id) => {
+                        devices.remove(id.as_u128()).map_err(storage_error)?;
+                        nonces.remove(id.as_u128()).map_err(storage_error)?;
+                        nonce_seals.remove(id.as_u128()).map_err(storage_error)?;
+                    }
+                    Write::PutNonce(id, nonce) => {
+                        let seal = self.key.seal_nonce(id, *nonce);
+                        nonces.insert(id.as_u128(), *nonce).map_err(storage_error)?;
+                        nonce_seals
+                            .insert(id.as_u128(), seal.as_str())
+                            .map_err(storage_error)?;
+                    }
+                    Write::Audit(event) => {
+                        let json = serde_json::to_string(event)?;
+                        audit_log
+                            .insert(sequence, json.as_str())
+                            .map_err(storage_error)?;
+                        sequence += 1;
+                    }
+                }
+            }
+        }
+
+        // Dropping an uncommitted transaction aborts it
+        txn.commit().map_err(storage_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::{check_store, test_config, test_key};
+
+    const PUBKEY_PEM: &str = include_str!("../../../test_data/pem_test.pub");
+
+    #[test]
+    fn test_database_store() {
+        let config = test_config("database_store", "database");
+        check_store(&DatabaseStore::open(&config.database_path(), test_key()).unwrap());
+    }
+
+    #[test]
+    fn test_rejects_records_sealed_by_another_identity() {
+        let config = test_config("database_other_identity", "database");
+        let id = uuid::Uuid::new_v4();
+        {
+            let other =
+                DatabaseStore::open(&config.database_path(), RegistryKey::from_bytes([7; 32]))
+                    .unwrap();
+            let transaction = Transaction::new()
+                .put_device(Device::new(id, PUBKEY_PEM.to_string(), 1_700_000_000))
+                .put_nonce(id, 3);
+            other.commit(transaction).unwrap();
+        }
+
+        let store = DatabaseStore::open(&config.database_path(), test_key()).unwrap();
+        assert!(store.device(&id).is_err());
+        assert!(store.nonce(&id).is_err());
+        assert!(store.devices().unwrap().is_empty());
+        assert_eq!(store.rejected().unwrap(), vec![id]);
+
+        store.reseal(&id).unwrap();
+        assert!(store.rejected().unwrap().is_empty());
+        assert_eq!(store.nonce(&id).unwrap(), Some(3));
+    }
+}
@@ -0,0 +1,536 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write as _};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    sync_parent, write_atomic, AuditEvent, Checked, Device, RegistryKey, Store, Transaction, Write,
+};
+
+const JOURNAL_FILE: &str = "journal.json";
+const AUDIT_LOG_FILE: &str = "audit.log";
+
+// Everything about a device except its key, which stays in `keys/`
+#[derive(Debug, Serialize, Deserialize)]
+struct Metadata {
+    enrolled: i64,
+    // Covers the key, so a dropped in key file has nothing valid to match
+    #[serde(default)]
+    seal: Option<String>,
+}
+
+// A transaction is written here before any of its files are touched and
+// removed once all of them are, so an interrupted commit is redone on open
+#[derive(Debug, Serialize, Deserialize)]
+struct Journal {
+    // Length of the audit log before the transaction, appends are cut back
+    // to it when redoing so events are not logged twice
+    audit_len: u64,
+    transaction: Transaction,
+    // Replaying writes seals, so only journals the daemon wrote are redone
+    seal: String,
+}
+
+impl Journal {
+    fn contents(audit_len: u64, transaction: &Transaction) -> Result<Vec<u8>, Error> {
+        Ok(serde_json::to_vec(&(audit_len, transaction))?)
+    }
+}
+
+// Keys in `keys/<uuid>.pub`, nonces in `nonces/<uuid>` and metadata in
+// `devices/<uuid>.json`, with a JSON lines audit log next to them
+pub struct FileStore {
+    keys_dir: PathBuf,
+    nonce_dir: PathBuf,
+    devices_dir: PathBuf,
+    journal_path: PathBuf,
+    audit_path: PathBuf,
+    key: RegistryKey,
+    commit_lock: Mutex<()>,
+}
+
+impl FileStore {
+    // Touches nothing on disk, for reading a layout that may be incomplete
+    pub(super) fn at(config: &ServerConfig, key: RegistryKey) -> FileStore {
+        let storage_dir = Path::new(config.storage_dir());
+        FileStore {
+            keys_dir: config.keys_dir(),
+            nonce_dir: config.nonce_dir(),
+            devices_dir: config.devices_dir(),
+            journal_path: storage_dir.join(JOURNAL_FILE),
+            audit_path: storage_dir.join(AUDIT_LOG_FILE),
+            key,
+            commit_lock: Mutex::new(()),
+        }
+    }
+
+    pub fn open(config: &ServerConfig, key: RegistryKey) -> Result<FileStore, Error> {
+        let store = FileStore::at(config, key);
+
+        for dir in [&store.keys_dir, &store.nonce_dir, &store.devices_dir] {
+            debug!("Creating storage directory: {:?}", dir);
+            std::fs::create_dir_all(dir)?;
+        }
+        store.replay_journal()?;
+
+        Ok(store)
+    }
+
+    // Reads the storage as it is, an interrupted commit is left for the daemon
+    pub(super) fn open_existing(
+        config: &ServerConfig,
+        key: RegistryKey,
+    ) -> Result<FileStore, Error> {
+        let store = FileStore::at(config, key);
+        if store.journal_path.exists() {
+            error!(
+                "Storage journal {:?} holds an interrupted transaction, start the daemon to redo it",
+                &store.journal_path
+            );
+            return Err(Error::new(
+                ErrorKind::Storage,
+                Some("Interrupted storage transaction"),
+            ));
+        }
+        Ok(store)
+    }
+
+    // Every file and directory the store owns
+    pub fn paths(&self) -> [PathBuf; 5] {
+        [
+            self.keys_dir.clone(),
+            self.nonce_dir.clone(),
+            self.devices_dir.clone(),
+            self.journal_path.clone(),
+            self.audit_path.clone(),
+        ]
+    }
+
+    // Devices whose key predates the metadata files
+    pub(super) fn devices_without_metadata(&self) -> Result<Vec<Device>, Error> {
+        let mut devices = Vec::new();
+        for id in self.ids()? {
+            if !self.metadata_path(&id).exists() {
+                devices.extend(self.read_device(&id)?.map(|(device, _)| device));
+            }
+        }
+        Ok(devices)
+    }
+
+    // Whether any device or nonce carries a seal, valid or not
+    pub(super) fn has_seals(&self) -> Result<bool, Error> {
+        for id in self.ids()? {
+            let device_seal = self.read_device(&id)?.and_then(|(_, seal)| seal);
+            let nonce_seal = self.read_nonce(&id)?.and_then(|(_, seal)| seal);
+            if device_seal.is_some() || nonce_seal.is_some() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    // Metadata as written before devices were sealed
+    pub(super) fn write_unsealed_metadata(&self, device: &Device) -> Result<(), Error> {
+        let metadata = Metadata {
+            enrolled: device.enrolled,
+            seal: None,
+        };
+        write_atomic(
+            &self.metadata_path(&device.id),
+            &serde_json::to_vec(&metadata)?,
+        )
+    }
+
+    fn file_name(id: &uuid::Uuid, extension: &str) -> String {
+        let mut id_buf: [u8; 32] = [0; 32];
+        let id = id.as_simple().encode_lower(&mut id_buf);
+        match extension {
+            "" => id.to_string(),
+            extension => format!("{}.{}", id, extension),
+        }
+    }
+
+    fn key_path(&self, id: &uuid::Uuid) -> PathBuf {
+        self.keys_dir.join(Self::file_name(id, "pub"))
+    }
+
+    fn nonce_path(&self, id: &uuid::Uuid) -> PathBuf {
+        self.nonce_dir.join(Self::file_name(id, ""))
+    }
+
+    fn metadata_path(&self, id: &uuid::Uuid) -> PathBuf {
+        self.devices_dir.join(Self::file_name(id, "json"))
+    }
+
+    // Devices with a key file, verified or not
+    fn ids(&self) -> Result<Vec<uuid::Uuid>, Error> {
+        if !self.keys_dir.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut ids = Vec::new();
+        for entry in std::fs::read_dir(&self.keys_dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("pub") {
+                continue;
+            }
+            let stem = path.file_stem().and_then(|stem| stem.to_str());
+            match stem.map(uuid::Uuid::try_parse) {
+                Some(Ok(id)) => ids.push(id),
+                _ => warn!("Ignoring unexpected key file {:?}", &path),
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    // The device and its seal as found on disk
+    fn read_device(&self, id: &uuid::Uuid) -> Result<Option<(Device, Option<String>)>, Error> {
+        let key_path = self.key_path(id);
+        let public_key = match std::fs::read_to_string(&key_path) {
+            Ok(public_key) => public_key,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        // Keys written before devices had metadata fall back to the key's mtime
+        let (enrolled, seal) = match std::fs::read(self.metadata_path(id)) {
+            Ok(contents) => {
+                let metadata = serde_json::from_slice::<Metadata>(&contents)?;
+                (metadata.enrolled, metadata.seal)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                (std::fs::metadata(&key_path)?.mtime(), None)
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let device = Device {
+            id: *id,
+            public_key,
+            enrolled,
+        };
+        Ok(Some((device, seal)))
+    }
+
+    // Nonce files hold the nonce followed by its seal
+    fn read_nonce(&self, id: &uuid::Uuid) -> Result<Option<(u128, Option<String>)>, Error> {
+        let path = self.nonce_path(id);
+        debug!("Loading nonce from file: {:?}", &path);
+
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                debug!("Nonce file not found: {:?}", &path);
+                return Ok(None);
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        // Resetting to zero would reopen every old signature to replay
+        let mut fields = contents.split_whitespace();
+        match fields.next().map(str::parse::<u128>) {
+            Some(Ok(nonce)) => Ok(Some((nonce, fields.next().map(str::to_string)))),
+            _ => {
+                error!("Corrupt nonce file: {:?}", &path);
+                Err(ErrorKind::CorruptNonce.into())
+            }
+        }
+    }
+
+    fn check_device(&self, id: &uuid::Uuid) -> Result<Checked<Device>, Error> {
+        Ok(match self.read_device(id)? {
+            Some((device, seal)) => self.key.check_device(device, seal.as_deref()),
+            None => Checked::Missing,
+        })
+    }
+
+    fn check_nonce(&self, id: &uuid::Uuid) -> Result<Checked<u128>, Error> {
+        Ok(match self.read_nonce(id)? {
+            Some((nonce, seal)) => self.key.check_nonce(id, nonce, seal.as_deref()),
+            None => Checked::Missing,
+        })
+    }
+
+    fn replay_journal(&self) -> Result<(), Error> {
+        let contents = match std::fs::read(&self.journal_path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+        // Journals are renamed into place whole, so one that does not parse
+        // was damaged afterwards and redoing or dropping it could lose writes
+        let journal = serde_json::from_slice::<Journal>(&contents).map_err(|e| {
+            error!("Corrupt storage journal {:?}: {}", &self.journal_path, e);
+            Error::new(ErrorKind::Storage, Some("Corrupt storage journal"))
+        })?;
+        let sealed = Journal::contents(journal.audit_len, &journal.transaction)?;
+        if !self.key.check(b"journal", &sealed, &journal.seal) {
+            error!(
+                "Storage journal {:?} failed verification",
+                &self.journal_path
+            );
+            return Err(ErrorKind::Tampered.into());
+        }
+
+        warn!("Redoing an interrupted storage transaction");
+        self.apply(&journal)?;
+
+        std::fs::remove_file(&self.journal_path)?;
+        sync_parent(&self.journal_path)
+    }
+
+    fn apply(&self, journal: &Journal) -> Result<(), Error> {
+        let mut audit_log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.audit_path)?;
+        audit_log.set_len(journal.audit_len)?;
+
+        for write in journal.transaction.writes() {
+            match write {
+                Write::PutDevice(device) => {
+                    let metadata = Metadata {
+                        enrolled: device.enrolled,
+                        seal: Some(self.key.seal_device(device)),
+                    };
+                    write_atomic(&self.key_path(&device.id), device.public_key.as_bytes())?;
+                    write_atomic(
+                        &self.metadata_path(&device.id),
+                        &serde_json::to_vec(&metadata)?,
+                    )?;
+                }
+                Write::RemoveDevice(id) => {
+                    // The key goes first, without it the rest is never read
+                    for path in [
+                        self.key_path(id),
+                        self.metadata_path(id),
+                        self.nonce_path(id),
+                    ] {
+                        match std::fs::remove_file(&path) {
+                            Ok(()) => sync_parent(&path)?,
+                            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                            Err(e) => return Err(e.into()),
+                        }
+                    }
+                }
+                Write::PutNonce(id, nonce) => {
+                    debug!("Writing nonce for {}", id);
+                    let contents = format!("{} {}", nonce, self.key.seal_nonce(id, *nonce));
+                    write_atomic(&self.nonce_path(id), contents.as_bytes())?;
+                }
+                Write::Audit(event) => {
+                    let mut line = serde_json::to_vec(event)?;
+                    line.push(b'\n');
+                    audit_log.write_all(&line)?;
+                }
+            }
+        }
+
+        audit_log.sync_all()?;
+        Ok(())
+    }
+}
+
+impl Store for FileStore {
+    fn device(&self, id: &uuid::Uuid) -> Result<Option<Device>, Error> {
+        self.check_device(id)?.into_result()
+    }
+
+    fn devices(&self) -> Result<Vec<Device>, Error> {
+        let mut devices = Vec::new();
+        for id in self.ids()? {
+            if let Checked::Valid(device) = self.check_device(&id)? {
+                devices.push(device);
+            }
+        }
+        Ok(devices)
+    }
+
+    fn nonce(&self, id: &uuid::Uuid) -> Result<Option<u128>, Error> {
+        self.check_nonce(id)?.into_result()
+    }
+
+    fn rejected(&self) -> Result<Vec<uuid::Uuid>, Error> {
+        let mut rejected = Vec::new();
+        for id in self.ids()? {
+            if self.check_device(&id)? == Checked::Rejected
+                || self.check_nonce(&id)? == Checked::Rejected
+            {
+                rejected.push(id);
+            }
+        }
+        Ok(rejected)
+    }
+
+    fn reseal(&self, id: &uuid::Uuid) -> Result<(), Error> {
+        let mut transaction = Transaction::new();
+        if let Some((device, _)) = self.read_device(id)? {
+            transaction = transaction.put_device(device);
+        }
+        if let Some((nonce, _)) = self.read_nonce(id)? {
+            transaction = transaction.put_nonce(*id, nonce);
+        }
+        self.commit(transaction)
+    }
+
+    fn audit_log(&self) -> Result<Vec<AuditEvent>, Error> {
+        let file = match File::open(&self.audit_path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut events = Vec::new();
+        for line in BufReader::new(file).lines() {
+            events.push(serde_json::from_str(&line?)?);
+        }
+        Ok(events)
+    }
+
+    fn commit(&self, transaction: Transaction) -> Result<(), Error> {
+        if transaction.is_empty() {
+            return Ok(());
+        }
+
+        let _guard = self
+            .commit_lock
+            .lock()
+            .map_err(|_| Error::new(ErrorKind::Storage, Some("Store lock poisoned")))?;
+        let audit_len = match std::fs::metadata(&self.audit_path) {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e.into()),
+        };
+        let sealed = Journal::contents(audit_len, &transaction)?;
+        let journal = Journal {
+            audit_len,
+            transaction,
+            seal: self.key.seal(b"journal", &sealed),
+        };
+
+        write_atomic(&self.journal_path, &serde_json::to_vec(&journal)?)?;
+        self.apply(&journal)?;
+        std::fs::remove_file(&self.journal_path)?;
+        sync_parent(&self.journal_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::{check_store, test_config, test_key};
+
+    const PUBKEY_PEM: &str = include_str!("../../../test_data/pem_test.pub");
+
+    #[test]
+    fn test_file_store() {
+        let config = test_config("file_store", "file");
+        check_store(&FileStore::open(&config, test_key()).unwrap());
+    }
+
+    #[test]
+    fn test_redo_interrupted_commit() {
+        let config = test_config("file_journal", "file");
+        let store = FileStore::open(&config, test_key()).unwrap();
+        let id = uuid::Uuid::new_v4();
+        store
+            .commit(
+                Transaction::new()
+                    .put_nonce(id, 1)
+                    .audit(id, "unlock", 1_700_000_000),
+            )
+            .unwrap();
+
+        // Crashed after journaling the next commit and logging its event
+        let audit_len = std::fs::metadata(&store.audit_path).unwrap().len();
+        let transaction = Transaction::new()
+            .put_device(Device::new(id, PUBKEY_PEM.to_string(), 1_700_000_000))
+            .put_nonce(id, 2)
+            .audit(id, "lock", 1_700_000_000);
+        let sealed = Journal::contents(audit_len, &transaction).unwrap();
+        let journal = Journal {
+            audit_len,
+            transaction,
+            seal: test_key().seal(b"journal", &sealed),
+        };
+        std::fs::write(&store.journal_path, serde_json::to_vec(&journal).unwrap()).unwrap();
+        let mut audit_log = OpenOptions::new()
+            .append(true)
+            .open(&store.audit_path)
+            .unwrap();
+        audit_log.write_all(b"{\"time\":").unwrap();
+
+        let store = FileStore::open(&config, test_key()).unwrap();
+        assert!(!store.journal_path.exists());
+        assert!(store.device(&id).unwrap().is_some());
+        assert_eq!(store.nonce(&id).unwrap(), Some(2));
+        assert_eq!(store.audit_log().unwrap().len(), 2);
+
+        std::fs::write(&store.journal_path, b"{\"audit_len\":").unwrap();
+        assert!(FileStore::open(&config, test_key()).is_err());
+
+        // Journals are only redone with the seal the daemon gave them
+        let forged = Journal {
+            seal: RegistryKey::from_bytes([1; 32]).seal(b"journal", &sealed),
+            ..journal
+        };
+        std::fs::write(&store.journal_path, serde_json::to_vec(&forged).unwrap()).unwrap();
+        assert!(FileStore::open(&config, test_key()).is_err());
+    }
+
+    #[test]
+    fn test_refuses_corrupt_nonce() {
+        let config = test_config("corrupt_nonce", "file");
+        let store = FileStore::open(&config, test_key()).unwrap();
+        let id = uuid::Uuid::new_v4();
+
+        for contents in ["", "12abc"] {
+            std::fs::write(store.nonce_path(&id), contents).unwrap();
+            assert!(store.nonce(&id).is_err());
+        }
+    }
+
+    #[test]
+    fn test_rejects_planted_records() {
+        let config = test_config("planted", "file");
+        let store = FileStore::open(&config, test_key()).unwrap();
+        let enrolled = Device::new(uuid::Uuid::new_v4(), PUBKEY_PEM.to_string(), 1_700_000_000);
+        store
+            .commit(
+                Transaction::new()
+                    .put_device(enrolled.clone())
+                    .put_nonce(enrolled.id, 9),
+            )
+            .unwrap();
+
+        // A key dropped in by someone who can write to the storage dir
+        let planted = uuid::Uuid::new_v4();
+        std::fs::write(store.key_path(&planted), PUBKEY_PEM).unwrap();
+        assert!(store.device(&planted).is_err());
+        assert_eq!(store.devices().unwrap(), vec![enrolled.clone()]);
+
+        // Winding a nonce back to reopen old signatures
+        std::fs::write(store.nonce_path(&enrolled.id), "0").unwrap();
+        assert!(store.nonce(&enrolled.id).is_err());
+
+        let mut rejected = store.rejected().unwrap();
+        rejected.sort();
+        let mut expected = vec![enrolled.id, planted];
+        expected.sort();
+        assert_eq!(rejected, expected);
+
+        store.reseal(&enrolled.id).unwrap();
+        assert_eq!(store.nonce(&enrolled.id).unwrap(), Some(0));
+        store
+            .commit(Transaction::new().remove_device(planted))
+            .unwrap();
+        assert!(store.rejected().unwrap().is_empty());
+        assert!(!store.key_path(&planted).exists());
+    }
+}
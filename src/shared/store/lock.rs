@@ -0,0 +1,59 @@
+use std::fs::{File, OpenOptions};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use crate::prelude::*;
+
+const LOCK_FILE: &str = "lock";
+
+// Held by whoever has the storage open, the daemon for as long as it runs.
+// The daemon caches nonces, so anything written behind its back would be
+// overwritten by its next commit.
+pub struct StorageLock {
+    _file: File,
+}
+
+impl StorageLock {
+    pub fn acquire(config: &ServerConfig) -> Result<StorageLock, Error> {
+        std::fs::create_dir_all(config.storage_dir())?;
+        let path = Path::new(config.storage_dir()).join(LOCK_FILE);
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .mode(0o600)
+            .open(&path)?;
+
+        // SAFETY: the descriptor is open for the duration of the call, the
+        // lock is released when the file is closed
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let e = std::io::Error::last_os_error();
+            if e.kind() == std::io::ErrorKind::WouldBlock {
+                error!(
+                    "Storage in {} is in use, stop the daemon first",
+                    config.storage_dir()
+                );
+                return Err(Error::new(ErrorKind::Storage, Some("Storage is in use")));
+            }
+            return Err(e.into());
+        }
+
+        Ok(StorageLock { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::test_config;
+
+    #[test]
+    fn test_lock_is_exclusive() {
+        let config = test_config("lock", "file");
+        let lock = StorageLock::acquire(&config).unwrap();
+        assert!(StorageLock::acquire(&config).is_err());
+        drop(lock);
+        assert!(StorageLock::acquire(&config).is_ok());
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use crate::prelude::*;
+
+use super::{AuditEvent, Device, Store, Transaction, Write};
+
+#[derive(Default)]
+struct Contents {
+    devices: BTreeMap<uuid::Uuid, Device>,
+    nonces: BTreeMap<uuid::Uuid, u128>,
+    audit_log: Vec<AuditEvent>,
+}
+
+// Keeps nothing across restarts, for tests and dry runs
+#[derive(Default)]
+pub struct MemoryStore {
+    contents: Mutex<Contents>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    fn contents(&self) -> Result<std::sync::MutexGuard<'_, Contents>, Error> {
+        self.contents
+            .lock()
+            .map_err(|_| Error::new(ErrorKind::Storage, Some("Store lock poisoned")))
+    }
+}
+
+impl Store for MemoryStore {
+    fn device(&self, id: &uuid::Uuid) -> Result<Option<Device>, Error> {
+        Ok(self.contents()?.devices.get(id).cloned())
+    }
+
+    fn devices(&self) -> Result<Vec<Device>, Error> {
+        Ok(self.contents()?.devices.values().cloned().collect())
+    }
+
+    fn nonce(&self, id: &uuid::Uuid) -> Result<Option<u128>, Error> {
+        Ok(self.contents()?.nonces.get(id).copied())
+    }
+
+    // Nothing outside the process can write to it
+    fn rejected(&self) -> Result<Vec<uuid::Uuid>, Error> {
+        Ok(Vec::new())
+    }
+
+    fn reseal(&self, _id: &uuid::Uuid) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn audit_log(&self) -> Result<Vec<AuditEvent>, Error> {
+        Ok(self.contents()?.audit_log.clone())
+    }
+
+    fn commit(&self, transaction: Transaction) -> Result<(), Error> {
+        let mut contents = self.contents()?;
+        for write in transaction.writes {
+            match write {
+                Write::PutDevice(device) => {
+                    contents.devices.insert(device.id, device);
+                }
+                Write::RemoveDevice(id) => {
+                    contents.devices.remove(&id);
+                    contents.nonces.remove(&id);
+                }
+                Write::PutNonce(id, nonce) => {
+                    contents.nonces.insert(id, nonce);
+                }
+                Write::Audit(event) => contents.audit_log.push(event),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::check_store;
+
+    #[test]
+    fn test_memory_store() {
+        check_store(&MemoryStore::new());
+    }
+}
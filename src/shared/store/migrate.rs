@@ -0,0 +1,464 @@
+use std::path::{Path, PathBuf};
+
+use crate::prelude::*;
+
+use super::{write_atomic, DatabaseStore, FileStore, RegistryKey, Store};
+
+// Layout of the storage dir this build reads and writes. Bumping it means
+// appending a migration from the previous version to `MIGRATIONS`.
+pub const STORAGE_VERSION: u32 = 2;
+
+const VERSION_FILE: &str = "storage_version";
+const VERSION_KIND: &[u8] = b"storage_version";
+const BACKUPS_DIR: &str = "backups";
+
+struct Migration {
+    // Version the storage is at afterwards, it runs on `version - 1`
+    version: u32,
+    description: &'static str,
+    // Describes each change `apply` would make without making it
+    plan: fn(&ServerConfig, &RegistryKey) -> Result<Vec<String>, Error>,
+    apply: fn(&ServerConfig, &RegistryKey) -> Result<(), Error>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Record when each device enrolled",
+        plan: plan_enrollment_times,
+        apply: record_enrollment_times,
+    },
+    Migration {
+        version: 2,
+        description: "Seal devices and nonces with the server identity",
+        plan: plan_seals,
+        apply: seal_registry,
+    },
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedMigration {
+    pub version: u32,
+    pub description: &'static str,
+    pub changes: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationPlan {
+    pub from: u32,
+    pub to: u32,
+    pub migrations: Vec<PlannedMigration>,
+}
+
+impl MigrationPlan {
+    pub fn is_empty(&self) -> bool {
+        self.migrations.is_empty()
+    }
+}
+
+fn version_path(config: &ServerConfig) -> PathBuf {
+    Path::new(config.storage_dir()).join(VERSION_FILE)
+}
+
+pub fn backups_dir(config: &ServerConfig) -> PathBuf {
+    Path::new(config.storage_dir()).join(BACKUPS_DIR)
+}
+
+// The marker holds the version and its seal, so it cannot be lowered to rerun
+// a migration over records written since
+fn write_version(config: &ServerConfig, key: &RegistryKey, version: u32) -> Result<(), Error> {
+    let version = version.to_string();
+    let contents = format!("{} {}", version, key.seal(VERSION_KIND, version.as_bytes()));
+    write_atomic(&version_path(config), contents.as_bytes())
+}
+
+// The version and its seal as found on disk, None without a marker
+fn read_version(config: &ServerConfig) -> Result<Option<(u32, Option<String>)>, Error> {
+    let path = version_path(config);
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut fields = contents.split_whitespace();
+    match fields.next().map(str::parse::<u32>) {
+        Some(Ok(version)) => Ok(Some((version, fields.next().map(str::to_string)))),
+        _ => {
+            error!("Corrupt storage version in {:?}", &path);
+            Err(Error::new(
+                ErrorKind::Storage,
+                Some("Corrupt storage version"),
+            ))
+        }
+    }
+}
+
+// Storage without a marker is from before versioning if it has the old key
+// and nonce directories, otherwise it is new. Markers written before they
+// were sealed are taken as they are unless the registry already carries
+// seals, then the storage is at the current version whatever they say.
+pub fn storage_version(config: &ServerConfig, key: &RegistryKey) -> Result<u32, Error> {
+    let marker = read_version(config)?;
+    if let Some((version, Some(seal))) = &marker {
+        if !key.check(VERSION_KIND, version.to_string().as_bytes(), seal) {
+            error!(
+                "Storage version in {:?} failed verification",
+                version_path(config)
+            );
+            return Err(ErrorKind::Tampered.into());
+        }
+        return Ok(*version);
+    }
+
+    if registry_sealed(config, key)? {
+        if marker.map(|(version, _)| version) != Some(STORAGE_VERSION) {
+            warn!("Storage version marker is missing or unsealed, keeping the current version");
+        }
+        return Ok(STORAGE_VERSION);
+    }
+    match marker {
+        Some((version, _)) => Ok(version),
+        None if config.keys_dir().is_dir() || config.nonce_dir().is_dir() => Ok(0),
+        None => Ok(STORAGE_VERSION),
+    }
+}
+
+// Whether the current version is recorded with a valid seal
+pub fn is_current(config: &ServerConfig, key: &RegistryKey) -> Result<bool, Error> {
+    Ok(match read_version(config)? {
+        Some((version, Some(seal))) => {
+            version == STORAGE_VERSION
+                && key.check(VERSION_KIND, version.to_string().as_bytes(), &seal)
+        }
+        _ => false,
+    })
+}
+
+pub fn plan(config: &ServerConfig, key: &RegistryKey) -> Result<MigrationPlan, Error> {
+    let from = storage_version(config, key)?;
+    if from > STORAGE_VERSION {
+        error!(
+            "Storage is at version {}, this build only knows up to {}",
+            from, STORAGE_VERSION
+        );
+        return Err(Error::new(
+            ErrorKind::UnsupportedVersion,
+            Some("Storage is newer than this build"),
+        ));
+    }
+
+    let mut migrations = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|m| m.version > from) {
+        migrations.push(PlannedMigration {
+            version: migration.version,
+            description: migration.description,
+            changes: (migration.plan)(config, key)?,
+        });
+    }
+
+    Ok(MigrationPlan {
+        from,
+        to: STORAGE_VERSION,
+        migrations,
+    })
+}
+
+// Brings the storage up to `STORAGE_VERSION`, returning the backup taken
+// beforehand if anything had to change. The version is recorded after each
+// migration so an interrupted run resumes where it stopped.
+pub fn migrate(config: &ServerConfig, key: &RegistryKey) -> Result<Option<PathBuf>, Error> {
+    let plan = plan(config, key)?;
+    std::fs::create_dir_all(config.storage_dir())?;
+    if plan.is_empty() {
+        if !is_current(config, key)? {
+            write_version(config, key, STORAGE_VERSION)?;
+        }
+        return Ok(None);
+    }
+
+    let backup = backup(config, plan.from)?;
+    info!("Storage backed up to {:?}", &backup);
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > plan.from) {
+        info!(
+            "Migrating storage to version {}: {}",
+            migration.version, migration.description
+        );
+        (migration.apply)(config, key)?;
+        write_version(config, key, migration.version)?;
+    }
+
+    Ok(Some(backup))
+}
+
+// Copies everything in the storage dir except earlier backups
+fn backup(config: &ServerConfig, version: u32) -> Result<PathBuf, Error> {
+    let storage_dir = Path::new(config.storage_dir());
+    let backups_dir = backups_dir(config);
+    let target = backups_dir.join(format!(
+        "v{}-{}",
+        version,
+        chrono::Utc::now().format("%Y%m%dT%H%M%S")
+    ));
+
+    std::fs::create_dir_all(&target)?;
+    for entry in std::fs::read_dir(storage_dir)? {
+        let path = entry?.path();
+        if path != backups_dir {
+            copy_recursive(&path, &target.join(entry_name(&path)?))?;
+        }
+    }
+
+    Ok(target)
+}
+
+fn entry_name(path: &Path) -> Result<&std::ffi::OsStr, Error> {
+    path.file_name().ok_or(Error::new(
+        ErrorKind::Storage,
+        Some("Unnamed storage entry"),
+    ))
+}
+
+fn copy_recursive(from: &Path, to: &Path) -> Result<(), Error> {
+    if from.is_dir() {
+        std::fs::create_dir_all(to)?;
+        for entry in std::fs::read_dir(from)? {
+            let path = entry?.path();
+            copy_recursive(&path, &to.join(entry_name(&path)?))?;
+        }
+    } else {
+        std::fs::copy(from, to)?;
+    }
+
+    Ok(())
+}
+
+// Version 1: keys carried their enrollment time only as the file's mtime,
+// which copying or restoring the storage dir would lose
+
+fn plan_enrollment_times(config: &ServerConfig, key: &RegistryKey) -> Result<Vec<String>, Error> {
+    let devices = FileStore::at(config, key.clone()).devices_without_metadata()?;
+    Ok(devices
+        .iter()
+        .map(|device| {
+            let mut id_buf = [0u8; 32];
+            let enrolled = chrono::DateTime::from_timestamp(device.enrolled, 0)
+                .map(|enrolled| enrolled.to_rfc3339())
+                .unwrap_or(device.enrolled.to_string());
+            format!(
+                "Record enrollment of {} at {}",
+                device.id.as_simple().encode_lower(&mut id_buf),
+                enrolled
+            )
+        })
+        .collect())
+}
+
+// Leaves the devices unsealed, sealing is up to the next migration
+fn record_enrollment_times(config: &ServerConfig, key: &RegistryKey) -> Result<(), Error> {
+    let store = FileStore::open(config, key.clone())?;
+    for device in store.devices_without_metadata()? {
+        store.write_unsealed_metadata(&device)?;
+    }
+    Ok(())
+}
+
+// Version 2: devices and nonces carry a MAC under a key derived from the
+// server identity. Whatever is in the registry when this runs is trusted,
+// the dry run lists it for review first. Once anything carries a seal the
+// registry is never trusted wholesale again, records failing verification
+// stay rejected until `cli storage repair` is told to trust them.
+
+// Both layouts may hold devices, the database after an import
+fn unsealed_stores(config: &ServerConfig, key: &RegistryKey) -> Result<Vec<Box<dyn Store>>, Error> {
+    let mut stores: Vec<Box<dyn Store>> = Vec::new();
+    if config.keys_dir().is_dir() {
+        stores.push(Box::new(FileStore::at(config, key.clone())));
+    }
+    // Opening creates the table of nonce seals, records stay untouched
+    if config.database_path().exists() {
+        stores.push(Box::new(DatabaseStore::open(
+            &config.database_path(),
+            key.clone(),
+        )?));
+    }
+    Ok(stores)
+}
+
+// Whether any record carries a seal, valid or not
+fn registry_sealed(config: &ServerConfig, key: &RegistryKey) -> Result<bool, Error> {
+    if config.keys_dir().is_dir() && FileStore::at(config, key.clone()).has_seals()? {
+        return Ok(true);
+    }
+    if config.database_path().exists() {
+        return DatabaseStore::open(&config.database_path(), key.clone())?.has_seals();
+    }
+    Ok(false)
+}
+
+fn plan_seals(config: &ServerConfig, key: &RegistryKey) -> Result<Vec<String>, Error> {
+    let sealed = registry_sealed(config, key)?;
+    let mut changes = Vec::new();
+    for store in unsealed_stores(config, key)? {
+        for id in store.rejected()? {
+            let mut id_buf = [0u8; 32];
+            let id = id.as_simple().encode_lower(&mut id_buf);
+            changes.push(match sealed {
+                true => format!("Leave device {} rejected for review", id),
+                false => format!("Seal device {}", id),
+            });
+        }
+    }
+    Ok(changes)
+}
+
+fn seal_registry(config: &ServerConfig, key: &RegistryKey) -> Result<(), Error> {
+    if config.keys_dir().is_dir() {
+        // Redoes an interrupted commit before resealing
+        FileStore::open(config, key.clone())?;
+    }
+    if registry_sealed(config, key)? {
+        warn!("Registry is already sealed, review rejected devices with `cli storage verify`");
+        return Ok(());
+    }
+    for store in unsealed_stores(config, key)? {
+        for id in store.rejected()? {
+            store.reseal(&id)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::{test_config, test_key};
+
+    const PUBKEY_PEM: &str = include_str!("../../../test_data/pem_test.pub");
+
+    // Storage as the daemon wrote it before versioning
+    fn legacy_storage(name: &str) -> (ServerConfig, uuid::Uuid) {
+        let config = test_config(name, "file");
+        let id = uuid::Uuid::new_v4();
+        let mut id_buf = [0u8; 32];
+        let name = id.as_simple().encode_lower(&mut id_buf);
+        std::fs::create_dir_all(config.keys_dir()).unwrap();
+        std::fs::create_dir_all(config.nonce_dir()).unwrap();
+        std::fs::write(config.keys_dir().join(format!("{}.pub", name)), PUBKEY_PEM).unwrap();
+        std::fs::write(config.nonce_dir().join(&*name), "42").unwrap();
+        (config, id)
+    }
+
+    #[test]
+    fn test_plan_changes_nothing() {
+        let (config, _) = legacy_storage("migrate_plan");
+        let plan = plan(&config, &test_key()).unwrap();
+        assert_eq!((plan.from, plan.to), (0, STORAGE_VERSION));
+        assert_eq!(plan.migrations.len(), 2);
+        assert_eq!(plan.migrations[0].changes.len(), 1);
+        assert_eq!(plan.migrations[1].changes.len(), 1);
+
+        assert!(!version_path(&config).exists());
+        assert!(!config.devices_dir().exists());
+        assert!(!backups_dir(&config).exists());
+    }
+
+    #[test]
+    fn test_migrate_legacy_storage() {
+        let (config, id) = legacy_storage("migrate_legacy");
+        let legacy = FileStore::at(&config, test_key());
+        let enrolled = legacy.devices_without_metadata().unwrap()[0].enrolled;
+        assert_eq!(legacy.rejected().unwrap(), vec![id]);
+
+        let backup = migrate(&config, &test_key()).unwrap().unwrap();
+        assert!(backup.join("keys").is_dir());
+        assert!(backup.join("nonces").is_dir());
+        assert_eq!(
+            storage_version(&config, &test_key()).unwrap(),
+            STORAGE_VERSION
+        );
+
+        let store = FileStore::open(&config, test_key()).unwrap();
+        assert!(store.devices_without_metadata().unwrap().is_empty());
+        assert!(store.rejected().unwrap().is_empty());
+        assert_eq!(store.device(&id).unwrap().unwrap().enrolled, enrolled);
+        assert_eq!(store.nonce(&id).unwrap(), Some(42));
+
+        assert!(plan(&config, &test_key()).unwrap().is_empty());
+        assert_eq!(migrate(&config, &test_key()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_new_storage_needs_no_migration() {
+        let config = test_config("migrate_new", "database");
+        assert!(plan(&config, &test_key()).unwrap().is_empty());
+        assert_eq!(migrate(&config, &test_key()).unwrap(), None);
+        assert!(is_current(&config, &test_key()).unwrap());
+    }
+
+    #[test]
+    fn test_rejects_forged_version() {
+        let config = test_config("migrate_forged", "database");
+        std::fs::create_dir_all(config.storage_dir()).unwrap();
+        let other = RegistryKey::from_bytes([7; 32]);
+        write_version(&config, &other, 0).unwrap();
+        assert!(storage_version(&config, &test_key()).is_err());
+        assert!(migrate(&config, &test_key()).is_err());
+    }
+
+    #[test]
+    fn test_lowered_version_does_not_reseal() {
+        let (config, _) = legacy_storage("migrate_lowered");
+        migrate(&config, &test_key()).unwrap();
+
+        // A key dropped in after the registry was sealed
+        let dropped = uuid::Uuid::new_v4();
+        let mut id_buf = [0u8; 32];
+        let name = dropped.as_simple().encode_lower(&mut id_buf);
+        std::fs::write(config.keys_dir().join(format!("{}.pub", name)), PUBKEY_PEM).unwrap();
+
+        for marker in [None, Some("0"), Some("1")] {
+            match marker {
+                Some(marker) => std::fs::write(version_path(&config), marker).unwrap(),
+                None => std::fs::remove_file(version_path(&config)).unwrap(),
+            }
+            assert_eq!(
+                storage_version(&config, &test_key()).unwrap(),
+                STORAGE_VERSION
+            );
+            assert_eq!(migrate(&config, &test_key()).unwrap(), None);
+            assert!(is_current(&config, &test_key()).unwrap());
+
+            let store = FileStore::open(&config, test_key()).unwrap();
+            assert_eq!(store.rejected().unwrap(), vec![dropped]);
+        }
+    }
+
+    #[test]
+    fn test_sealed_registry_is_not_resealed() {
+        let (config, _) = legacy_storage("migrate_sealed");
+        migrate(&config, &test_key()).unwrap();
+        let dropped = uuid::Uuid::new_v4();
+        let mut id_buf = [0u8; 32];
+        let name = dropped.as_simple().encode_lower(&mut id_buf);
+        std::fs::write(config.keys_dir().join(format!("{}.pub", name)), PUBKEY_PEM).unwrap();
+
+        // Even a genuine marker from before the registry was sealed
+        write_version(&config, &test_key(), 1).unwrap();
+        let plan = plan(&config, &test_key()).unwrap();
+        assert!(plan.migrations[0].changes[0].starts_with("Leave device"));
+        migrate(&config, &test_key()).unwrap();
+        let store = FileStore::open(&config, test_key()).unwrap();
+        assert_eq!(store.rejected().unwrap(), vec![dropped]);
+    }
+
+    #[test]
+    fn test_refuses_newer_storage() {
+        let config = test_config("migrate_newer", "database");
+        std::fs::create_dir_all(config.storage_dir()).unwrap();
+        write_version(&config, &test_key(), STORAGE_VERSION + 1).unwrap();
+        assert!(plan(&config, &test_key()).is_err());
+        assert!(migrate(&config, &test_key()).is_err());
+    }
+}
@@ -0,0 +1,440 @@
+use std::fs::File;
+use std::io::Write as _;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::config::StorageBackend;
+use crate::crypto::key::PublicKey;
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub mod bundle;
+pub mod database;
+pub mod file;
+pub mod lock;
+pub mod memory;
+pub mod migrate;
+pub mod seal;
+
+pub use database::DatabaseStore;
+pub use file::FileStore;
+pub use lock::StorageLock;
+pub use memory::MemoryStore;
+pub use seal::{Checked, RegistryKey};
+
+// Shared between the server loop and the control socket thread
+pub type SharedStore = Arc<dyn Store>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Device {
+    pub id: uuid::Uuid,
+    // PEM encoded public key the device signs requests with
+    pub public_key: String,
+    // Unix time of enrollment
+    pub enrolled: i64,
+}
+
+impl Device {
+    pub fn new(id: uuid::Uuid, public_key: String, enrolled: i64) -> Device {
+        Device {
+            id,
+            public_key,
+            enrolled,
+        }
+    }
+
+    pub fn public_key(&self) -> Result<PublicKey, Error> {
+        PublicKey::from_pem(self.public_key.as_bytes())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub time: i64,
+    pub device: uuid::Uuid,
+    pub action: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Write {
+    PutDevice(Device),
+    // Drops the device's key, metadata and nonce
+    RemoveDevice(uuid::Uuid),
+    PutNonce(uuid::Uuid, u128),
+    Audit(AuditEvent),
+}
+
+// Writes that are applied together or not at all
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transaction {
+    writes: Vec<Write>,
+}
+
+impl Transaction {
+    pub fn new() -> Transaction {
+        Transaction::default()
+    }
+
+    pub fn put_device(mut self, device: Device) -> Transaction {
+        self.writes.push(Write::PutDevice(device));
+        self
+    }
+
+    pub fn remove_device(mut self, id: uuid::Uuid) -> Transaction {
+        self.writes.push(Write::RemoveDevice(id));
+        self
+    }
+
+    pub fn put_nonce(mut self, id: uuid::Uuid, nonce: u128) -> Transaction {
+        self.writes.push(Write::PutNonce(id, nonce));
+        self
+    }
+
+    // `time` is Unix time, from the caller's clock
+    pub fn audit(mut self, device: uuid::Uuid, action: &str, time: i64) -> Transaction {
+        self.writes.push(Write::Audit(AuditEvent {
+            time,
+            device,
+            action: action.to_string(),
+        }));
+        self
+    }
+
+    pub fn writes(&self) -> &[Write] {
+        &self.writes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+}
+
+// Devices and nonces are sealed with the `RegistryKey` when written. Those
+// failing verification are refused by `device` and `nonce` and left out of
+// `devices` until repaired.
+pub trait Store: Send + Sync {
+    fn device(&self, id: &uuid::Uuid) -> Result<Option<Device>, Error>;
+
+    fn devices(&self) -> Result<Vec<Device>, Error>;
+
+    // None for devices that never had a nonce stored
+    fn nonce(&self, id: &uuid::Uuid) -> Result<Option<u128>, Error>;
+
+    // Devices with a key or nonce that failed verification
+    fn rejected(&self) -> Result<Vec<uuid::Uuid>, Error>;
+
+    // Accepts a rejected device's records as they are, sealing them again
+    fn reseal(&self, id: &uuid::Uuid) -> Result<(), Error>;
+
+    // Oldest first
+    fn audit_log(&self) -> Result<Vec<AuditEvent>, Error>;
+
+    // After a crash either every write of the transaction is visible or none
+    fn commit(&self, transaction: Transaction) -> Result<(), Error>;
+}
+
+// Keeps the storage locked for as long as the store is in use
+struct LockedStore<S> {
+    store: S,
+    _lock: StorageLock,
+}
+
+impl<S: Store> Store for LockedStore<S> {
+    fn device(&self, id: &uuid::Uuid) -> Result<Option<Device>, Error> {
+        self.store.device(id)
+    }
+
+    fn devices(&self) -> Result<Vec<Device>, Error> {
+        self.store.devices()
+    }
+
+    fn nonce(&self, id: &uuid::Uuid) -> Result<Option<u128>, Error> {
+        self.store.nonce(id)
+    }
+
+    fn rejected(&self) -> Result<Vec<uuid::Uuid>, Error> {
+        self.store.rejected()
+    }
+
+    fn reseal(&self, id: &uuid::Uuid) -> Result<(), Error> {
+        self.store.reseal(id)
+    }
+
+    fn audit_log(&self) -> Result<Vec<AuditEvent>, Error> {
+        self.store.audit_log()
+    }
+
+    fn commit(&self, transaction: Transaction) -> Result<(), Error> {
+        self.store.commit(transaction)
+    }
+}
+
+// Migrates the storage to the current version before opening it, records
+// failing verification are reported but do not stop the daemon. The storage
+// stays locked until the store is dropped, a second open fails.
+pub fn open(config: &ServerConfig) -> Result<SharedStore, Error> {
+    let lock = StorageLock::acquire(config)?;
+    let key = RegistryKey::load(config)?;
+    migrate::migrate(config, &key)?;
+    let store: SharedStore = match config.storage_backend() {
+        StorageBackend::File => {
+            info!("Using file storage in {}", config.storage_dir());
+            Arc::new(LockedStore {
+                store: FileStore::open(config, key)?,
+                _lock: lock,
+            })
+        }
+        StorageBackend::Database => {
+            let path = config.database_path();
+            info!("Using database storage at {:?}", &path);
+            let store = DatabaseStore::open(&path, key.clone())?;
+            migrate_file_layout(config, &store, key)?;
+            Arc::new(LockedStore { store, _lock: lock })
+        }
+    };
+
+    let rejected = store.rejected()?;
+    if !rejected.is_empty() {
+        error!(
+            "{} devices failed verification and are refused, see `cli storage verify`",
+            rejected.len()
+        );
+    }
+    Ok(store)
+}
+
+// Opens the storage for inspection without migrating it or redoing an
+// interrupted commit, so nothing in it is sealed anew before it is checked
+pub fn open_existing(config: &ServerConfig) -> Result<SharedStore, Error> {
+    let lock = StorageLock::acquire(config)?;
+    let key = RegistryKey::load(config)?;
+    if !migrate::is_current(config, &key)? {
+        error!(
+            "Storage is not recorded at version {}, see `cli storage migrate --dry-run`",
+            migrate::STORAGE_VERSION
+        );
+        return Err(Error::new(
+            ErrorKind::UnsupportedVersion,
+            Some("Storage is not at the current version"),
+        ));
+    }
+
+    Ok(match config.storage_backend() {
+        StorageBackend::File => Arc::new(LockedStore {
+            store: FileStore::open_existing(config, key)?,
+            _lock: lock,
+        }),
+        StorageBackend::Database => {
+            if config.keys_dir().is_dir() {
+                error!("File storage has not been imported into the database yet");
+                return Err(Error::new(
+                    ErrorKind::Storage,
+                    Some("File storage not imported yet"),
+                ));
+            }
+            Arc::new(LockedStore {
+                store: DatabaseStore::open_existing(&config.database_path(), key)?,
+                _lock: lock,
+            })
+        }
+    })
+}
+
+// Moves devices, nonces and the audit log of the file layout into `store` in
+// a single transaction, then sets the old files aside. Importing again after
+// a crash before the rename only rewrites the same values.
+fn migrate_file_layout(
+    config: &ServerConfig,
+    store: &dyn Store,
+    key: RegistryKey,
+) -> Result<(), Error> {
+    let keys_dir = config.keys_dir();
+    if !keys_dir.is_dir() {
+        return Ok(());
+    }
+
+    // Records failing verification would be sealed anew by the import
+    let files = FileStore::open(config, key)?;
+    if !files.rejected()?.is_empty() {
+        error!("Repair the file storage with the file backend before importing it");
+        return Err(ErrorKind::Tampered.into());
+    }
+    let mut transaction = Transaction::new();
+    let devices = files.devices()?;
+    for device in &devices {
+        let id = device.id;
+        transaction = transaction.put_device(device.clone());
+        if let Some(nonce) = files.nonce(&id)? {
+            transaction = transaction.put_nonce(id, nonce);
+        }
+    }
+    for event in files.audit_log()? {
+        transaction.writes.push(Write::Audit(event));
+    }
+
+    info!("Migrating {} devices to the database", devices.len());
+    store.commit(transaction)?;
+
+    let suffix = format!("migrated-{}", chrono::Utc::now().timestamp());
+    for path in files.paths() {
+        if path.exists() {
+            let mut target = path.clone().into_os_string();
+            target.push(".");
+            target.push(&suffix);
+            debug!("Moving {:?} to {:?}", &path, &target);
+            std::fs::rename(&path, target)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Written to a temporary file and renamed over the old one, so a crash
+// leaves either the old or the new contents on disk
+fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), Error> {
+    let tmp_path = path.with_extension("tmp");
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, path)?;
+    sync_parent(path)
+}
+
+// Renames and removals are only durable once the directory is synced
+fn sync_parent(path: &Path) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Overrides;
+
+    const PRIVATE_KEY_PEM: &str = include_str!("../../../test_data/pem_test");
+    const PUBKEY_PEM: &str = include_str!("../../../test_data/pem_test.pub");
+
+    pub fn test_key() -> RegistryKey {
+        RegistryKey::from_bytes([42; 32])
+    }
+
+    pub fn test_config(name: &str, backend: &str) -> ServerConfig {
+        let storage_dir = std::env::temp_dir().join(format!(
+            "remote_unlock_test_{}_{}",
+            name,
+            rand::random::<u32>()
+        ));
+        let overrides = Overrides::new()
+            .set("storage_dir", storage_dir.to_str().unwrap())
+            .set("storage_backend", backend);
+        ServerConfig::load_from(&[], |_| None, &overrides).unwrap()
+    }
+
+    // Readable by the daemon user only, as the daemon writes it
+    fn write_identity(config: &ServerConfig) {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = config.identity_key_path();
+        std::fs::write(&path, PRIVATE_KEY_PEM).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+    }
+
+    // Behaviour every backend has to share
+    pub fn check_store(store: &dyn Store) {
+        let id = uuid::Uuid::new_v4();
+        assert_eq!(store.device(&id).unwrap(), None);
+        assert_eq!(store.nonce(&id).unwrap(), None);
+
+        let device = Device::new(id, PUBKEY_PEM.to_string(), 1_700_000_000);
+        store
+            .commit(
+                Transaction::new()
+                    .put_device(device.clone())
+                    .put_nonce(id, 3)
+                    .audit(id, "enroll", 1_700_000_000),
+            )
+            .unwrap();
+        assert_eq!(store.device(&id).unwrap(), Some(device.clone()));
+        assert!(store.device(&id).unwrap().unwrap().public_key().is_ok());
+        assert_eq!(store.devices().unwrap(), vec![device]);
+        assert_eq!(store.nonce(&id).unwrap(), Some(3));
+
+        store
+            .commit(
+                Transaction::new()
+                    .put_nonce(id, 7)
+                    .audit(id, "unlock", 1_700_000_000),
+            )
+            .unwrap();
+        assert_eq!(store.nonce(&id).unwrap(), Some(7));
+
+        let actions: Vec<_> = store
+            .audit_log()
+            .unwrap()
+            .into_iter()
+            .map(|event| event.action)
+            .collect();
+        assert_eq!(actions, ["enroll", "unlock"]);
+        assert!(store.rejected().unwrap().is_empty());
+
+        store.commit(Transaction::new().remove_device(id)).unwrap();
+        assert_eq!(store.device(&id).unwrap(), None);
+        assert_eq!(store.nonce(&id).unwrap(), None);
+        assert!(store.devices().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_migrate_file_layout() {
+        let config = test_config("migrate", "database");
+        let id = uuid::Uuid::new_v4();
+
+        // Keys and nonces as written before devices had metadata or seals
+        let mut id_buf = [0u8; 32];
+        let name = id.as_simple().encode_lower(&mut id_buf);
+        std::fs::create_dir_all(config.keys_dir()).unwrap();
+        std::fs::create_dir_all(config.nonce_dir()).unwrap();
+        write_identity(&config);
+        std::fs::write(config.keys_dir().join(format!("{}.pub", name)), PUBKEY_PEM).unwrap();
+        std::fs::write(config.nonce_dir().join(&*name), "42").unwrap();
+
+        let store = open(&config).unwrap();
+        let device = store.device(&id).unwrap().unwrap();
+        assert_eq!(device.public_key, PUBKEY_PEM);
+        assert_eq!(store.nonce(&id).unwrap(), Some(42));
+        assert!(!config.keys_dir().exists());
+        assert!(!config.nonce_dir().exists());
+
+        // Nothing left to migrate on the next start
+        drop(store);
+        let store = open(&config).unwrap();
+        assert_eq!(store.devices().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_open_existing_does_not_migrate() {
+        let config = test_config("open_existing", "file");
+        std::fs::create_dir_all(config.storage_dir()).unwrap();
+        write_identity(&config);
+        assert!(open_existing(&config).is_err());
+
+        let store = open(&config).unwrap();
+        assert!(open_existing(&config).is_err());
+        drop(store);
+        let store = open_existing(&config).unwrap();
+        assert!(store.rejected().unwrap().is_empty());
+
+        // A dropped in key behind a lowered marker is reported, not sealed
+        let id = uuid::Uuid::new_v4();
+        let mut id_buf = [0u8; 32];
+        let name = id.as_simple().encode_lower(&mut id_buf);
+        std::fs::write(config.keys_dir().join(format!("{}.pub", name)), PUBKEY_PEM).unwrap();
+        assert_eq!(store.rejected().unwrap(), vec![id]);
+        drop(store);
+        std::fs::write(Path::new(config.storage_dir()).join("storage_version"), "1").unwrap();
+        assert!(open_existing(&config).is_err());
+        let files = FileStore::at(&config, RegistryKey::load(&config).unwrap());
+        assert_eq!(files.rejected().unwrap(), vec![id]);
+    }
+}
@@ -0,0 +1,239 @@
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use base64::prelude::*;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::crypto::key::PrivateKey;
+use crate::prelude::*;
+
+use super::Device;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const KEY_LABEL: &[u8] = b"remote-unlock registry v1";
+
+// Outcome of reading a record that carries a seal
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Checked<T> {
+    Missing,
+    Valid(T),
+    // Present but not sealed by this server's identity
+    Rejected,
+}
+
+impl<T> Checked<T> {
+    // Rejected records are refused like corrupt ones
+    pub fn into_result(self) -> Result<Option<T>, Error> {
+        match self {
+            Checked::Missing => Ok(None),
+            Checked::Valid(value) => Ok(Some(value)),
+            Checked::Rejected => Err(ErrorKind::Tampered.into()),
+        }
+    }
+}
+
+// MAC key for registry records. It is derived from the server identity, so
+// a key or nonce written by anyone without the identity fails verification.
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct RegistryKey([u8; 32]);
+
+impl RegistryKey {
+    pub fn derive(identity: &PrivateKey) -> RegistryKey {
+        let mut mac = HmacSha256::new_from_slice(identity.inner().as_bytes())
+            .expect("HMAC takes keys of any length");
+        mac.update(KEY_LABEL);
+        RegistryKey(mac.finalize().into_bytes().into())
+    }
+
+    // Reads the identity the daemon generated on its first start
+    pub fn load(config: &ServerConfig) -> Result<RegistryKey, Error> {
+        let path = config.identity_key_path();
+        check_identity_file(&path)?;
+        let identity = PrivateKey::read_pem_file(&path).map_err(|e| {
+            error!("Cannot read the server identity at {:?}: {}", &path, e);
+            e
+        })?;
+        Ok(RegistryKey::derive(&identity))
+    }
+
+    #[cfg(test)]
+    pub(crate) fn from_bytes(bytes: [u8; 32]) -> RegistryKey {
+        RegistryKey(bytes)
+    }
+
+    // Fields are length prefixed so no two records share an encoding
+    fn mac(&self, kind: &[u8], fields: &[&[u8]]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.0).expect("HMAC takes keys of any length");
+        for field in [kind].iter().chain(fields) {
+            mac.update(&(field.len() as u32).to_be_bytes());
+            mac.update(field);
+        }
+        mac
+    }
+
+    fn device_mac(&self, device: &Device) -> HmacSha256 {
+        self.mac(
+            b"device",
+            &[
+                device.id.as_bytes(),
+                device.public_key.as_bytes(),
+                &device.enrolled.to_be_bytes(),
+            ],
+        )
+    }
+
+    fn nonce_mac(&self, id: &uuid::Uuid, nonce: u128) -> HmacSha256 {
+        self.mac(b"nonce", &[id.as_bytes(), &nonce.to_be_bytes()])
+    }
+
+    // For anything else the store writes, such as the file store's journal
+    pub(super) fn seal(&self, kind: &[u8], data: &[u8]) -> String {
+        BASE64_STANDARD.encode(self.mac(kind, &[data]).finalize().into_bytes())
+    }
+
+    pub(super) fn check(&self, kind: &[u8], data: &[u8], seal: &str) -> bool {
+        decode(Some(seal)).is_some_and(|tag| self.mac(kind, &[data]).verify_slice(&tag).is_ok())
+    }
+
+    pub fn seal_device(&self, device: &Device) -> String {
+        BASE64_STANDARD.encode(self.device_mac(device).finalize().into_bytes())
+    }
+
+    pub fn seal_nonce(&self, id: &uuid::Uuid, nonce: u128) -> String {
+        BASE64_STANDARD.encode(self.nonce_mac(id, nonce).finalize().into_bytes())
+    }
+
+    pub fn check_device(&self, device: Device, seal: Option<&str>) -> Checked<Device> {
+        let valid =
+            decode(seal).is_some_and(|tag| self.device_mac(&device).verify_slice(&tag).is_ok());
+        if valid {
+            Checked::Valid(device)
+        } else {
+            error!("Device {} failed verification, rejecting it", device.id);
+            Checked::Rejected
+        }
+    }
+
+    pub fn check_nonce(&self, id: &uuid::Uuid, nonce: u128, seal: Option<&str>) -> Checked<u128> {
+        let valid =
+            decode(seal).is_some_and(|tag| self.nonce_mac(id, nonce).verify_slice(&tag).is_ok());
+        if valid {
+            Checked::Valid(nonce)
+        } else {
+            error!("Nonce of {} failed verification, rejecting it", id);
+            Checked::Rejected
+        }
+    }
+}
+
+// Anyone able to replace the identity can forge seals, so it and the
+// directory holding it may only be writable by the daemon user or root, and
+// the identity readable by no one else
+pub fn check_identity_file(path: &Path) -> Result<(), Error> {
+    // SAFETY: geteuid has no preconditions and cannot fail
+    let euid = unsafe { libc::geteuid() };
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    for (path, metadata, mask) in [
+        (path, std::fs::metadata(path)?, 0o027),
+        (dir, std::fs::metadata(dir)?, 0o022),
+    ] {
+        if (metadata.uid() != euid && metadata.uid() != 0) || metadata.mode() & mask != 0 {
+            error!(
+                "{:?} must be owned by the daemon user or root and not open to others, it holds or contains the server identity",
+                path
+            );
+            return Err(Error::new(
+                ErrorKind::Tampered,
+                Some("Server identity is open to other users"),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn decode(seal: Option<&str>) -> Option<Vec<u8>> {
+    BASE64_STANDARD.decode(seal?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRIVATE_KEY_PEM: &str = include_str!("../../../test_data/pem_test");
+    const PUBKEY_PEM: &str = include_str!("../../../test_data/pem_test.pub");
+
+    #[test]
+    fn test_seals() {
+        let identity = PrivateKey::from_pem(PRIVATE_KEY_PEM.as_bytes()).unwrap();
+        let key = RegistryKey::derive(&identity);
+        let other = RegistryKey::from_bytes([7; 32]);
+        let device = Device::new(uuid::Uuid::new_v4(), PUBKEY_PEM.to_string(), 1_700_000_000);
+        let seal = key.seal_device(&device);
+
+        assert!(matches!(
+            key.check_device(device.clone(), Some(&seal)),
+            Checked::Valid(_)
+        ));
+        assert_eq!(key.check_device(device.clone(), None), Checked::Rejected);
+        assert_eq!(
+            other.check_device(device.clone(), Some(&seal)),
+            Checked::Rejected
+        );
+
+        let moved = Device {
+            enrolled: device.enrolled + 1,
+            ..device.clone()
+        };
+        assert_eq!(key.check_device(moved, Some(&seal)), Checked::Rejected);
+
+        let seal = key.seal_nonce(&device.id, 5);
+        assert_eq!(
+            key.check_nonce(&device.id, 5, Some(&seal)),
+            Checked::Valid(5)
+        );
+        assert_eq!(
+            key.check_nonce(&device.id, 6, Some(&seal)),
+            Checked::Rejected
+        );
+        let other_id = uuid::Uuid::new_v4();
+        assert_eq!(
+            key.check_nonce(&other_id, 5, Some(&seal)),
+            Checked::Rejected
+        );
+    }
+
+    #[test]
+    fn test_check_identity_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!(
+            "remote_unlock_test_identity_{}",
+            rand::random::<u32>()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("identity.pem");
+        std::fs::write(&path, PRIVATE_KEY_PEM).unwrap();
+        let chmod = |path: &Path, mode| {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).unwrap()
+        };
+
+        chmod(&dir, 0o755);
+        chmod(&path, 0o600);
+        assert!(check_identity_file(&path).is_ok());
+        chmod(&path, 0o640);
+        assert!(check_identity_file(&path).is_ok());
+        chmod(&path, 0o644);
+        assert!(check_identity_file(&path).is_err());
+        chmod(&path, 0o600);
+        chmod(&dir, 0o777);
+        assert!(check_identity_file(&path).is_err());
+        assert!(check_identity_file(&dir.join("missing.pem")).is_err());
+    }
+}
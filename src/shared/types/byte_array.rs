@@ -1,11 +1,10 @@
+use core::fmt::Display;
 use core::fmt::{self, Debug};
-use der::{Decode, DecodeValue, EncodeValue, FixedTag};
+use der::{DecodeValue, EncodeValue, FixedTag};
 use serde::de::{Deserialize, Deserializer, SeqAccess, Visitor};
 use serde::ser::{Serialize, Serializer};
-use spki::EncodePublicKey;
-use std::fmt::Display;
-use std::io::Read;
-use std::io::Write;
+#[cfg(feature = "std")]
+use std::io::{Read, Write};
 use zeroize::Zeroize;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -48,6 +47,7 @@ impl<const N: usize> ByteArray<N> {
         Ok(core::str::from_utf8(&self.data[..self.length])?)
     }
 
+    #[cfg(feature = "std")]
     pub fn to_stdout_raw(&self) -> Result<(), error::Error> {
         let mut stdout = std::io::stdout().lock();
         stdout.write_all(&self.data[..self.length])?;
@@ -116,6 +116,14 @@ impl<const N: usize> From<([u8; N], usize)> for ByteArray<N> {
     }
 }
 
+// Appends formatted output, failing once the buffer is full
+impl<const N: usize> fmt::Write for ByteArray<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.append_slice(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+#[cfg(feature = "std")]
 impl<const N: usize> Write for ByteArray<N> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         let len = core::cmp::min(buf.len(), N - self.length);
@@ -129,6 +137,7 @@ impl<const N: usize> Write for ByteArray<N> {
     }
 }
 
+#[cfg(feature = "std")]
 impl<const N: usize> Read for ByteArray<N> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         let len = core::cmp::min(buf.len(), self.length);
@@ -251,9 +260,10 @@ impl<const N: usize> Default for ByteArray<N> {
     }
 }
 
-impl<const N: usize> EncodePublicKey for ByteArray<N> {
+#[cfg(feature = "std")]
+impl<const N: usize> spki::EncodePublicKey for ByteArray<N> {
     fn to_public_key_der(&self) -> spki::Result<der::Document> {
-        let doc = der::Document::from_der(self.as_bytes())?;
+        let doc = <der::Document as der::Decode>::from_der(self.as_bytes())?;
         Ok(doc)
     }
 }
@@ -282,16 +292,11 @@ impl TryFrom<u16> for ByteArrayString<5> {
     type Error = error::Error;
     fn try_from(n: u16) -> Result<Self, error::Error> {
         let mut data = [0; 5];
-        let digits = n.checked_ilog10().unwrap_or(0);
-
-        if digits == 0 {
-            return ByteArrayString::try_from("0");
-        }
+        let digits = n.checked_ilog10().unwrap_or(0) + 1;
 
-        for digit in 0..digits {
-            let divisor = 10u16.pow(digits - digit - 1);
-            let digit = (n / divisor) % 10;
-            data[digit as usize] = b'0' + digit as u8;
+        for position in 0..digits {
+            let divisor = 10u16.pow(digits - position - 1);
+            data[position as usize] = b'0' + ((n / divisor) % 10) as u8;
         }
         Ok(ByteArrayString(ByteArray {
             data,
@@ -402,13 +407,14 @@ impl TryFrom<i32> for ByteArrayString<11> {
 
 mod error {
     use crate::types::OwnError;
-    use std::fmt::Display;
+    use core::fmt::Display;
 
     #[derive(Debug)]
     pub enum Error {
         OwnError(OwnError<ErrorKind>),
+        #[cfg(feature = "std")]
         Io(std::io::Error),
-        Utf8(std::str::Utf8Error),
+        Utf8(core::str::Utf8Error),
     }
 
     impl From<ErrorKind> for Error {
@@ -418,9 +424,10 @@ mod error {
     }
 
     impl Display for Error {
-        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
             match self {
                 Error::OwnError(e) => write!(f, "{}", e),
+                #[cfg(feature = "std")]
                 Error::Io(e) => write!(f, "{}", e),
                 Error::Utf8(e) => write!(f, "{}", e),
             }
@@ -436,7 +443,7 @@ mod error {
     impl crate::types::ErrorKindMarker for ErrorKind {}
 
     impl Display for ErrorKind {
-        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
             match self {
                 ErrorKind::Bounds => write!(f, "Buffer bounds error"),
                 ErrorKind::Utf8 => write!(f, "UTF-8 error"),
@@ -444,18 +451,20 @@ mod error {
         }
     }
 
+    #[cfg(feature = "std")]
     impl From<std::io::Error> for Error {
         fn from(e: std::io::Error) -> Self {
             Error::Io(e)
         }
     }
 
-    impl From<std::str::Utf8Error> for Error {
-        fn from(e: std::str::Utf8Error) -> Self {
+    impl From<core::str::Utf8Error> for Error {
+        fn from(e: core::str::Utf8Error) -> Self {
             Error::Utf8(e)
         }
     }
 
+    #[cfg(feature = "std")]
     impl std::error::Error for Error {}
 }
 
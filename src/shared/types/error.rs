@@ -1,22 +1,29 @@
 use crate::types::{ByteArrayError, OwnError};
-use std::fmt::Display;
+use core::fmt::Display;
 
 #[derive(Debug)]
 pub enum Error {
+    #[cfg(feature = "std")]
     SocketError(std::io::Error),
     HTTParseError(httparse::Error),
     KeyParseError(der::Error),
     PKCS8Error(pkcs8::Error),
     SPKIError(spki::Error),
+    #[cfg(feature = "std")]
     SerdeJSONError(serde_json::Error),
+    SerdeJSONCoreSerError(serde_json_core::ser::Error),
+    SerdeJSONCoreDeError(serde_json_core::de::Error),
     P256KeyError(p256::elliptic_curve::Error),
     P256SignatureParseError(p256::ecdsa::Error),
     SignatureDecodeError(base64::DecodeSliceError),
     UuidError(uuid::Error),
     ByteArrayError(ByteArrayError),
+    #[cfg(feature = "discovery")]
     MDNSDaemon(mdns_sd::Error),
+    #[cfg(any(feature = "client", feature = "server"))]
+    ConfigError(crate::config::ConfigError),
     OwnError(OwnError<ErrorKind>),
-    Utf8Error(std::str::Utf8Error),
+    Utf8Error(core::str::Utf8Error),
 }
 
 #[derive(Debug)]
@@ -31,16 +38,44 @@ pub enum ErrorKind {
     ContentLengthMismatch,
     NonceQueueFull,
     SwaylockBackend,
+    InvalidFingerprint,
+    InvalidPairingUri,
+    QrCode,
+    InvalidAdvertisement,
+    ProfileExists,
+    ProfileNotFound,
+    NotEnrolled,
+    UnexpectedStatus,
+    InvalidAction,
+    MalformedRequest,
+    InvalidArgument,
+    UnexpectedMessage,
+    UnsupportedVersion,
+    CorruptNonce,
+    Storage,
+    Tampered,
+    Decryption,
+    InvalidPolicy,
+    StaleRequest,
+    PolicyDenied(crate::denial::Denial),
 }
 
 impl Error {
     pub fn new(kind: ErrorKind, message: Option<&str>) -> Self {
         Self::OwnError(OwnError::new(kind, message))
     }
+
+    // None for errors passed up from other crates
+    pub fn kind(&self) -> Option<&ErrorKind> {
+        match self {
+            Self::OwnError(e) => Some(&e.kind),
+            _ => None,
+        }
+    }
 }
 
 impl Display for ErrorKind {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         match self {
             ErrorKind::PubkeyNotFound => write!(f, "Public key not found"),
             ErrorKind::IncompleteRequest => write!(f, "Incomplete request"),
@@ -52,6 +87,26 @@ impl Display for ErrorKind {
             ErrorKind::ContentLengthMismatch => write!(f, "Content length mismatch"),
             ErrorKind::NonceQueueFull => write!(f, "Nonce queue full"),
             ErrorKind::SwaylockBackend => write!(f, "Swaylock backend error"),
+            ErrorKind::InvalidFingerprint => write!(f, "Invalid fingerprint"),
+            ErrorKind::InvalidPairingUri => write!(f, "Invalid pairing URI"),
+            ErrorKind::QrCode => write!(f, "QR code error"),
+            ErrorKind::InvalidAdvertisement => write!(f, "Invalid service advertisement"),
+            ErrorKind::ProfileExists => write!(f, "Profile exists"),
+            ErrorKind::ProfileNotFound => write!(f, "Profile not found"),
+            ErrorKind::NotEnrolled => write!(f, "Client not enrolled"),
+            ErrorKind::UnexpectedStatus => write!(f, "Unexpected response status"),
+            ErrorKind::InvalidAction => write!(f, "Invalid request action"),
+            ErrorKind::MalformedRequest => write!(f, "Malformed request"),
+            ErrorKind::InvalidArgument => write!(f, "Invalid argument"),
+            ErrorKind::UnexpectedMessage => write!(f, "Unexpected message"),
+            ErrorKind::UnsupportedVersion => write!(f, "Unsupported protocol version"),
+            ErrorKind::CorruptNonce => write!(f, "Corrupt nonce file"),
+            ErrorKind::Storage => write!(f, "Storage error"),
+            ErrorKind::Tampered => write!(f, "Record failed verification"),
+            ErrorKind::Decryption => write!(f, "Decryption failed"),
+            ErrorKind::InvalidPolicy => write!(f, "Invalid policy"),
+            ErrorKind::StaleRequest => write!(f, "Request too old or clock out of sync"),
+            ErrorKind::PolicyDenied(denial) => write!(f, "Denied by policy: {}", denial),
         }
     }
 }
@@ -64,9 +119,10 @@ impl From<ErrorKind> for Error {
 
 impl crate::types::ErrorKindMarker for ErrorKind {}
 
-impl std::fmt::Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+impl Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         match self {
+            #[cfg(feature = "std")]
             Self::SocketError(e) => write!(f, "SocketError: {}", e),
 
             Self::HTTParseError(e) => {
@@ -90,28 +146,40 @@ impl std::fmt::Display for Error {
             Self::SignatureDecodeError(e) => {
                 write!(f, "SignatureDecodeError: {}", e)
             }
+            #[cfg(feature = "std")]
             Self::SerdeJSONError(e) => {
                 write!(f, "SerdeJSONError: {}", e)
             }
+            Self::SerdeJSONCoreSerError(e) => {
+                write!(f, "SerdeJSONCoreSerError: {}", e)
+            }
+            Self::SerdeJSONCoreDeError(e) => {
+                write!(f, "SerdeJSONCoreDeError: {}", e)
+            }
             Self::ByteArrayError(e) => {
                 write!(f, "ByteArrayError: {}", e)
             }
             Self::Utf8Error(e) => {
                 write!(f, "Utf8Error: {}", e)
             }
+            #[cfg(feature = "discovery")]
             Self::MDNSDaemon(e) => {
                 write!(f, "MDNSDaemon: {}", e)
             }
             Self::UuidError(e) => {
                 write!(f, "UuidError: {}", e)
             }
+            #[cfg(any(feature = "client", feature = "server"))]
+            Self::ConfigError(e) => write!(f, "ConfigError: {}", e),
             Self::OwnError(e) => write!(f, "{}", e),
         }
     }
 }
 
+#[cfg(feature = "std")]
 impl std::error::Error for Error {}
 
+#[cfg(feature = "std")]
 impl From<std::io::Error> for Error {
     fn from(err: std::io::Error) -> Self {
         Self::SocketError(err)
@@ -130,12 +198,13 @@ impl From<der::Error> for Error {
     }
 }
 
-impl From<std::str::Utf8Error> for Error {
-    fn from(e: std::str::Utf8Error) -> Self {
+impl From<core::str::Utf8Error> for Error {
+    fn from(e: core::str::Utf8Error) -> Self {
         Self::Utf8Error(e)
     }
 }
 
+#[cfg(feature = "std")]
 impl From<der::pem::Error> for Error {
     fn from(err: der::pem::Error) -> Self {
         Self::KeyParseError(err.into())
@@ -172,6 +241,7 @@ impl From<base64::DecodeSliceError> for Error {
     }
 }
 
+#[cfg(feature = "std")]
 impl From<serde_json::Error> for Error {
     fn from(err: serde_json::Error) -> Self {
         Self::SerdeJSONError(err)
@@ -190,8 +260,28 @@ impl From<uuid::Error> for Error {
     }
 }
 
+#[cfg(feature = "discovery")]
 impl From<mdns_sd::Error> for Error {
     fn from(err: mdns_sd::Error) -> Self {
         Self::MDNSDaemon(err)
     }
 }
+
+#[cfg(any(feature = "client", feature = "server"))]
+impl From<crate::config::ConfigError> for Error {
+    fn from(err: crate::config::ConfigError) -> Self {
+        Self::ConfigError(err)
+    }
+}
+
+impl From<serde_json_core::ser::Error> for Error {
+    fn from(err: serde_json_core::ser::Error) -> Self {
+        Self::SerdeJSONCoreSerError(err)
+    }
+}
+
+impl From<serde_json_core::de::Error> for Error {
+    fn from(err: serde_json_core::de::Error) -> Self {
+        Self::SerdeJSONCoreDeError(err)
+    }
+}
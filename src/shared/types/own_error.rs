@@ -1,6 +1,5 @@
-use core::fmt;
+use core::fmt::{self, Debug, Display};
 use log::error;
-use std::fmt::{Debug, Display};
 
 use crate::config::Config;
 
@@ -40,7 +39,7 @@ impl<K: ErrorKindMarker> From<K> for OwnError<K> {
 }
 
 impl<K: ErrorKindMarker> Display for OwnError<K> {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match &self.message {
             Some(msg) => write!(f, "{}: {}", self.kind, msg),
             None => write!(f, "{}", self.kind),
@@ -1,28 +1,113 @@
 use crate::prelude::*;
 
+use core::fmt::Write;
 use p256::ecdsa::{self, signature::Verifier, VerifyingKey};
 use spki::DecodePublicKey;
 
-// Serial format: {"id":"...","nonce":...}
-const SERIAL_LEN: usize = 1024;
+// Serial format: {"id":"...",("nonce":...|"challenge":"...")[,"ts":...][,"action":"..."]}
+pub const SERIAL_LEN: usize = 1024;
 
+// Signed actions other than unlock, which omits the field for compatibility
+pub const ACTION_LOCK: &str = "lock";
+pub const ACTION_STATUS: &str = "status";
+pub const ACTION_WAKE: &str = "wake";
+
+// Signed body shared by all authenticated requests
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 pub struct UnlockRequestBody<'a> {
     id: &'a str,
-    nonce: u128,
+    // Counter kept by the client, absent when answering a challenge
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    nonce: Option<u128>,
+    // Issued by the server's `/challenge` route for this request only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    challenge: Option<&'a str>,
+    // Unix time the request was signed at, so a captured copy goes stale
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ts: Option<i64>,
+    // Binds the signature to one route so it can't be replayed against another
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    action: Option<&'a str>,
 }
 
 impl<'a> UnlockRequestBody<'a> {
+    pub fn new(id: &'a str, nonce: u128) -> UnlockRequestBody<'a> {
+        UnlockRequestBody {
+            id,
+            nonce: Some(nonce),
+            challenge: None,
+            ts: None,
+            action: None,
+        }
+    }
+
+    pub fn challenged(id: &'a str, challenge: &'a str) -> UnlockRequestBody<'a> {
+        UnlockRequestBody {
+            id,
+            nonce: None,
+            challenge: Some(challenge),
+            ts: None,
+            action: None,
+        }
+    }
+
+    pub fn with_timestamp(mut self, ts: i64) -> UnlockRequestBody<'a> {
+        self.ts = Some(ts);
+        self
+    }
+
+    pub fn with_action(mut self, action: &'a str) -> UnlockRequestBody<'a> {
+        self.action = Some(action);
+        self
+    }
+
+    // Canonical bytes covered by the signature, identical to the serde_json encoding.
+    // Written by hand as no allocation-free JSON serializer supports u128.
+    pub fn signing_bytes(&self) -> Result<ByteArray<SERIAL_LEN>, Error> {
+        // Strings are written verbatim, so anything JSON would escape is rejected
+        let plain = |s: &str| {
+            s.bytes()
+                .all(|b| (0x20..0x7f).contains(&b) && b != b'"' && b != b'\\')
+        };
+        if !plain(self.id)
+            || !self.challenge.map(plain).unwrap_or(true)
+            || !self.action.map(plain).unwrap_or(true)
+        {
+            return Err(Error::new(
+                ErrorKind::MalformedRequest,
+                Some("Unexpected characters in signed body"),
+            ));
+        }
+
+        let oversize = |_| Error::from(ErrorKind::OversizePacket);
+        let mut serial: ByteArray<SERIAL_LEN> = ByteArray::new();
+        write!(serial, "{{\"id\":\"{}\"", self.id).map_err(oversize)?;
+        if let Some(nonce) = self.nonce {
+            write!(serial, ",\"nonce\":{}", nonce).map_err(oversize)?;
+        }
+        if let Some(challenge) = self.challenge {
+            write!(serial, ",\"challenge\":\"{}\"", challenge).map_err(oversize)?;
+        }
+        if let Some(ts) = self.ts {
+            write!(serial, ",\"ts\":{}", ts).map_err(oversize)?;
+        }
+        if let Some(action) = self.action {
+            write!(serial, ",\"action\":\"{}\"", action).map_err(oversize)?;
+        }
+        serial.append_slice(b"}")?;
+
+        Ok(serial)
+    }
+
     pub fn verify(
         &self,
         signature: &[u8],
         pubkey: &crate::crypto::key::PublicKey,
     ) -> Result<bool, Error> {
         trace!("Start request signature verification");
-        let mut serial: ByteArray<SERIAL_LEN> = ByteArray::new();
 
         trace!("Serializing request to sign");
-        serde_json::to_writer(&mut serial, self)?;
+        let serial = self.signing_bytes()?;
 
         debug!(
             "Serialized request for signature verification: {}",
@@ -56,42 +141,84 @@ impl<'a> UnlockRequestBody<'a> {
         self.id.as_bytes()
     }
 
-    pub fn nonce(&self) -> u128 {
+    pub fn nonce(&self) -> Option<u128> {
         self.nonce
     }
+
+    pub fn challenge(&self) -> Option<&str> {
+        self.challenge
+    }
+
+    pub fn timestamp(&self) -> Option<i64> {
+        self.ts
+    }
+
+    pub fn action(&self) -> Option<&str> {
+        self.action
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::crypto::key::PublicKey;
-
     use super::*;
 
+    use crate::client::Signer;
     use p256::ecdsa::SigningKey;
-    use rand::rngs::OsRng;
-    use spki::EncodePublicKey;
 
     #[test]
     fn test_verify() {
-        let signing_key = SigningKey::random(&mut OsRng);
-        let verifying_key = signing_key.verifying_key();
-        let unlock_request = UnlockRequestBody {
-            id: "test",
-            nonce: 0,
-        };
+        let signing_key = SigningKey::from_bytes(&[7; 32].into()).unwrap();
+        let unlock_request = UnlockRequestBody::new("test", 0);
 
-        let mut serial: ByteArray<SERIAL_LEN> = ByteArray::new();
+        let serial = unlock_request.signing_bytes().unwrap();
+        let signature = Signer::sign(&signing_key, serial.as_bytes()).unwrap();
+        let pubkey = Signer::public_key(&signing_key).unwrap();
+
+        let valid = unlock_request
+            .verify(signature.as_bytes(), &pubkey)
+            .unwrap();
+        assert!(valid);
+    }
+
+    #[test]
+    fn test_rejects_unescaped() {
+        assert!(UnlockRequestBody::new("te\"st", 0).signing_bytes().is_err());
+        assert!(UnlockRequestBody::challenged("test", "a\\b")
+            .signing_bytes()
+            .is_err());
+    }
 
-        serde_json::to_writer(&mut serial, &unlock_request).unwrap();
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_matches_serde_json() {
+        let body = UnlockRequestBody::new("00c0ffee", u128::MAX).with_action(ACTION_STATUS);
+        let expected = serde_json::to_vec(&body).unwrap();
 
-        let (signature, _) = signing_key.sign_recoverable(serial.as_bytes()).unwrap();
+        assert_eq!(body.signing_bytes().unwrap().as_bytes(), &expected[..]);
 
-        let pubkey =
-            PublicKey::from_der(verifying_key.to_public_key_der().unwrap().as_bytes()).unwrap();
+        let body = UnlockRequestBody::challenged("00c0ffee", "q83v+w/=").with_action(ACTION_LOCK);
+        let expected = serde_json::to_vec(&body).unwrap();
+        assert_eq!(body.signing_bytes().unwrap().as_bytes(), &expected[..]);
 
-        let valid = unlock_request
-            .verify(signature.to_der().as_bytes(), &pubkey)
+        let body = UnlockRequestBody::new("00c0ffee", 7)
+            .with_timestamp(-1_700_000_000)
+            .with_action(ACTION_LOCK);
+        let expected = serde_json::to_vec(&body).unwrap();
+        assert_eq!(body.signing_bytes().unwrap().as_bytes(), &expected[..]);
+    }
+
+    #[test]
+    fn test_unlock_omits_action() {
+        let unlock = UnlockRequestBody::new("test", 1).signing_bytes().unwrap();
+        assert_eq!(unlock.as_str().unwrap(), r#"{"id":"test","nonce":1}"#);
+
+        let lock = UnlockRequestBody::new("test", 1)
+            .with_action(ACTION_LOCK)
+            .signing_bytes()
             .unwrap();
-        assert!(valid);
+        assert_eq!(
+            lock.as_str().unwrap(),
+            r#"{"id":"test","nonce":1,"action":"lock"}"#
+        );
     }
 }